@@ -0,0 +1,88 @@
+use actix_web::{
+    http::{header::WWW_AUTHENTICATE, StatusCode},
+    HttpResponse, ResponseError,
+};
+use serde_json::json;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Missing access token")]
+    MissingToken,
+
+    #[error("Invalid token: {0}")]
+    InvalidToken(String),
+
+    #[error(transparent)]
+    Jwt(#[from] jsonwebtoken::errors::Error),
+
+    #[error("Token expired")]
+    TokenExpired,
+
+    #[error("Token revoked")]
+    TokenRevoked,
+
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
+    #[error("Invalid signing key: {0}")]
+    InvalidSigningKey(String),
+
+    #[error("JWT creation failed: {0}")]
+    JwtCreationFailed(String),
+
+    #[error("Network error: {0}")]
+    Network(#[from] reqwest::Error),
+
+    /// The key set to verify tokens against could not be fetched or read. Tokens are not at
+    /// fault, so this is a 503 rather than a 401.
+    #[error("Key set unavailable: {0}")]
+    KeySetUnavailable(String),
+
+    /// The app is missing something the extractor or middleware needs.
+    #[error("Not configured: {0}")]
+    NotConfigured(String),
+
+    /// A [`RevocationStore`](crate::RevocationStore) backend failed.
+    #[error("Token store error: {0}")]
+    Storage(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Error::MissingToken
+            | Error::InvalidToken(_)
+            | Error::Jwt(_)
+            | Error::TokenExpired
+            | Error::TokenRevoked => StatusCode::UNAUTHORIZED,
+            Error::Forbidden(_) => StatusCode::FORBIDDEN,
+            Error::Network(_) => StatusCode::BAD_GATEWAY,
+            Error::KeySetUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Error::InvalidSigningKey(_)
+            | Error::JwtCreationFailed(_)
+            | Error::NotConfigured(_)
+            | Error::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status_code = self.status_code();
+        // Details of why a token was rejected are logged, not returned.
+        let message = match self {
+            Error::MissingToken => "Missing access token",
+            Error::TokenExpired => "Token expired",
+            Error::TokenRevoked => "Token revoked",
+            Error::InvalidToken(_) | Error::Jwt(_) => "Invalid token",
+            Error::Forbidden(_) => "Forbidden",
+            _ => "Token verification unavailable",
+        };
+        log::debug!("Rejecting request: {}", self);
+
+        let mut response = HttpResponse::build(status_code);
+        if status_code == StatusCode::UNAUTHORIZED {
+            response.insert_header((WWW_AUTHENTICATE, "Bearer"));
+        }
+        response.json(json!({ "error": message }))
+    }
+}
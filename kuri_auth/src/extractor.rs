@@ -0,0 +1,170 @@
+use std::{ops::Deref, sync::Arc};
+
+use actix_web::{
+    dev::Payload, http::header::AUTHORIZATION, web, FromRequest, HttpMessage, HttpRequest,
+};
+use futures::future::LocalBoxFuture;
+use subtle::ConstantTimeEq;
+
+use crate::{Claims, Error, TokenVerifier};
+
+/// Header that must repeat the CSRF cookie on state-changing requests authenticated by cookie.
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+/// Cookie holding the CSRF token unless configured otherwise.
+pub const DEFAULT_CSRF_COOKIE: &str = "kuri_csrf";
+
+/// Where [`AuthenticatedUser`] looks for the access token besides the `Authorization` header.
+/// Register it with `app_data`; without it only the header is read.
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUserConfig {
+    pub cookie_name: Option<String>,
+    /// Cookie the browser's CSRF token is read from (double-submit, see [`verify_csrf`]).
+    pub csrf_cookie_name: String,
+}
+
+impl Default for AuthenticatedUserConfig {
+    fn default() -> Self {
+        Self {
+            cookie_name: None,
+            csrf_cookie_name: DEFAULT_CSRF_COOKIE.to_string(),
+        }
+    }
+}
+
+/// The verified claims of the request's access token.
+///
+/// Behind [`KuriAuth`](crate::KuriAuth) the claims it verified are reused. Otherwise the token
+/// is read from `Authorization: Bearer` or, when configured, a cookie, and verified with the
+/// `web::Data<Arc<dyn TokenVerifier>>` in the app data.
+#[derive(Debug)]
+pub struct AuthenticatedUser {
+    pub claims: Claims,
+}
+
+impl AuthenticatedUser {
+    pub fn user_id(&self) -> i32 {
+        self.claims.sub
+    }
+}
+
+impl Deref for AuthenticatedUser {
+    type Target = Claims;
+
+    fn deref(&self) -> &Claims {
+        &self.claims
+    }
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        if let Some(claims) = req.extensions().get::<Claims>() {
+            let claims = claims.clone();
+            return Box::pin(async move { Ok(AuthenticatedUser { claims }) });
+        }
+
+        let default_config = AuthenticatedUserConfig::default();
+        let config = req
+            .app_data::<AuthenticatedUserConfig>()
+            .unwrap_or(&default_config);
+        let token = access_token(req, config.cookie_name.as_deref(), &config.csrf_cookie_name);
+        let verifier = req.app_data::<web::Data<Arc<dyn TokenVerifier>>>().cloned();
+
+        Box::pin(async move {
+            let verifier = verifier.ok_or_else(|| {
+                log::error!("AuthenticatedUser used without a TokenVerifier in the app data");
+                Error::NotConfigured("TokenVerifier".to_string())
+            })?;
+            let token = token?.ok_or(Error::MissingToken)?;
+            let claims = verifier.verify(&token).await?;
+            Ok(AuthenticatedUser { claims })
+        })
+    }
+}
+
+/// An [`AuthenticatedUser`] whose token was issued by KuriLogin's own logins. Tokens that
+/// registered clients obtained through `/oauth/token` carry a `client_id` and are refused, so
+/// relying parties cannot manage the user's account with them.
+#[derive(Debug)]
+pub struct FirstPartyUser(pub AuthenticatedUser);
+
+impl FirstPartyUser {
+    pub fn user_id(&self) -> i32 {
+        self.0.user_id()
+    }
+}
+
+impl Deref for FirstPartyUser {
+    type Target = Claims;
+
+    fn deref(&self) -> &Claims {
+        &self.0.claims
+    }
+}
+
+impl FromRequest for FirstPartyUser {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let user = AuthenticatedUser::from_request(req, payload);
+        Box::pin(async move {
+            let user = user.await?;
+            if let Some(client_id) = &user.claims.client_id {
+                return Err(Error::Forbidden(format!(
+                    "token was issued to client {}",
+                    client_id
+                )));
+            }
+            Ok(FirstPartyUser(user))
+        })
+    }
+}
+
+/// The token from `Authorization: Bearer`, falling back to the `cookie_name` cookie. Browsers
+/// send cookies on cross-site requests too, so a cookie only counts for state-changing methods
+/// if the request passes [`verify_csrf`].
+pub(crate) fn access_token(
+    req: &HttpRequest,
+    cookie_name: Option<&str>,
+    csrf_cookie_name: &str,
+) -> Result<Option<String>, Error> {
+    let bearer = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if let Some(token) = bearer {
+        return Ok(Some(token.to_string()));
+    }
+
+    let Some(cookie) = cookie_name.and_then(|name| req.cookie(name)) else {
+        return Ok(None);
+    };
+    if !req.method().is_safe() {
+        verify_csrf(req, csrf_cookie_name)?;
+    }
+    Ok(Some(cookie.value().to_string()))
+}
+
+/// Double-submit check: the [`CSRF_HEADER`] must repeat the CSRF cookie. Other sites can make
+/// the browser send the cookie but cannot read it to set the header.
+pub fn verify_csrf(req: &HttpRequest, csrf_cookie_name: &str) -> Result<(), Error> {
+    let cookie = req.cookie(csrf_cookie_name);
+    let header = req
+        .headers()
+        .get(CSRF_HEADER)
+        .and_then(|value| value.to_str().ok());
+    match (cookie, header) {
+        (Some(cookie), Some(header))
+            if !header.is_empty()
+                && bool::from(cookie.value().as_bytes().ct_eq(header.as_bytes())) =>
+        {
+            Ok(())
+        }
+        _ => Err(Error::Forbidden("CSRF token mismatch".to_string())),
+    }
+}
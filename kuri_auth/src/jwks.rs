@@ -0,0 +1,105 @@
+use std::time::{Duration, Instant};
+
+use jsonwebtoken::{
+    jwk::{Jwk, JwkSet},
+    DecodingKey,
+};
+use tokio::sync::RwLock;
+
+use crate::Error;
+
+/// Keys are refetched after this long even if every `kid` still resolves.
+const MAX_AGE: Duration = Duration::from_secs(3600);
+
+/// A missing `kid` only forces a refetch if the cached set is at least this old.
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+struct CachedKeySet {
+    keys: JwkSet,
+    fetched_at: Instant,
+}
+
+/// Caches a provider's JSON Web Key Set.
+///
+/// `source` is either an `http(s)://` URL or a path to a local JWKS file (optionally prefixed
+/// with `file://`), which lets tests and local setups point the verifier at their own keys.
+pub struct JwksCache {
+    source: String,
+    cached: RwLock<Option<CachedKeySet>>,
+}
+
+impl JwksCache {
+    pub fn new<S: Into<String>>(source: S) -> Self {
+        Self {
+            source: source.into(),
+            cached: RwLock::new(None),
+        }
+    }
+
+    /// Returns the decoding key for `kid`, refreshing the key set when it is stale or the key
+    /// is unknown (providers publish new keys before signing with them).
+    pub async fn decoding_key(&self, kid: &str) -> Result<DecodingKey, Error> {
+        Ok(DecodingKey::from_jwk(&self.jwk(kid).await?)?)
+    }
+
+    /// Returns the published key for `kid`, with the same refresh rules as `decoding_key`.
+    pub async fn jwk(&self, kid: &str) -> Result<Jwk, Error> {
+        {
+            let cached = self.cached.read().await;
+            if let Some(cached) = cached.as_ref() {
+                if cached.fetched_at.elapsed() < MAX_AGE {
+                    if let Some(jwk) = cached.keys.find(kid) {
+                        return Ok(jwk.clone());
+                    }
+                    if cached.fetched_at.elapsed() < MIN_REFRESH_INTERVAL {
+                        return Err(unknown_kid(kid));
+                    }
+                }
+            }
+        }
+
+        self.refresh().await?;
+
+        let cached = self.cached.read().await;
+        cached
+            .as_ref()
+            .and_then(|cached| cached.keys.find(kid))
+            .cloned()
+            .ok_or_else(|| unknown_kid(kid))
+    }
+
+    /// Fetches the key set from its source and replaces the cached one.
+    pub async fn refresh(&self) -> Result<(), Error> {
+        let keys = self.fetch().await?;
+        *self.cached.write().await = Some(CachedKeySet {
+            keys,
+            fetched_at: Instant::now(),
+        });
+        Ok(())
+    }
+
+    async fn fetch(&self) -> Result<JwkSet, Error> {
+        let unavailable = |e: &dyn std::fmt::Display| {
+            log::error!("Failed to load JWKS from {}: {}", self.source, e);
+            Error::KeySetUnavailable(self.source.clone())
+        };
+
+        if self.source.starts_with("http://") || self.source.starts_with("https://") {
+            let response = reqwest::get(&self.source)
+                .await
+                .and_then(reqwest::Response::error_for_status)
+                .map_err(|e| unavailable(&e))?;
+            return response.json::<JwkSet>().await.map_err(|e| unavailable(&e));
+        }
+
+        let path = self.source.trim_start_matches("file://");
+        let contents = tokio::fs::read_to_string(path)
+            .await
+            .map_err(|e| unavailable(&e))?;
+        serde_json::from_str(&contents).map_err(|e| unavailable(&e))
+    }
+}
+
+fn unknown_kid(kid: &str) -> Error {
+    Error::InvalidToken(format!("No signing key found for kid {}", kid))
+}
@@ -0,0 +1,587 @@
+use std::{
+    fmt,
+    sync::{Arc, PoisonError, RwLock, RwLockReadGuard},
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{
+    decode, decode_header, encode,
+    errors::ErrorKind,
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters,
+        EllipticCurveKeyType, Jwk, JwkSet, OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse,
+        RSAKeyParameters, RSAKeyType,
+    },
+    Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{Error, InMemoryRevocationStore, RevocationStore};
+
+/// A key the service signs tokens with, along with what is needed to verify them and, for
+/// asymmetric keys, the public JWK that is published for other services.
+#[derive(Clone)]
+pub struct SigningKey {
+    kid: String,
+    algorithm: Algorithm,
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+    public_jwk: Option<Jwk>,
+}
+
+impl fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SigningKey")
+            .field("kid", &self.kid)
+            .field("algorithm", &self.algorithm)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SigningKey {
+    /// A shared-secret key (HS256/HS384/HS512). It is never published in the JWKS.
+    ///
+    /// The secret must be at least as long as the hash output (32, 48 or 64 bytes, RFC 7518
+    /// section 3.2); shorter secrets can be guessed offline from any token.
+    pub fn hmac<S: Into<String>>(
+        kid: S,
+        algorithm: Algorithm,
+        secret: &[u8],
+    ) -> Result<Self, Error> {
+        let min_length = match algorithm {
+            Algorithm::HS256 => 32,
+            Algorithm::HS384 => 48,
+            Algorithm::HS512 => 64,
+            _ => {
+                return Err(Error::InvalidSigningKey(format!(
+                    "{:?} needs a private key, not a secret",
+                    algorithm
+                )))
+            }
+        };
+        if secret.len() < min_length {
+            return Err(Error::InvalidSigningKey(format!(
+                "{:?} needs a secret of at least {} bytes",
+                algorithm, min_length
+            )));
+        }
+        Ok(SigningKey {
+            kid: kid.into(),
+            algorithm,
+            encoding_key: EncodingKey::from_secret(secret),
+            decoding_key: DecodingKey::from_secret(secret),
+            public_jwk: None,
+        })
+    }
+
+    /// An asymmetric key from a PKCS#8 private key PEM (PKCS#1 is also accepted for RSA).
+    /// Without a `kid`, the key's RFC 7638 thumbprint is used.
+    pub fn from_pem(
+        kid: Option<String>,
+        algorithm: Algorithm,
+        private_key_pem: &[u8],
+    ) -> Result<Self, Error> {
+        let invalid = |e: String| Error::InvalidSigningKey(e);
+        let pem = std::str::from_utf8(private_key_pem).map_err(|e| invalid(e.to_string()))?;
+
+        let (encoding_key, public_params) = match algorithm {
+            Algorithm::RS256
+            | Algorithm::RS384
+            | Algorithm::RS512
+            | Algorithm::PS256
+            | Algorithm::PS384
+            | Algorithm::PS512 => {
+                use rsa::{
+                    pkcs1::DecodeRsaPrivateKey, pkcs8::DecodePrivateKey, traits::PublicKeyParts,
+                    RsaPrivateKey,
+                };
+                let key = RsaPrivateKey::from_pkcs8_pem(pem)
+                    .or_else(|_| RsaPrivateKey::from_pkcs1_pem(pem))
+                    .map_err(|e| invalid(e.to_string()))?;
+                (
+                    EncodingKey::from_rsa_pem(private_key_pem)?,
+                    AlgorithmParameters::RSA(RSAKeyParameters {
+                        key_type: RSAKeyType::RSA,
+                        n: URL_SAFE_NO_PAD.encode(key.n().to_bytes_be()),
+                        e: URL_SAFE_NO_PAD.encode(key.e().to_bytes_be()),
+                    }),
+                )
+            }
+            Algorithm::ES256 => {
+                use p256::{elliptic_curve::sec1::ToEncodedPoint, pkcs8::DecodePrivateKey};
+                let key =
+                    p256::SecretKey::from_pkcs8_pem(pem).map_err(|e| invalid(e.to_string()))?;
+                let point = key.public_key().to_encoded_point(false);
+                let (Some(x), Some(y)) = (point.x(), point.y()) else {
+                    return Err(invalid("EC public key is not uncompressed".into()));
+                };
+                (
+                    EncodingKey::from_ec_pem(private_key_pem)?,
+                    AlgorithmParameters::EllipticCurve(EllipticCurveKeyParameters {
+                        key_type: EllipticCurveKeyType::EC,
+                        curve: EllipticCurve::P256,
+                        x: URL_SAFE_NO_PAD.encode(x),
+                        y: URL_SAFE_NO_PAD.encode(y),
+                    }),
+                )
+            }
+            Algorithm::EdDSA => {
+                use ed25519_dalek::pkcs8::DecodePrivateKey;
+                let key = ed25519_dalek::SigningKey::from_pkcs8_pem(pem)
+                    .map_err(|e| invalid(e.to_string()))?;
+                (
+                    EncodingKey::from_ed_pem(private_key_pem)?,
+                    AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                        key_type: OctetKeyPairType::OctetKeyPair,
+                        curve: EllipticCurve::Ed25519,
+                        x: URL_SAFE_NO_PAD.encode(key.verifying_key().to_bytes()),
+                    }),
+                )
+            }
+            _ => {
+                return Err(invalid(format!(
+                    "{:?} is not a supported asymmetric algorithm",
+                    algorithm
+                )))
+            }
+        };
+
+        let kid = kid.unwrap_or_else(|| thumbprint(&public_params));
+        let public_jwk = Jwk {
+            common: CommonParameters {
+                public_key_use: Some(PublicKeyUse::Signature),
+                key_algorithm: format!("{:?}", algorithm).parse().ok(),
+                key_id: Some(kid.clone()),
+                ..Default::default()
+            },
+            algorithm: public_params,
+        };
+
+        Ok(SigningKey {
+            kid,
+            algorithm,
+            encoding_key,
+            decoding_key: DecodingKey::from_jwk(&public_jwk)?,
+            public_jwk: Some(public_jwk),
+        })
+    }
+
+    /// Creates a fresh key for `algorithm` and returns it with its serialized material: a
+    /// PKCS#8 PEM for asymmetric keys, a base64url secret for HMAC keys.
+    pub fn generate(algorithm: Algorithm) -> Result<(Self, String), Error> {
+        use rand::{rngs::OsRng, RngCore};
+
+        let invalid = |e: String| Error::InvalidSigningKey(e);
+        let material = match algorithm {
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+                let mut secret = [0u8; 64];
+                OsRng.fill_bytes(&mut secret);
+                URL_SAFE_NO_PAD.encode(secret)
+            }
+            Algorithm::RS256
+            | Algorithm::RS384
+            | Algorithm::RS512
+            | Algorithm::PS256
+            | Algorithm::PS384
+            | Algorithm::PS512 => {
+                use rsa::pkcs8::{EncodePrivateKey, LineEnding};
+                rsa::RsaPrivateKey::new(&mut OsRng, 2048)
+                    .map_err(|e| invalid(e.to_string()))?
+                    .to_pkcs8_pem(LineEnding::LF)
+                    .map_err(|e| invalid(e.to_string()))?
+                    .to_string()
+            }
+            Algorithm::ES256 => {
+                use p256::pkcs8::{EncodePrivateKey, LineEnding};
+                p256::SecretKey::random(&mut OsRng)
+                    .to_pkcs8_pem(LineEnding::LF)
+                    .map_err(|e| invalid(e.to_string()))?
+                    .to_string()
+            }
+            Algorithm::EdDSA => {
+                use ed25519_dalek::pkcs8::{spki::der::pem::LineEnding, EncodePrivateKey};
+                ed25519_dalek::SigningKey::generate(&mut OsRng)
+                    .to_pkcs8_pem(LineEnding::LF)
+                    .map_err(|e| invalid(e.to_string()))?
+                    .to_string()
+            }
+            _ => {
+                return Err(invalid(format!(
+                    "{:?} is not a supported algorithm",
+                    algorithm
+                )))
+            }
+        };
+
+        let kid = match algorithm {
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+                let mut kid = [0u8; 16];
+                OsRng.fill_bytes(&mut kid);
+                Some(URL_SAFE_NO_PAD.encode(kid))
+            }
+            _ => None,
+        };
+        let key = Self::from_material(kid, algorithm, &material)?;
+        Ok((key, material))
+    }
+
+    /// Loads a key from the material produced by [`SigningKey::generate`].
+    pub fn from_material(
+        kid: Option<String>,
+        algorithm: Algorithm,
+        material: &str,
+    ) -> Result<Self, Error> {
+        match algorithm {
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+                let kid = kid
+                    .ok_or_else(|| Error::InvalidSigningKey("HMAC keys need a kid".to_string()))?;
+                let secret = URL_SAFE_NO_PAD
+                    .decode(material)
+                    .map_err(|e| Error::InvalidSigningKey(e.to_string()))?;
+                Self::hmac(kid, algorithm, &secret)
+            }
+            _ => Self::from_pem(kid, algorithm, material.as_bytes()),
+        }
+    }
+
+    pub fn kid(&self) -> &str {
+        &self.kid
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    pub fn public_jwk(&self) -> Option<&Jwk> {
+        self.public_jwk.as_ref()
+    }
+}
+
+/// The RFC 7638 JWK thumbprint of a public key.
+fn thumbprint(params: &AlgorithmParameters) -> String {
+    // Members in lexicographic order, as the RFC requires.
+    let canonical = match params {
+        AlgorithmParameters::RSA(rsa) => {
+            format!(r#"{{"e":"{}","kty":"RSA","n":"{}"}}"#, rsa.e, rsa.n)
+        }
+        AlgorithmParameters::EllipticCurve(ec) => format!(
+            r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#,
+            ec.x, ec.y
+        ),
+        AlgorithmParameters::OctetKeyPair(okp) => {
+            format!(r#"{{"crv":"Ed25519","kty":"OKP","x":"{}"}}"#, okp.x)
+        }
+        AlgorithmParameters::OctetKey(_) => String::new(),
+    };
+    URL_SAFE_NO_PAD.encode(Sha256::digest(canonical.as_bytes()))
+}
+
+/// The keys a `JwtManager` works with: one current key that signs new tokens, plus keys that
+/// only verify (older keys until their retirement, and new keys published ahead of promotion).
+#[derive(Debug, Clone)]
+pub struct Keyring {
+    current: SigningKey,
+    verification_keys: Vec<(SigningKey, Option<DateTime<Utc>>)>,
+}
+
+impl Keyring {
+    pub fn new(current: SigningKey) -> Self {
+        Keyring {
+            current,
+            verification_keys: Vec::new(),
+        }
+    }
+
+    /// Adds a key that verifies tokens until `retires_at` (or indefinitely) without signing.
+    pub fn with_verification_key(
+        mut self,
+        key: SigningKey,
+        retires_at: Option<DateTime<Utc>>,
+    ) -> Self {
+        if key.kid != self.current.kid {
+            self.verification_keys.push((key, retires_at));
+        }
+        self
+    }
+
+    pub fn current(&self) -> &SigningKey {
+        &self.current
+    }
+
+    /// The current key and every verification key that has not been retired yet.
+    fn active_keys(&self) -> impl Iterator<Item = &SigningKey> {
+        let now = Utc::now();
+        std::iter::once(&self.current).chain(
+            self.verification_keys
+                .iter()
+                .filter(move |(_, retires_at)| retires_at.map_or(true, |at| at > now))
+                .map(|(key, _)| key),
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct JwtManager {
+    keyring: RwLock<Keyring>,
+    validation: Validation,
+    issuer: Option<String>,
+    audience: Option<String>,
+    ttl: chrono::Duration,
+    revocation_store: Arc<dyn RevocationStore>,
+}
+
+impl JwtManager {
+    // Create a JWT for a given user, tied to the session (refresh token family) it belongs to
+    pub fn create_jwt(&self, user_id: i32, session_id: &str) -> Result<String, Error> {
+        self.sign(&self.claims(user_id, session_id))
+    }
+
+    /// The claims `create_jwt` signs, for callers that add a scope or client before signing.
+    pub fn claims(&self, user_id: i32, session_id: &str) -> Claims {
+        let now = Utc::now();
+        Claims {
+            sub: user_id,
+            exp: (now + self.ttl).timestamp(), // Unix timestamp
+            iat: now.timestamp(),
+            iss: self.issuer.clone(),
+            aud: self.audience.clone(),
+            jti: Some(random_id()),
+            sid: Some(session_id.to_string()),
+            client_id: None,
+            scope: None,
+        }
+    }
+
+    /// Signs arbitrary claims with the current key, with its `kid` in the header.
+    pub fn sign<T: Serialize>(&self, claims: &T) -> Result<String, Error> {
+        let keyring = self.keyring();
+        let signing_key = keyring.current();
+        let mut header = Header::new(signing_key.algorithm);
+        header.kid = Some(signing_key.kid.clone());
+
+        encode(&header, claims, &signing_key.encoding_key)
+            .map_err(|err| Error::JwtCreationFailed(err.to_string()))
+    }
+
+    // Verify a JWT, including that it has not been revoked, and return the associated claims
+    pub async fn verify_jwt(&self, token: &str) -> Result<Claims, Error> {
+        let claims = self.decode_jwt(token)?;
+        if self.revocation_store.is_revoked(&claims).await? {
+            return Err(Error::TokenRevoked);
+        }
+        Ok(claims)
+    }
+
+    /// Revokes a single token until it would have expired anyway.
+    pub async fn revoke(&self, claims: &Claims) -> Result<(), Error> {
+        match &claims.jti {
+            Some(jti) => {
+                self.revocation_store
+                    .revoke_token(jti, claims.expires_at())
+                    .await
+            }
+            // Tokens issued before `jti` existed can only be revoked together with the user's.
+            None => self.revoke_all(claims.sub).await,
+        }
+    }
+
+    /// Revokes every token issued for the session, i.e. its refresh token family.
+    pub async fn revoke_session(&self, session_id: &str) -> Result<(), Error> {
+        self.revocation_store
+            .revoke_session(session_id, Utc::now() + self.ttl + self.leeway())
+            .await
+    }
+
+    /// Revokes every token issued to the user so far. `iat` only counts whole seconds, so the
+    /// cut-off is the start of the current second: tokens issued earlier in that second stay
+    /// valid, rather than revoking a login that follows right after. Revoke the user's
+    /// sessions with [`revoke_session`](Self::revoke_session) too to catch those.
+    pub async fn revoke_all(&self, user_id: i32) -> Result<(), Error> {
+        let now = Utc::now();
+        let issued_before = DateTime::from_timestamp(now.timestamp(), 0).unwrap_or(now);
+        self.revocation_store
+            .revoke_user_tokens(user_id, issued_before, now + self.ttl + self.leeway())
+            .await
+    }
+
+    /// Drops revocations for tokens that have expired since.
+    pub async fn prune_revocations(&self) -> Result<u64, Error> {
+        self.revocation_store.prune_expired(Utc::now()).await
+    }
+
+    fn leeway(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.validation.leeway as i64)
+    }
+
+    fn decode_jwt(&self, token: &str) -> Result<Claims, Error> {
+        let header = decode_header(token)?;
+        let keyring = self.keyring();
+        // Tokens without a `kid` predate key rotation and can only match the current key.
+        let key = match &header.kid {
+            Some(kid) => keyring
+                .active_keys()
+                .find(|key| &key.kid == kid)
+                .ok_or_else(|| Error::InvalidToken(format!("unknown kid {}", kid)))?,
+            None => keyring.current(),
+        };
+
+        let mut validation = self.validation.clone();
+        validation.algorithms = vec![key.algorithm];
+
+        decode::<Claims>(token, &key.decoding_key, &validation)
+            .map(|data| data.claims)
+            .map_err(|err| match err.kind() {
+                ErrorKind::ExpiredSignature => Error::TokenExpired,
+                _ => Error::Jwt(err),
+            })
+    }
+
+    /// The public keys other services use to verify our tokens. HMAC keys are never listed.
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self
+                .keyring()
+                .active_keys()
+                .filter_map(|key| key.public_jwk().cloned())
+                .collect(),
+        }
+    }
+
+    pub fn issuer(&self) -> Option<&str> {
+        self.issuer.as_deref()
+    }
+
+    /// Lifetime of the access tokens this manager issues.
+    pub fn ttl_seconds(&self) -> i64 {
+        self.ttl.num_seconds()
+    }
+
+    pub fn current_key(&self) -> SigningKey {
+        self.keyring().current().clone()
+    }
+
+    /// Swaps in a new set of keys, e.g. after a key was promoted or retired.
+    pub fn replace_keyring(&self, keyring: Keyring) {
+        *self.keyring.write().unwrap_or_else(PoisonError::into_inner) = keyring;
+    }
+
+    fn keyring(&self) -> RwLockReadGuard<'_, Keyring> {
+        self.keyring.read().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+pub struct JwtManagerBuilder {
+    signing_key: SigningKey,
+    issuer: Option<String>,
+    audience: Option<String>,
+    ttl_seconds: i64,
+    leeway_seconds: u64,
+    revocation_store: Option<Arc<dyn RevocationStore>>,
+}
+impl JwtManagerBuilder {
+    pub fn new(signing_key: SigningKey) -> Self {
+        Self {
+            signing_key,
+            issuer: None,
+            audience: None,
+            ttl_seconds: 3600,
+            leeway_seconds: 60,
+            revocation_store: None,
+        }
+    }
+    pub fn issuer<S: Into<String>>(mut self, issuer: S) -> Self {
+        self.issuer = Some(issuer.into());
+        self
+    }
+    pub fn audience<S: Into<String>>(mut self, audience: S) -> Self {
+        self.audience = Some(audience.into());
+        self
+    }
+    pub fn ttl_seconds(mut self, ttl_seconds: i64) -> Self {
+        self.ttl_seconds = ttl_seconds;
+        self
+    }
+    pub fn leeway_seconds(mut self, leeway_seconds: u64) -> Self {
+        self.leeway_seconds = leeway_seconds;
+        self
+    }
+    /// Where revoked tokens are recorded. Defaults to an in-memory store, which is only
+    /// suitable for a single instance.
+    pub fn revocation_store(mut self, revocation_store: Arc<dyn RevocationStore>) -> Self {
+        self.revocation_store = Some(revocation_store);
+        self
+    }
+    pub fn build(self) -> JwtManager {
+        let mut validation = Validation::new(self.signing_key.algorithm);
+        validation.leeway = self.leeway_seconds;
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        // Tokens without an `aud` only verify when no audience is configured.
+        match &self.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+
+        JwtManager {
+            keyring: RwLock::new(Keyring::new(self.signing_key)),
+            validation,
+            issuer: self.issuer,
+            audience: self.audience,
+            ttl: chrono::Duration::seconds(self.ttl_seconds),
+            revocation_store: self
+                .revocation_store
+                .unwrap_or_else(|| Arc::new(InMemoryRevocationStore::default())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: i32,
+    pub exp: i64,
+    #[serde(default)]
+    pub iat: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+    /// The session (refresh token family) the token was issued for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+    /// The OAuth client the token was issued to, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    /// Space-separated scopes, as in OAuth 2.0.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+}
+
+impl Claims {
+    pub fn expires_at(&self) -> DateTime<Utc> {
+        DateTime::from_timestamp(self.exp, 0).unwrap_or_else(Utc::now)
+    }
+
+    pub fn issued_at(&self) -> DateTime<Utc> {
+        DateTime::from_timestamp(self.iat, 0).unwrap_or(DateTime::UNIX_EPOCH)
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scope
+            .as_deref()
+            .is_some_and(|scopes| scopes.split_whitespace().any(|s| s == scope))
+    }
+}
+
+/// A random 128-bit identifier, base64url encoded.
+fn random_id() -> String {
+    use rand::{rngs::OsRng, RngCore};
+
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
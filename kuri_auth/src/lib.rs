@@ -0,0 +1,35 @@
+//! Issuing and verifying KuriLogin access tokens.
+//!
+//! Services that accept KuriLogin tokens wrap their routes with [`KuriAuth`] and read the
+//! caller from [`AuthenticatedUser`]:
+//!
+//! ```ignore
+//! App::new()
+//!     .wrap(KuriAuth::new("https://login.example.com/.well-known/jwks.json"))
+//!     .route("/orders", web::get().to(|user: AuthenticatedUser| async move {
+//!         format!("orders of user {}", user.user_id())
+//!     }))
+//! ```
+//!
+//! The KuriLogin service itself issues tokens through [`JwtManager`].
+
+mod error;
+pub use error::*;
+
+mod jwt;
+pub use jwt::*;
+
+mod jwks;
+pub use jwks::*;
+
+mod revocation;
+pub use revocation::*;
+
+mod verifier;
+pub use verifier::*;
+
+mod extractor;
+pub use extractor::*;
+
+mod middleware;
+pub use middleware::*;
@@ -0,0 +1,174 @@
+use std::{
+    future::{ready, Ready},
+    rc::Rc,
+    sync::Arc,
+};
+
+use actix_web::{
+    body::EitherBody,
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    HttpMessage, ResponseError,
+};
+use futures::future::LocalBoxFuture;
+use jsonwebtoken::Algorithm;
+
+use crate::{
+    extractor::access_token, Claims, Error, JwksVerifier, SecretVerifier, TokenVerifier,
+    DEFAULT_CSRF_COOKIE,
+};
+
+/// Middleware that verifies the access token of every request it wraps and makes the claims
+/// available to [`AuthenticatedUser`](crate::AuthenticatedUser) and `req.extensions()`.
+///
+/// ```ignore
+/// App::new().service(
+///     web::scope("/orders")
+///         .wrap(KuriAuth::new("https://login.example.com/.well-known/jwks.json").require_scope("orders:read"))
+///         .route("", web::get().to(list_orders)),
+/// )
+/// ```
+#[derive(Clone)]
+pub struct KuriAuth {
+    verifier: Arc<dyn TokenVerifier>,
+    cookie_name: Option<String>,
+    csrf_cookie_name: String,
+    required_scopes: Vec<String>,
+    optional: bool,
+}
+
+impl KuriAuth {
+    /// Verifies tokens against the JWKS KuriLogin publishes.
+    pub fn new<S: Into<String>>(jwks_url: S) -> Self {
+        Self::with_verifier(Arc::new(JwksVerifier::new(jwks_url)))
+    }
+
+    /// Verifies HS256 tokens signed with the secret shared with KuriLogin.
+    pub fn with_secret(secret: &[u8]) -> Self {
+        Self::with_verifier(Arc::new(SecretVerifier::new(secret, Algorithm::HS256)))
+    }
+
+    /// Verifies tokens with a configured verifier, e.g. a `JwksVerifier` that checks the issuer.
+    pub fn with_verifier(verifier: Arc<dyn TokenVerifier>) -> Self {
+        Self {
+            verifier,
+            cookie_name: None,
+            csrf_cookie_name: DEFAULT_CSRF_COOKIE.to_string(),
+            required_scopes: Vec::new(),
+            optional: false,
+        }
+    }
+
+    /// Also reads the token from this cookie when there is no `Authorization` header.
+    /// State-changing requests authenticated this way must pass the CSRF check.
+    pub fn cookie<S: Into<String>>(mut self, cookie_name: S) -> Self {
+        self.cookie_name = Some(cookie_name.into());
+        self
+    }
+
+    /// The cookie holding the CSRF token, `kuri_csrf` by default.
+    pub fn csrf_cookie<S: Into<String>>(mut self, csrf_cookie_name: S) -> Self {
+        self.csrf_cookie_name = csrf_cookie_name.into();
+        self
+    }
+
+    /// Rejects tokens without this scope with `403 Forbidden`.
+    pub fn require_scope<S: Into<String>>(mut self, scope: S) -> Self {
+        self.required_scopes.push(scope.into());
+        self
+    }
+
+    /// Lets requests without a token through; tokens that are present must still verify.
+    pub fn optional(mut self) -> Self {
+        self.optional = true;
+        self
+    }
+
+    fn authorize(&self, claims: &Claims) -> Result<(), Error> {
+        if let Some(scope) = self
+            .required_scopes
+            .iter()
+            .find(|scope| !claims.has_scope(scope))
+        {
+            return Err(Error::Forbidden(format!("missing scope {}", scope)));
+        }
+        Ok(())
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for KuriAuth
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = KuriAuthMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(KuriAuthMiddleware {
+            service: Rc::new(service),
+            config: Rc::new(self.clone()),
+        }))
+    }
+}
+
+pub struct KuriAuthMiddleware<S> {
+    service: Rc<S>,
+    config: Rc<KuriAuth>,
+}
+
+impl<S, B> Service<ServiceRequest> for KuriAuthMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let config = self.config.clone();
+        let token = access_token(
+            req.request(),
+            config.cookie_name.as_deref(),
+            &config.csrf_cookie_name,
+        );
+
+        Box::pin(async move {
+            let token = match token {
+                Ok(token) => token,
+                Err(e) => return Ok(reject(req, e)),
+            };
+            let claims = match token {
+                Some(token) => match config.verifier.verify(&token).await {
+                    Ok(claims) => Some(claims),
+                    Err(e) => return Ok(reject(req, e)),
+                },
+                None if config.optional => None,
+                None => return Ok(reject(req, Error::MissingToken)),
+            };
+
+            if let Some(claims) = claims {
+                if let Err(e) = config.authorize(&claims) {
+                    return Ok(reject(req, e));
+                }
+                req.extensions_mut().insert(claims);
+            }
+
+            service
+                .call(req)
+                .await
+                .map(ServiceResponse::map_into_left_body)
+        })
+    }
+}
+
+fn reject<B>(req: ServiceRequest, error: Error) -> ServiceResponse<EitherBody<B>> {
+    req.into_response(error.error_response())
+        .map_into_right_body()
+}
@@ -0,0 +1,122 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::{Claims, Error};
+
+/// Records revoked access tokens until they would have expired anyway.
+#[async_trait]
+pub trait RevocationStore: Send + Sync + std::fmt::Debug {
+    async fn revoke_token(&self, jti: &str, expires_at: DateTime<Utc>) -> Result<(), Error>;
+
+    /// Revokes every token carrying `session_id` as its `sid`.
+    async fn revoke_session(
+        &self,
+        session_id: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), Error>;
+
+    /// Revokes every token issued to `user_id` before `issued_before`, comparing whole seconds
+    /// like `iat`. The entry is kept until `expires_at`, when all such tokens have expired.
+    async fn revoke_user_tokens(
+        &self,
+        user_id: i32,
+        issued_before: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), Error>;
+
+    async fn is_revoked(&self, claims: &Claims) -> Result<bool, Error>;
+
+    /// Deletes entries that expired before `now`, returning how many were removed.
+    async fn prune_expired(&self, now: DateTime<Utc>) -> Result<u64, Error>;
+}
+
+/// Keeps revocations in process memory. They are lost on restart and not shared between
+/// instances, so this only suits single-instance deployments and development.
+#[derive(Debug, Default)]
+pub struct InMemoryRevocationStore {
+    state: Mutex<Revocations>,
+}
+
+#[derive(Debug, Default)]
+struct Revocations {
+    /// Revoked `jti`s and when the token expires.
+    tokens: HashMap<String, DateTime<Utc>>,
+    /// Revoked session ids and when their last token expires.
+    sessions: HashMap<String, DateTime<Utc>>,
+    /// Per user: tokens issued before the first timestamp are revoked until the second.
+    users: HashMap<i32, (DateTime<Utc>, DateTime<Utc>)>,
+}
+
+impl Revocations {
+    fn len(&self) -> usize {
+        self.tokens.len() + self.sessions.len() + self.users.len()
+    }
+}
+
+impl InMemoryRevocationStore {
+    fn state(&self) -> MutexGuard<'_, Revocations> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[async_trait]
+impl RevocationStore for InMemoryRevocationStore {
+    async fn revoke_token(&self, jti: &str, expires_at: DateTime<Utc>) -> Result<(), Error> {
+        self.state().tokens.insert(jti.to_string(), expires_at);
+        Ok(())
+    }
+
+    async fn revoke_session(
+        &self,
+        session_id: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        self.state()
+            .sessions
+            .insert(session_id.to_string(), expires_at);
+        Ok(())
+    }
+
+    async fn revoke_user_tokens(
+        &self,
+        user_id: i32,
+        issued_before: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        self.state()
+            .users
+            .insert(user_id, (issued_before, expires_at));
+        Ok(())
+    }
+
+    async fn is_revoked(&self, claims: &Claims) -> Result<bool, Error> {
+        let state = self.state();
+        let token_revoked = claims
+            .jti
+            .as_ref()
+            .is_some_and(|jti| state.tokens.contains_key(jti));
+        let session_revoked = claims
+            .sid
+            .as_ref()
+            .is_some_and(|sid| state.sessions.contains_key(sid));
+        let user_revoked = state
+            .users
+            .get(&claims.sub)
+            .is_some_and(|(issued_before, _)| claims.issued_at() < *issued_before);
+        Ok(token_revoked || session_revoked || user_revoked)
+    }
+
+    async fn prune_expired(&self, now: DateTime<Utc>) -> Result<u64, Error> {
+        let mut state = self.state();
+        let before = state.len();
+        state.tokens.retain(|_, expires_at| *expires_at > now);
+        state.sessions.retain(|_, expires_at| *expires_at > now);
+        state.users.retain(|_, (_, expires_at)| *expires_at > now);
+        Ok((before - state.len()) as u64)
+    }
+}
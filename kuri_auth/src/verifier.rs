@@ -0,0 +1,157 @@
+use async_trait::async_trait;
+use jsonwebtoken::{
+    decode, decode_header,
+    errors::ErrorKind,
+    jwk::{AlgorithmParameters, EllipticCurve, Jwk},
+    Algorithm, DecodingKey, Validation,
+};
+
+use crate::{Claims, Error, JwksCache, JwtManager};
+
+/// Turns an access token into its verified claims.
+#[async_trait]
+pub trait TokenVerifier: Send + Sync {
+    async fn verify(&self, token: &str) -> Result<Claims, Error>;
+}
+
+#[async_trait]
+impl TokenVerifier for JwtManager {
+    async fn verify(&self, token: &str) -> Result<Claims, Error> {
+        self.verify_jwt(token).await
+    }
+}
+
+/// Verifies tokens against the keys KuriLogin publishes at `/.well-known/jwks.json`.
+///
+/// The check is stateless: only the signature, expiry and the configured issuer and audience
+/// are verified. Tokens revoked by a logout stay valid here until they expire, so keep the
+/// access token TTL short.
+pub struct JwksVerifier {
+    jwks: JwksCache,
+    validation: Validation,
+}
+
+impl JwksVerifier {
+    /// `jwks_url` may also be a path to a local JWKS file.
+    pub fn new<S: Into<String>>(jwks_url: S) -> Self {
+        Self {
+            jwks: JwksCache::new(jwks_url),
+            validation: default_validation(Algorithm::RS256),
+        }
+    }
+    pub fn issuer(mut self, issuer: &str) -> Self {
+        self.validation.set_issuer(&[issuer]);
+        self
+    }
+    pub fn audience(mut self, audience: &str) -> Self {
+        self.validation.set_audience(&[audience]);
+        self
+    }
+    pub fn leeway_seconds(mut self, leeway_seconds: u64) -> Self {
+        self.validation.leeway = leeway_seconds;
+        self
+    }
+}
+
+#[async_trait]
+impl TokenVerifier for JwksVerifier {
+    async fn verify(&self, token: &str) -> Result<Claims, Error> {
+        let header = decode_header(token)?;
+        let kid = header
+            .kid
+            .ok_or_else(|| Error::InvalidToken("token has no kid".to_string()))?;
+        let jwk = self.jwks.jwk(&kid).await?;
+
+        let mut validation = self.validation.clone();
+        validation.algorithms = algorithms(&jwk);
+        if validation.algorithms.is_empty() {
+            return Err(Error::InvalidToken(format!(
+                "key {} cannot verify tokens",
+                kid
+            )));
+        }
+        decode_claims(token, &DecodingKey::from_jwk(&jwk)?, &validation)
+    }
+}
+
+/// Verifies tokens signed with a secret shared with KuriLogin (`JWT_SECRET`).
+///
+/// Like [`JwksVerifier`], it does not see revocations and only checks the signature, expiry
+/// and the configured issuer and audience.
+pub struct SecretVerifier {
+    key: DecodingKey,
+    validation: Validation,
+}
+
+impl SecretVerifier {
+    pub fn new(secret: &[u8], algorithm: Algorithm) -> Self {
+        Self {
+            key: DecodingKey::from_secret(secret),
+            validation: default_validation(algorithm),
+        }
+    }
+    pub fn issuer(mut self, issuer: &str) -> Self {
+        self.validation.set_issuer(&[issuer]);
+        self
+    }
+    pub fn audience(mut self, audience: &str) -> Self {
+        self.validation.set_audience(&[audience]);
+        self
+    }
+    pub fn leeway_seconds(mut self, leeway_seconds: u64) -> Self {
+        self.validation.leeway = leeway_seconds;
+        self
+    }
+}
+
+#[async_trait]
+impl TokenVerifier for SecretVerifier {
+    async fn verify(&self, token: &str) -> Result<Claims, Error> {
+        decode_claims(token, &self.key, &self.validation)
+    }
+}
+
+/// Like `JwtManagerBuilder`, tokens without an `aud` only verify when no audience is set.
+fn default_validation(algorithm: Algorithm) -> Validation {
+    let mut validation = Validation::new(algorithm);
+    validation.validate_aud = false;
+    validation
+}
+
+fn decode_claims(token: &str, key: &DecodingKey, validation: &Validation) -> Result<Claims, Error> {
+    decode::<Claims>(token, key, validation)
+        .map(|data| data.claims)
+        .map_err(|err| match err.kind() {
+            ErrorKind::ExpiredSignature => Error::TokenExpired,
+            _ => Error::Jwt(err),
+        })
+}
+
+/// The algorithms a published key may be used with: its `alg` if set, otherwise every
+/// algorithm of its key type.
+fn algorithms(jwk: &Jwk) -> Vec<Algorithm> {
+    if let Some(algorithm) = jwk
+        .common
+        .key_algorithm
+        .and_then(|alg| format!("{:?}", alg).parse().ok())
+    {
+        return vec![algorithm];
+    }
+    match &jwk.algorithm {
+        AlgorithmParameters::RSA(_) => vec![
+            Algorithm::RS256,
+            Algorithm::RS384,
+            Algorithm::RS512,
+            Algorithm::PS256,
+            Algorithm::PS384,
+            Algorithm::PS512,
+        ],
+        AlgorithmParameters::EllipticCurve(ec) => match ec.curve {
+            EllipticCurve::P384 => vec![Algorithm::ES384],
+            _ => vec![Algorithm::ES256],
+        },
+        AlgorithmParameters::OctetKeyPair(_) => vec![Algorithm::EdDSA],
+        // Secrets are never published; refuse to treat one as a verification key.
+        AlgorithmParameters::OctetKey(_) => Vec::new(),
+    }
+}
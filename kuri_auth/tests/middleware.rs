@@ -0,0 +1,233 @@
+use std::sync::Arc;
+
+use actix_web::{
+    cookie::Cookie,
+    http::{Method, StatusCode},
+    test, web, App, HttpResponse,
+};
+use chrono::Utc;
+use jsonwebtoken::Algorithm;
+use kuri_auth::{
+    verify_csrf, AuthenticatedUser, Claims, FirstPartyUser, JwksVerifier, JwtManager,
+    JwtManagerBuilder, KuriAuth, SigningKey, TokenVerifier, CSRF_HEADER, DEFAULT_CSRF_COOKIE,
+};
+
+fn manager(algorithm: Algorithm) -> JwtManager {
+    let (key, _) = SigningKey::generate(algorithm).unwrap();
+    JwtManagerBuilder::new(key)
+        .issuer("https://login.test")
+        .build()
+}
+
+fn claims(scope: Option<&str>) -> Claims {
+    let now = Utc::now().timestamp();
+    Claims {
+        sub: 7,
+        exp: now + 60,
+        iat: now,
+        iss: Some("https://login.test".to_string()),
+        aud: None,
+        jti: None,
+        sid: None,
+        client_id: None,
+        scope: scope.map(String::from),
+    }
+}
+
+/// Publishes the manager's keys as a JWKS file the middleware can read.
+fn jwks_file(manager: &JwtManager, name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("kuri_auth_{}_{}.json", name, std::process::id()));
+    std::fs::write(&path, serde_json::to_string(&manager.jwks()).unwrap()).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+async fn whoami(user: AuthenticatedUser) -> HttpResponse {
+    HttpResponse::Ok().body(user.user_id().to_string())
+}
+
+async fn status(auth: KuriAuth, token: Option<&str>) -> StatusCode {
+    let app = test::init_service(
+        App::new().service(
+            web::scope("/api")
+                .wrap(auth)
+                .route("/whoami", web::get().to(whoami)),
+        ),
+    )
+    .await;
+    let mut req = test::TestRequest::get().uri("/api/whoami");
+    if let Some(token) = token {
+        req = req.insert_header(("Authorization", format!("Bearer {}", token)));
+    }
+    test::call_service(&app, req.to_request()).await.status()
+}
+
+#[actix_web::test]
+async fn accepts_tokens_signed_by_a_published_key() {
+    for algorithm in [Algorithm::RS256, Algorithm::ES256, Algorithm::EdDSA] {
+        let manager = manager(algorithm);
+        let jwks = jwks_file(&manager, &format!("{:?}", algorithm));
+        let token = manager.create_jwt(7, "session").unwrap();
+
+        let auth = KuriAuth::with_verifier(Arc::new(
+            JwksVerifier::new(jwks).issuer("https://login.test"),
+        ));
+        assert_eq!(status(auth, Some(&token)).await, StatusCode::OK);
+    }
+}
+
+#[actix_web::test]
+async fn rejects_missing_and_foreign_tokens() {
+    let manager = manager(Algorithm::ES256);
+    let jwks = jwks_file(&manager, "foreign");
+    let foreign = self::manager(Algorithm::ES256)
+        .create_jwt(7, "session")
+        .unwrap();
+
+    assert_eq!(
+        status(KuriAuth::new(jwks.clone()), None).await,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(
+        status(KuriAuth::new(jwks), Some(&foreign)).await,
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[actix_web::test]
+async fn an_unreadable_key_set_is_unavailable_not_unauthorized() {
+    let token = manager(Algorithm::ES256).create_jwt(7, "session").unwrap();
+    let missing =
+        std::env::temp_dir().join(format!("kuri_auth_missing_{}.json", std::process::id()));
+    let auth = KuriAuth::new(missing.to_string_lossy().into_owned());
+    assert_eq!(
+        status(auth, Some(&token)).await,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+
+    let malformed =
+        std::env::temp_dir().join(format!("kuri_auth_malformed_{}.json", std::process::id()));
+    std::fs::write(&malformed, "not a key set").unwrap();
+    let auth = KuriAuth::new(malformed.to_string_lossy().into_owned());
+    assert_eq!(
+        status(auth, Some(&token)).await,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+}
+
+#[actix_web::test]
+async fn enforces_scopes() {
+    let manager = manager(Algorithm::ES256);
+    let jwks = jwks_file(&manager, "guards");
+    let reader = manager.sign(&claims(Some("profile orders:read"))).unwrap();
+    let user = manager.sign(&claims(Some("profile"))).unwrap();
+
+    let guarded = || KuriAuth::new(jwks.clone()).require_scope("orders:read");
+    assert_eq!(status(guarded(), Some(&reader)).await, StatusCode::OK);
+    assert_eq!(status(guarded(), Some(&user)).await, StatusCode::FORBIDDEN);
+}
+
+#[actix_web::test]
+async fn verifies_shared_secret_tokens() {
+    let secret = b"a shared secret of at least 32 bytes";
+    assert!(SigningKey::hmac("default", Algorithm::HS256, b"").is_err());
+    assert!(SigningKey::hmac("default", Algorithm::HS256, b"shared secret").is_err());
+    let key = SigningKey::hmac("default", Algorithm::HS256, secret).unwrap();
+    let token = JwtManagerBuilder::new(key)
+        .build()
+        .create_jwt(7, "session")
+        .unwrap();
+
+    assert_eq!(
+        status(KuriAuth::with_secret(secret), Some(&token)).await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        status(KuriAuth::with_secret(b"other secret"), Some(&token)).await,
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[actix_web::test]
+async fn cookie_tokens_need_the_csrf_header_to_change_state() {
+    let manager = manager(Algorithm::ES256);
+    let jwks = jwks_file(&manager, "csrf");
+    let token = manager.create_jwt(7, "session").unwrap();
+    let app = test::init_service(
+        App::new().service(
+            web::scope("/api")
+                .wrap(KuriAuth::new(jwks).cookie("session"))
+                .route("/whoami", web::to(whoami)),
+        ),
+    )
+    .await;
+
+    let request = |method: Method, csrf_header: Option<&str>| {
+        let mut req = test::TestRequest::default()
+            .method(method)
+            .uri("/api/whoami")
+            .cookie(Cookie::new("session", token.clone()))
+            .cookie(Cookie::new("kuri_csrf", "csrf-token"));
+        if let Some(csrf_header) = csrf_header {
+            req = req.insert_header(("X-CSRF-Token", csrf_header));
+        }
+        req.to_request()
+    };
+
+    let get = test::call_service(&app, request(Method::GET, None)).await;
+    assert_eq!(get.status(), StatusCode::OK);
+    let missing = test::call_service(&app, request(Method::POST, None)).await;
+    assert_eq!(missing.status(), StatusCode::FORBIDDEN);
+    let forged = test::call_service(&app, request(Method::POST, Some("guess"))).await;
+    assert_eq!(forged.status(), StatusCode::FORBIDDEN);
+    let post = test::call_service(&app, request(Method::POST, Some("csrf-token"))).await;
+    assert_eq!(post.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn csrf_header_must_repeat_the_cookie() {
+    let check = |cookie: Option<&str>, header: Option<&str>| {
+        let mut req = test::TestRequest::post();
+        if let Some(cookie) = cookie {
+            req = req.cookie(Cookie::new(DEFAULT_CSRF_COOKIE, cookie));
+        }
+        if let Some(header) = header {
+            req = req.insert_header((CSRF_HEADER, header));
+        }
+        verify_csrf(&req.to_http_request(), DEFAULT_CSRF_COOKIE).is_ok()
+    };
+
+    assert!(check(Some("csrf-token"), Some("csrf-token")));
+    assert!(!check(Some("csrf-token"), None));
+    assert!(!check(None, Some("csrf-token")));
+    assert!(!check(Some("csrf-token"), Some("guess")));
+    assert!(!check(Some(""), Some("")));
+}
+
+#[actix_web::test]
+async fn first_party_routes_refuse_client_tokens() {
+    let manager = Arc::new(manager(Algorithm::ES256));
+    let verifier: Arc<dyn TokenVerifier> = manager.clone();
+    let app = test::init_service(App::new().app_data(web::Data::new(verifier)).route(
+        "/me",
+        web::get().to(|user: FirstPartyUser| async move {
+            HttpResponse::Ok().body(user.user_id().to_string())
+        }),
+    ))
+    .await;
+    let request = |token: String| {
+        test::TestRequest::get()
+            .uri("/me")
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_request()
+    };
+
+    let own = manager.create_jwt(7, "session").unwrap();
+    let response = test::call_service(&app, request(own)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let mut client_claims = claims(Some("openid profile"));
+    client_claims.client_id = Some("relying-party".to_string());
+    let client = manager.sign(&client_claims).unwrap();
+    let response = test::call_service(&app, request(client)).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
@@ -0,0 +1,84 @@
+use chrono::{DateTime, Utc};
+use jsonwebtoken::Algorithm;
+use kuri_auth::{
+    Claims, InMemoryRevocationStore, JwtManager, JwtManagerBuilder, RevocationStore, SigningKey,
+};
+
+fn manager() -> JwtManager {
+    let (key, _) = SigningKey::generate(Algorithm::ES256).unwrap();
+    JwtManagerBuilder::new(key).build()
+}
+
+fn claims(iat: i64) -> Claims {
+    Claims {
+        sub: 7,
+        exp: iat + 60,
+        iat,
+        iss: None,
+        aud: None,
+        jti: None,
+        sid: None,
+        client_id: None,
+        scope: None,
+    }
+}
+
+#[tokio::test]
+async fn user_revocations_spare_tokens_from_the_cut_off_second() {
+    let store = InMemoryRevocationStore::default();
+    let issued_before = DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+    store
+        .revoke_user_tokens(7, issued_before, Utc::now())
+        .await
+        .unwrap();
+
+    assert!(store.is_revoked(&claims(1_699_999_999)).await.unwrap());
+    assert!(!store.is_revoked(&claims(1_700_000_000)).await.unwrap());
+}
+
+#[tokio::test]
+async fn tokens_issued_right_after_revoke_all_stay_valid() {
+    let manager = manager();
+    manager.revoke_all(7).await.unwrap();
+
+    let fresh = manager.claims(7, "new-session");
+    assert!(manager
+        .verify_jwt(&manager.sign(&fresh).unwrap())
+        .await
+        .is_ok());
+
+    let mut old = manager.claims(7, "old-session");
+    // Two seconds back is before the cut-off even if the second changed since `revoke_all`.
+    old.iat -= 2;
+    assert!(manager
+        .verify_jwt(&manager.sign(&old).unwrap())
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn revoking_the_sessions_too_catches_tokens_from_the_same_second() {
+    let manager = manager();
+    let token = manager.sign(&manager.claims(7, "session")).unwrap();
+
+    manager.revoke_all(7).await.unwrap();
+    manager.revoke_session("session").await.unwrap();
+
+    assert!(manager.verify_jwt(&token).await.is_err());
+    let fresh = manager.sign(&manager.claims(7, "new-session")).unwrap();
+    assert!(manager.verify_jwt(&fresh).await.is_ok());
+}
+
+#[tokio::test]
+async fn revoking_a_session_revokes_all_its_tokens() {
+    let manager = manager();
+    let first = manager.sign(&manager.claims(7, "session")).unwrap();
+    let second = manager.sign(&manager.claims(7, "session")).unwrap();
+    let other = manager.sign(&manager.claims(7, "other-session")).unwrap();
+
+    manager.revoke_session("session").await.unwrap();
+
+    assert!(manager.verify_jwt(&first).await.is_err());
+    assert!(manager.verify_jwt(&second).await.is_err());
+    assert!(manager.verify_jwt(&other).await.is_ok());
+}
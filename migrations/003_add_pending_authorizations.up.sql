@@ -0,0 +1,13 @@
+-- Creating the Pending_Authorizations table
+CREATE TABLE Pending_Authorizations (
+    state VARCHAR(255) PRIMARY KEY,
+    provider_id INTEGER NOT NULL,
+    return_to TEXT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    CONSTRAINT fk_provider
+        FOREIGN KEY(provider_id)
+        REFERENCES OAuth_Providers(provider_id)
+        ON DELETE CASCADE
+);
+
+CREATE INDEX idx_pending_created_at ON Pending_Authorizations (created_at);
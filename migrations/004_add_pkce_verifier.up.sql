@@ -0,0 +1 @@
+ALTER TABLE Pending_Authorizations ADD COLUMN pkce_verifier TEXT NULL;
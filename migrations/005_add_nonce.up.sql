@@ -0,0 +1 @@
+ALTER TABLE Pending_Authorizations ADD COLUMN nonce TEXT NULL;
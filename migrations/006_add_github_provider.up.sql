@@ -0,0 +1,3 @@
+INSERT INTO OAuth_Providers (provider_id, name)
+VALUES (2, 'github')
+ON CONFLICT (provider_id) DO NOTHING;
@@ -0,0 +1,9 @@
+-- Creating the Signing_Keys table
+CREATE TABLE Signing_Keys (
+    kid VARCHAR(255) PRIMARY KEY,
+    algorithm VARCHAR(16) NOT NULL,
+    key_material TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    promoted_at TIMESTAMP WITH TIME ZONE NULL,
+    retires_at TIMESTAMP WITH TIME ZONE NULL
+);
@@ -0,0 +1,21 @@
+-- Creating the Revoked_Tokens table: access tokens revoked individually by their jti
+CREATE TABLE Revoked_Tokens (
+    jti VARCHAR(64) PRIMARY KEY,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+CREATE INDEX idx_revoked_tokens_expires_at ON Revoked_Tokens (expires_at);
+
+-- Creating the User_Token_Revocations table: every token issued to the user up to
+-- issued_before is revoked
+CREATE TABLE User_Token_Revocations (
+    user_id INTEGER PRIMARY KEY,
+    issued_before TIMESTAMP WITH TIME ZONE NOT NULL,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    CONSTRAINT fk_user
+        FOREIGN KEY(user_id)
+        REFERENCES Users(user_id)
+        ON DELETE CASCADE
+);
+
+CREATE INDEX idx_user_token_revocations_expires_at ON User_Token_Revocations (expires_at);
@@ -0,0 +1,29 @@
+-- Creating the Sessions table: one row per login, tied to its refresh token family
+CREATE TABLE Sessions (
+    session_id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    provider_id INTEGER NULL,
+    refresh_family_id VARCHAR(64) UNIQUE NOT NULL,
+    user_agent TEXT NULL,
+    ip_address TEXT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    last_seen_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    revoked_at TIMESTAMP WITH TIME ZONE NULL,
+    CONSTRAINT fk_user
+        FOREIGN KEY(user_id)
+        REFERENCES Users(user_id)
+        ON DELETE CASCADE,
+    CONSTRAINT fk_provider
+        FOREIGN KEY(provider_id)
+        REFERENCES OAuth_Providers(provider_id)
+);
+
+CREATE INDEX idx_sessions_user_id ON Sessions (user_id);
+
+-- Creating the Revoked_Sessions table: every access token carrying the session id is revoked
+CREATE TABLE Revoked_Sessions (
+    session_id VARCHAR(64) PRIMARY KEY,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+CREATE INDEX idx_revoked_sessions_expires_at ON Revoked_Sessions (expires_at);
@@ -0,0 +1,2 @@
+-- The client that started a login decides how its tokens are delivered
+ALTER TABLE Pending_Authorizations ADD COLUMN client_id VARCHAR(255) NULL;
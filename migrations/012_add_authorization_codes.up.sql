@@ -0,0 +1,21 @@
+-- PKCE challenge a client sent when starting a login, checked when it redeems its code
+ALTER TABLE Pending_Authorizations ADD COLUMN code_challenge TEXT NULL;
+
+-- Creating the Authorization_Codes table: single-use codes that clients exchange for tokens
+CREATE TABLE Authorization_Codes (
+    code_hash VARCHAR(64) PRIMARY KEY,
+    client_id VARCHAR(255) NOT NULL,
+    user_id INTEGER NOT NULL,
+    refresh_family_id VARCHAR(64) NOT NULL,
+    code_challenge TEXT NULL,
+    redirect_url TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    used_at TIMESTAMP WITH TIME ZONE NULL,
+    CONSTRAINT fk_user
+        FOREIGN KEY(user_id)
+        REFERENCES Users(user_id)
+        ON DELETE CASCADE
+);
+
+CREATE INDEX idx_authorization_codes_expires_at ON Authorization_Codes (expires_at);
@@ -0,0 +1,17 @@
+-- Creating the Clients table: applications that use KuriLogin as their OpenID Connect provider
+CREATE TABLE Clients (
+    client_id VARCHAR(64) PRIMARY KEY,
+    name VARCHAR(255) NOT NULL,
+    -- NULL for public clients, which prove themselves with PKCE instead
+    secret_hash VARCHAR(64) NULL,
+    redirect_uris TEXT[] NOT NULL,
+    allowed_scopes TEXT[] NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+-- What a code and the tokens redeemed from it were granted
+ALTER TABLE Authorization_Codes ADD COLUMN scope TEXT NULL;
+ALTER TABLE Authorization_Codes ADD COLUMN nonce TEXT NULL;
+
+ALTER TABLE Refresh_Tokens ADD COLUMN client_id VARCHAR(255) NULL;
+ALTER TABLE Refresh_Tokens ADD COLUMN scope TEXT NULL;
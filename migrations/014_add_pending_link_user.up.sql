@@ -0,0 +1,3 @@
+-- The signed-in user an authorization links a new identity to, for linking flows
+ALTER TABLE Pending_Authorizations ADD COLUMN link_user_id INTEGER NULL
+    REFERENCES Users(user_id) ON DELETE CASCADE;
@@ -0,0 +1,7 @@
+-- Subject ids are only unique within a provider (GitHub's numeric ids can match another
+-- provider's), so identities are keyed by the pair
+ALTER TABLE OAuth_Authorizations DROP CONSTRAINT unq_provider_user_id;
+ALTER TABLE OAuth_Authorizations
+    ADD CONSTRAINT unq_provider_subject UNIQUE (provider_id, provider_user_id);
+-- The composite constraint's index serves lookups by provider
+DROP INDEX idx_oauth_provider_id;
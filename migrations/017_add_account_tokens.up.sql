@@ -0,0 +1,20 @@
+-- When a password account proved it controls its email
+ALTER TABLE User_Credentials ADD COLUMN email_verified_at TIMESTAMP WITH TIME ZONE NULL;
+
+-- Creating the Account_Tokens table: single-use tokens emailed for email verification and
+-- password reset, stored only as hashes
+CREATE TABLE Account_Tokens (
+    token_hash VARCHAR(64) PRIMARY KEY,
+    user_id INTEGER NOT NULL,
+    purpose VARCHAR(32) NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    used_at TIMESTAMP WITH TIME ZONE NULL,
+    CONSTRAINT fk_user
+        FOREIGN KEY(user_id)
+        REFERENCES Users(user_id)
+        ON DELETE CASCADE
+);
+
+CREATE INDEX idx_account_tokens_user_id ON Account_Tokens (user_id);
+CREATE INDEX idx_account_tokens_expires_at ON Account_Tokens (expires_at);
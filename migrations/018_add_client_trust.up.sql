@@ -0,0 +1,4 @@
+-- Without a consent step, /oauth/authorize only serves clients an admin marked as trusted.
+-- Clients registered so far were all allowed to sign users in, so they stay trusted.
+ALTER TABLE Clients ADD COLUMN trusted BOOLEAN NOT NULL DEFAULT FALSE;
+UPDATE Clients SET trusted = TRUE;
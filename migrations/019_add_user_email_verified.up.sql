@@ -0,0 +1,6 @@
+-- Only verified emails may join a new sign-in method to an existing user. Password accounts
+-- that confirmed their email are verified; emails of provider logins were stored without
+-- recording whether the provider had verified them, so those users count as unverified.
+ALTER TABLE Users ADD COLUMN email_verified BOOLEAN NOT NULL DEFAULT FALSE;
+UPDATE Users SET email_verified = TRUE
+    WHERE user_id IN (SELECT user_id FROM User_Credentials WHERE email_verified_at IS NOT NULL);
@@ -1,9 +1,9 @@
 use actix_web::{http::StatusCode, HttpResponse, ResponseError};
 use jsonwebtoken::errors::Error as JwtError;
-use serde_json::json;
+use serde_json::{json, Map, Value};
 use thiserror::Error;
 
-use crate::modules::{auth::AuthError, user::UserError};
+use crate::modules::{auth::AuthError, session::SessionError, user::UserError};
 
 #[derive(Error, Debug)]
 pub enum AppError {
@@ -13,6 +13,9 @@ pub enum AppError {
     #[error("General user management error: {0}")]
     UserError(#[from] UserError),
 
+    #[error("Session error: {0}")]
+    SessionError(#[from] SessionError),
+
     #[error("Unexpected error")]
     Unexpected,
 
@@ -23,6 +26,33 @@ pub enum AppError {
     NetworkError(String),
 }
 
+impl From<kuri_auth::Error> for AppError {
+    fn from(error: kuri_auth::Error) -> Self {
+        AppError::AuthError(error.into())
+    }
+}
+
+impl AppError {
+    /// Fields added next to `error` for errors that clients act on.
+    fn details(&self) -> Option<Map<String, Value>> {
+        match self {
+            AppError::AuthError(AuthError::LinkRequired(provider)) => {
+                let mut details = Map::new();
+                details.insert("provider".to_string(), json!(provider));
+                details.insert(
+                    "message".to_string(),
+                    json!(format!(
+                        "An account with this email exists; sign in to it and link {}",
+                        provider
+                    )),
+                );
+                Some(details)
+            }
+            _ => None,
+        }
+    }
+}
+
 impl ResponseError for AppError {
     fn error_response(&self) -> HttpResponse {
         let (status_code, error_message) = match self {
@@ -63,6 +93,93 @@ impl ResponseError for AppError {
                     StatusCode::BAD_REQUEST,
                     format!("OAuth2 request token error: {}", msg),
                 ),
+                AuthError::InvalidSigningKey(_) => (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Signing key misconfigured".to_string(),
+                ),
+                AuthError::SigningKeyNotFound(kid) => (
+                    StatusCode::NOT_FOUND,
+                    format!("Signing key not found: {}", kid),
+                ),
+                AuthError::KeyRotationConflict(msg) => (
+                    StatusCode::CONFLICT,
+                    format!("Key rotation conflict: {}", msg),
+                ),
+                AuthError::Unauthorized => (StatusCode::UNAUTHORIZED, "Unauthorized".to_string()),
+                AuthError::InvalidRefreshToken => (
+                    StatusCode::UNAUTHORIZED,
+                    "Invalid refresh token".to_string(),
+                ),
+                AuthError::RefreshTokenReused => (
+                    StatusCode::UNAUTHORIZED,
+                    "Refresh token reused; the session has been revoked".to_string(),
+                ),
+                AuthError::TokenRevoked => (StatusCode::UNAUTHORIZED, "Token revoked".to_string()),
+                AuthError::TokenExpired => (StatusCode::UNAUTHORIZED, "Token expired".to_string()),
+                AuthError::InvalidState(msg) => (
+                    StatusCode::BAD_REQUEST,
+                    format!("Invalid OAuth state: {}", msg),
+                ),
+                AuthError::InvalidClient(client) => (
+                    StatusCode::BAD_REQUEST,
+                    format!("Unknown client: {}", client),
+                ),
+                AuthError::InvalidReturnTo(return_to) => (
+                    StatusCode::BAD_REQUEST,
+                    format!("return_to not allowed: {}", return_to),
+                ),
+                AuthError::InvalidRedirectUri(redirect_uri) => (
+                    StatusCode::BAD_REQUEST,
+                    format!("redirect_uri not registered: {}", redirect_uri),
+                ),
+                AuthError::ClientNotFound(client_id) => (
+                    StatusCode::NOT_FOUND,
+                    format!("Client not found: {}", client_id),
+                ),
+                AuthError::InvalidGrant(msg) => {
+                    (StatusCode::BAD_REQUEST, format!("Invalid grant: {}", msg))
+                }
+                AuthError::RevocationFailed(msg) => (
+                    StatusCode::BAD_GATEWAY,
+                    format!("Upstream token revocation failed: {}", msg),
+                ),
+                AuthError::IdentityAlreadyLinked(provider) => (
+                    StatusCode::CONFLICT,
+                    format!(
+                        "This {} account is already linked to another user",
+                        provider
+                    ),
+                ),
+                // Front-ends key on `link_required` to send the user to sign in with their
+                // existing account and link the provider from there.
+                AuthError::LinkRequired(_) => (StatusCode::CONFLICT, "link_required".to_string()),
+                AuthError::InvalidCredentials => (
+                    StatusCode::UNAUTHORIZED,
+                    "Invalid email or password".to_string(),
+                ),
+                AuthError::WeakPassword(reason) => {
+                    (StatusCode::BAD_REQUEST, format!("Password {}", reason))
+                }
+                AuthError::InvalidAccountToken => (
+                    StatusCode::BAD_REQUEST,
+                    "Invalid or expired token".to_string(),
+                ),
+                AuthError::MailDeliveryFailed(_) => {
+                    (StatusCode::BAD_GATEWAY, "Failed to send email".to_string())
+                }
+                AuthError::IdentityNotFound(identity_id) => (
+                    StatusCode::NOT_FOUND,
+                    format!("Identity not found: {}", identity_id),
+                ),
+                AuthError::LastIdentity => (
+                    StatusCode::CONFLICT,
+                    "Cannot unlink the last way to sign in".to_string(),
+                ),
+                AuthError::Forbidden(_) => (StatusCode::FORBIDDEN, "Forbidden".to_string()),
+                AuthError::TokenVerificationUnavailable(_) => (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "Token verification unavailable".to_string(),
+                ),
             },
             AppError::UserError(user_error) => match user_error {
                 UserError::UserNotFound => (StatusCode::NOT_FOUND, "User not found".to_string()),
@@ -79,6 +196,15 @@ impl ResponseError for AppError {
                     "Unauthorized access attempt".to_string(),
                 ),
             },
+            AppError::SessionError(session_error) => match session_error {
+                SessionError::SessionNotFound => {
+                    (StatusCode::NOT_FOUND, "Session not found".to_string())
+                }
+                SessionError::DatabaseError(_) => (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Database error in session operation".to_string(),
+                ),
+            },
             AppError::Unexpected => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "An unexpected error occurred".to_string(),
@@ -90,7 +216,11 @@ impl ResponseError for AppError {
             AppError::NetworkError(msg) => (StatusCode::BAD_GATEWAY, msg.clone()),
         };
 
-        HttpResponse::build(status_code).json(json!({ "error": error_message }))
+        let mut body = json!({ "error": error_message });
+        if let (Some(body), Some(details)) = (body.as_object_mut(), self.details()) {
+            body.extend(details);
+        }
+        HttpResponse::build(status_code).json(body)
     }
 
     fn status_code(&self) -> StatusCode {
@@ -106,6 +236,31 @@ impl ResponseError for AppError {
                 AuthError::InvalidTokenError(_) => StatusCode::BAD_REQUEST,
                 AuthError::OAuth2RequestTokenError(_) => StatusCode::BAD_REQUEST,
                 AuthError::ProviderNotFound(_) => StatusCode::NOT_FOUND,
+                AuthError::InvalidSigningKey(_) => StatusCode::INTERNAL_SERVER_ERROR,
+                AuthError::SigningKeyNotFound(_) => StatusCode::NOT_FOUND,
+                AuthError::KeyRotationConflict(_) => StatusCode::CONFLICT,
+                AuthError::Unauthorized => StatusCode::UNAUTHORIZED,
+                AuthError::InvalidRefreshToken => StatusCode::UNAUTHORIZED,
+                AuthError::RefreshTokenReused => StatusCode::UNAUTHORIZED,
+                AuthError::TokenRevoked => StatusCode::UNAUTHORIZED,
+                AuthError::TokenExpired => StatusCode::UNAUTHORIZED,
+                AuthError::InvalidState(_) => StatusCode::BAD_REQUEST,
+                AuthError::InvalidClient(_) => StatusCode::BAD_REQUEST,
+                AuthError::InvalidReturnTo(_) => StatusCode::BAD_REQUEST,
+                AuthError::InvalidRedirectUri(_) => StatusCode::BAD_REQUEST,
+                AuthError::ClientNotFound(_) => StatusCode::NOT_FOUND,
+                AuthError::InvalidGrant(_) => StatusCode::BAD_REQUEST,
+                AuthError::RevocationFailed(_) => StatusCode::BAD_GATEWAY,
+                AuthError::IdentityAlreadyLinked(_) => StatusCode::CONFLICT,
+                AuthError::LinkRequired(_) => StatusCode::CONFLICT,
+                AuthError::InvalidCredentials => StatusCode::UNAUTHORIZED,
+                AuthError::WeakPassword(_) => StatusCode::BAD_REQUEST,
+                AuthError::InvalidAccountToken => StatusCode::BAD_REQUEST,
+                AuthError::MailDeliveryFailed(_) => StatusCode::BAD_GATEWAY,
+                AuthError::IdentityNotFound(_) => StatusCode::NOT_FOUND,
+                AuthError::LastIdentity => StatusCode::CONFLICT,
+                AuthError::Forbidden(_) => StatusCode::FORBIDDEN,
+                AuthError::TokenVerificationUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
             },
             AppError::UserError(user_error) => match user_error {
                 UserError::UserNotFound => StatusCode::NOT_FOUND,
@@ -113,6 +268,10 @@ impl ResponseError for AppError {
                 UserError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
                 UserError::Unauthorized => StatusCode::UNAUTHORIZED,
             },
+            AppError::SessionError(session_error) => match session_error {
+                SessionError::SessionNotFound => StatusCode::NOT_FOUND,
+                SessionError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            },
             AppError::Unexpected => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::JwtError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::NetworkError(_) => StatusCode::BAD_GATEWAY,
@@ -1,16 +1,30 @@
 #![allow(dead_code)]
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 use actix_cors::Cors;
-use actix_web::{middleware::Logger, web, App, HttpServer};
+use actix_web::{
+    cookie::{time::Duration as CookieDuration, SameSite},
+    middleware::Logger,
+    web, App, HttpServer,
+};
+use kuri_auth::TokenVerifier;
 
 use crate::{
     modules::{
-        auth::{self, infrastructure::GoogleProvider},
-        user,
+        auth::{
+            self,
+            infrastructure::{
+                FileMailer, GenericOidcProvider, GitHubProvider, GoogleProvider, SmtpMailer,
+                SmtpSecurity,
+            },
+            ports::{Mailer, Provider},
+        },
+        session, user,
+    },
+    utils::{
+        config::Config, password::PasswordHasher, postgres::PostgresRepository, token::hash_token,
     },
-    utils::{config::Config, postgres::PostgresRepository},
 };
 mod error;
 mod modules;
@@ -28,30 +42,292 @@ async fn main() -> std::io::Result<()> {
         config.google_client_id.clone(),
         config.google_client_secret.clone(),
         format!("{}/{}", config.domain.clone(), "auth/google/callback"),
+        config.google_jwks_uri.clone(),
     );
 
-    let jwt_manager = Arc::new(auth::JwtManager::new(config.jwt_secret.clone()));
+    let mut providers: Vec<Arc<dyn Provider>> = vec![Arc::new(google_provider)];
+    if let (Some(client_id), Some(client_secret)) = (
+        config.github_client_id.clone(),
+        config.github_client_secret.clone(),
+    ) {
+        providers.push(Arc::new(GitHubProvider::new(
+            client_id,
+            client_secret,
+            format!("{}/{}", config.domain.clone(), "auth/github/callback"),
+        )));
+    }
+    for oidc_config in config.oidc_providers {
+        let name = oidc_config.name.clone();
+        let redirect_uri = format!("{}/auth/{}/callback", config.domain, name);
+        let provider = GenericOidcProvider::discover(oidc_config, redirect_uri)
+            .await
+            .unwrap_or_else(|e| panic!("Failed to discover OIDC provider {}: {}", name, e));
+        log::info!("Configured OIDC provider {}", name);
+        providers.push(Arc::new(provider));
+    }
+
+    let jwt_algorithm = config
+        .jwt_algorithm
+        .parse()
+        .expect("JWT_ALGORITHM is not a known algorithm");
+    let signing_key = {
+        let algorithm = jwt_algorithm;
+        match &config.jwt_private_key_path {
+            Some(path) => {
+                let pem = std::fs::read(path).expect("Failed to read JWT_PRIVATE_KEY_PATH");
+                auth::SigningKey::from_pem(config.jwt_key_id.clone(), algorithm, &pem)
+            }
+            None => auth::SigningKey::hmac(
+                config
+                    .jwt_key_id
+                    .clone()
+                    .unwrap_or_else(|| "default".to_string()),
+                algorithm,
+                config
+                    .jwt_secret
+                    .as_ref()
+                    .expect("JWT_SECRET not set")
+                    .as_bytes(),
+            ),
+        }
+        .expect("Invalid JWT signing key")
+    };
+
+    let jwt_manager = {
+        let mut jwt_builder = auth::JwtManagerBuilder::new(signing_key)
+            .issuer(config.jwt_issuer.clone())
+            .ttl_seconds(config.jwt_ttl_seconds)
+            .leeway_seconds(config.jwt_leeway_seconds);
+        jwt_builder = match config.revocation_store.as_str() {
+            "postgres" => jwt_builder.revocation_store(repo.clone()),
+            "memory" => {
+                jwt_builder.revocation_store(Arc::new(auth::InMemoryRevocationStore::default()))
+            }
+            other => panic!("Unknown REVOCATION_STORE {}", other),
+        };
+        if let Some(audience) = config.jwt_audience.clone() {
+            jwt_builder = jwt_builder.audience(audience);
+        }
+        Arc::new(jwt_builder.build())
+    };
+
+    let mailer: Arc<dyn Mailer> = match config.mailer.as_str() {
+        "log" => Arc::new(FileMailer::new(config.mail_file.clone().map(Into::into))),
+        "smtp" => Arc::new(
+            SmtpMailer::new(
+                config.smtp_host.as_deref().expect("SMTP_HOST not set"),
+                config.smtp_port,
+                match config.smtp_security.as_str() {
+                    "tls" => SmtpSecurity::Tls,
+                    "starttls" => SmtpSecurity::StartTls,
+                    "none" => SmtpSecurity::None,
+                    other => panic!("Unknown SMTP_SECURITY {}", other),
+                },
+                match (&config.smtp_username, &config.smtp_password) {
+                    (Some(username), Some(password)) => Some((username.clone(), password.clone())),
+                    (None, None) => None,
+                    _ => panic!("SMTP_USERNAME and SMTP_PASSWORD must be set together"),
+                },
+                &config.mail_from,
+            )
+            .expect("Invalid SMTP settings"),
+        ),
+        other => panic!("Unknown MAILER {}", other),
+    };
+    assert!(
+        config.password_min_length <= config.password_max_length,
+        "PASSWORD_MIN_LENGTH must not exceed PASSWORD_MAX_LENGTH"
+    );
+    for url in [&config.email_verification_url, &config.password_reset_url] {
+        oauth2::url::Url::parse(url)
+            .unwrap_or_else(|e| panic!("Invalid account link URL {}: {}", url, e));
+    }
 
     let user_service = Arc::new(user::AppService::new(repo.clone(), jwt_manager.clone()));
+    let session_service = Arc::new(session::AppService::new(repo.clone(), jwt_manager.clone()));
 
     let auth_service = Arc::new(auth::AppService::new(
-        vec![Arc::new(google_provider)],
+        providers,
         repo.clone(),
+        mailer,
         user_service.clone(),
-        jwt_manager,
+        session_service.clone(),
+        jwt_manager.clone(),
+        auth::AuthSettings {
+            admin_token: config.admin_token.clone(),
+            key_algorithm: jwt_algorithm,
+            key_retirement: chrono::Duration::seconds(config.jwt_key_retirement_seconds),
+            refresh_token_ttl: chrono::Duration::seconds(config.refresh_token_ttl_seconds),
+            clients: config
+                .clients
+                .iter()
+                .map(|client| {
+                    let delivery = match client.token_delivery.as_str() {
+                        "json" => auth::TokenDelivery::Json,
+                        "cookie" => auth::TokenDelivery::Cookie,
+                        "fragment" => auth::TokenDelivery::Fragment,
+                        "code" => auth::TokenDelivery::Code,
+                        other => panic!(
+                            "Unknown token delivery {} for client {}",
+                            other, client.client_id
+                        ),
+                    };
+                    if delivery != auth::TokenDelivery::Json && client.redirect_urls.is_empty() {
+                        panic!("Client {} needs a redirect URL", client.client_id);
+                    }
+                    auth::ClientSettings {
+                        client_id: client.client_id.clone(),
+                        secret_hash: client.secret.as_deref().map(hash_token),
+                        delivery,
+                        redirect_urls: client.redirect_urls.clone(),
+                    }
+                })
+                .collect(),
+            return_to_origins: config.return_to_origins.clone(),
+            base_url: config.domain.clone(),
+            default_provider: config.default_provider.clone(),
+            revoke_upstream_tokens: config.revoke_upstream_tokens,
+            email_linking: match config.auto_link_by_email.as_str() {
+                "verified" => auth::EmailLinking::Verified,
+                "never" => auth::EmailLinking::Never,
+                other => panic!("Unknown AUTO_LINK_BY_EMAIL policy {}", other),
+            },
+            password_hasher: PasswordHasher::new(
+                argon2::Params::new(
+                    config.argon2_memory_kib,
+                    config.argon2_iterations,
+                    config.argon2_parallelism,
+                    None,
+                )
+                .expect("Invalid Argon2 parameters"),
+            ),
+            password_policy: auth::PasswordPolicy {
+                min_length: config.password_min_length,
+                max_length: config.password_max_length,
+                blocklist: Arc::new(
+                    config
+                        .password_blocklist_file
+                        .as_deref()
+                        .map(|path| {
+                            let contents = std::fs::read_to_string(path).unwrap_or_else(|e| {
+                                panic!("Failed to read PASSWORD_BLOCKLIST_FILE {}: {}", path, e)
+                            });
+                            auth::PasswordPolicy::parse_blocklist(&contents)
+                        })
+                        .unwrap_or_default(),
+                ),
+            },
+            email_verification_url: config.email_verification_url.clone(),
+            password_reset_url: config.password_reset_url.clone(),
+        },
     ));
+    auth_service
+        .register_providers()
+        .await
+        .expect("Failed to register OAuth providers");
+    auth_service
+        .reload_signing_keys()
+        .await
+        .expect("Failed to load signing keys");
+
+    // Other instances may rotate keys; pick up their changes periodically.
+    let key_reloader = auth_service.clone();
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            if let Err(e) = key_reloader.reload_signing_keys().await {
+                log::error!("Failed to reload signing keys: {}", e);
+            }
+        }
+    });
+
+    let revocation_pruner = auth_service.clone();
+    actix_web::rt::spawn(async move {
+        let mut interval = actix_web::rt::time::interval(Duration::from_secs(3600));
+        loop {
+            interval.tick().await;
+            match revocation_pruner.prune_revocations().await {
+                Ok(pruned) => log::debug!("Pruned {} expired token revocations", pruned),
+                Err(e) => log::error!("Failed to prune token revocations: {}", e),
+            }
+        }
+    });
+
+    let token_verifier: Arc<dyn TokenVerifier> = jwt_manager.clone();
+    let authenticated_user_config = auth::api::AuthenticatedUserConfig {
+        cookie_name: Some(config.auth_cookie_name.clone()),
+        csrf_cookie_name: config.csrf_cookie_name.clone(),
+    };
+    let cookie_settings = web::Data::new(auth::api::CookieSettings {
+        access_cookie: config.auth_cookie_name.clone(),
+        refresh_cookie: config.refresh_cookie_name.clone(),
+        csrf_cookie: config.csrf_cookie_name.clone(),
+        domain: config.cookie_domain.clone(),
+        same_site: match config.cookie_same_site.to_lowercase().as_str() {
+            "strict" => SameSite::Strict,
+            "lax" => SameSite::Lax,
+            "none" => SameSite::None,
+            other => panic!("Unknown COOKIE_SAME_SITE {}", other),
+        },
+        secure: config.cookie_secure,
+        refresh_max_age: CookieDuration::seconds(config.refresh_token_ttl_seconds),
+    });
+    let allow_path_token = config.allow_path_token;
+    if allow_path_token {
+        log::warn!("GET /me/{{token}} is enabled; it is deprecated in favour of GET /me");
+    }
+
+    // Cross-origin requests carry the session cookies, so only the frontends this service
+    // sends users to may read the responses.
+    let cors_origins: Vec<String> = config
+        .return_to_origins
+        .iter()
+        .chain(
+            config
+                .clients
+                .iter()
+                .flat_map(|client| &client.redirect_urls),
+        )
+        .chain([
+            &config.domain,
+            &config.email_verification_url,
+            &config.password_reset_url,
+        ])
+        .filter_map(|url| oauth2::url::Url::parse(url).ok())
+        .map(|url| url.origin())
+        .filter(|origin| origin.is_tuple())
+        .map(|origin| origin.ascii_serialization())
+        .collect();
 
     log::info!("Starting HTTP server on 0.0.0.0:80...");
     HttpServer::new(move || {
-        let cors = Cors::permissive();
+        let cors = cors_origins.iter().fold(
+            Cors::default()
+                .allow_any_method()
+                .allow_any_header()
+                .expose_any_header()
+                .supports_credentials()
+                .max_age(3600),
+            |cors, origin| cors.allowed_origin(origin),
+        );
 
-        App::new()
+        let mut app = App::new()
             .wrap(cors)
             .wrap(Logger::default())
             .configure(auth::api::config)
-            .configure(user::api::config)
+            .configure(session::api::config)
+            .configure(user::api::config);
+        if allow_path_token {
+            app = app.configure(user::api::path_token_config);
+        }
+
+        app.app_data(authenticated_user_config.clone())
+            .app_data(cookie_settings.clone())
+            .app_data(web::Data::new(token_verifier.clone()))
             .app_data(web::Data::new(auth_service.clone()))
             .app_data(web::Data::new(user_service.clone()))
+            .app_data(web::Data::new(session_service.clone()))
     })
     .bind("0.0.0.0:80")?
     .run()
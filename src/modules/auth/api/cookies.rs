@@ -0,0 +1,268 @@
+use actix_web::{
+    cookie::{time::Duration, Cookie, SameSite},
+    HttpRequest,
+};
+
+use crate::{
+    error::AppError,
+    modules::auth::{AuthError, PendingAuthorization, TokenPair},
+    utils::token::{generate_token, hash_token},
+};
+
+/// How browser-mode logins are stored in cookies.
+#[derive(Debug, Clone)]
+pub struct CookieSettings {
+    /// `HttpOnly` cookie holding the access token.
+    pub access_cookie: String,
+    /// `HttpOnly` cookie holding the refresh token, only sent to the refresh endpoint.
+    pub refresh_cookie: String,
+    /// Cookie holding the CSRF token, readable by the page so it can echo it in a header.
+    pub csrf_cookie: String,
+    pub domain: Option<String>,
+    pub same_site: SameSite,
+    pub secure: bool,
+    pub refresh_max_age: Duration,
+}
+
+impl CookieSettings {
+    /// The cookies of a signed-in browser, including a fresh CSRF token.
+    pub fn session_cookies(&self, tokens: &TokenPair) -> Vec<Cookie<'static>> {
+        vec![
+            self.cookie(&self.access_cookie, tokens.access_token.clone(), "/")
+                .http_only(true)
+                .max_age(Duration::seconds(tokens.expires_in))
+                .finish(),
+            self.cookie(
+                &self.refresh_cookie,
+                tokens.refresh_token.clone(),
+                "/auth/token/refresh",
+            )
+            .http_only(true)
+            .max_age(self.refresh_max_age)
+            .finish(),
+            self.cookie(&self.csrf_cookie, generate_token(32), "/")
+                .max_age(self.refresh_max_age)
+                .finish(),
+        ]
+    }
+
+    /// The cookie binding a login's `state` to the browser that started it, holding the
+    /// state's hash. It has to reach the callback when the provider redirects back from
+    /// another site, so it is always `SameSite=Lax`.
+    pub fn state_cookie(&self, state: &str) -> Cookie<'static> {
+        Cookie::build(self.state_cookie_name(), hash_token(state))
+            .path("/")
+            .secure(self.secure)
+            .http_only(true)
+            .same_site(SameSite::Lax)
+            .max_age(Duration::seconds(PendingAuthorization::TTL_SECONDS))
+            .finish()
+    }
+
+    /// Removes the state cookie once the callback has used it.
+    pub fn state_removal_cookie(&self) -> Cookie<'static> {
+        let mut cookie = self.state_cookie("");
+        cookie.make_removal();
+        cookie
+    }
+
+    /// The `__Host-` prefix keeps subdomains from planting the cookie, but browsers only
+    /// accept it on `Secure` cookies.
+    pub fn state_cookie_name(&self) -> &'static str {
+        if self.secure {
+            "__Host-kuri_oauth_state"
+        } else {
+            "kuri_oauth_state"
+        }
+    }
+
+    /// The refresh token of a cookie session. The browser sends the cookie on cross-site
+    /// requests too, so the request must also pass the CSRF check.
+    pub fn refresh_token(&self, req: &HttpRequest) -> Result<String, AppError> {
+        let refresh_cookie = req
+            .cookie(&self.refresh_cookie)
+            .ok_or(AuthError::InvalidRefreshToken)?;
+        kuri_auth::verify_csrf(req, &self.csrf_cookie)?;
+        Ok(refresh_cookie.value().to_string())
+    }
+
+    /// Cookies that make the browser forget its session.
+    pub fn removal_cookies(&self) -> Vec<Cookie<'static>> {
+        [
+            (&self.access_cookie, "/"),
+            (&self.refresh_cookie, "/auth/token/refresh"),
+            (&self.csrf_cookie, "/"),
+        ]
+        .into_iter()
+        .map(|(name, path)| {
+            let mut cookie = self.cookie(name, String::new(), path).finish();
+            cookie.make_removal();
+            cookie
+        })
+        .collect()
+    }
+
+    fn cookie(
+        &self,
+        name: &str,
+        value: String,
+        path: &'static str,
+    ) -> actix_web::cookie::CookieBuilder<'static> {
+        let mut builder = Cookie::build(name.to_string(), value)
+            .path(path)
+            .secure(self.secure)
+            .same_site(self.same_site);
+        if let Some(domain) = &self.domain {
+            builder = builder.domain(domain.clone());
+        }
+        builder
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        http::StatusCode,
+        test,
+        web::{self, Data},
+        App, HttpResponse, ResponseError,
+    };
+
+    use super::*;
+
+    fn settings(domain: Option<&str>) -> CookieSettings {
+        CookieSettings {
+            access_cookie: "kuri_access".to_string(),
+            refresh_cookie: "kuri_refresh".to_string(),
+            csrf_cookie: "kuri_csrf".to_string(),
+            domain: domain.map(String::from),
+            same_site: SameSite::Lax,
+            secure: true,
+            refresh_max_age: Duration::days(30),
+        }
+    }
+
+    fn tokens() -> TokenPair {
+        TokenPair {
+            access_token: "access".to_string(),
+            refresh_token: "refresh".to_string(),
+            token_type: "Bearer".to_string(),
+            expires_in: 900,
+            scope: None,
+            id_token: None,
+        }
+    }
+
+    /// Signs the browser in like a cookie login, and refreshes like `/auth/token/refresh`.
+    fn routes(cfg: &mut web::ServiceConfig) {
+        cfg.route(
+            "/login",
+            web::get().to(|settings: Data<CookieSettings>| async move {
+                let mut response = HttpResponse::Found();
+                for cookie in settings.session_cookies(&tokens()) {
+                    response.cookie(cookie);
+                }
+                response.finish()
+            }),
+        )
+        .route(
+            "/auth/token/refresh",
+            web::post().to(
+                |settings: Data<CookieSettings>, req: HttpRequest| async move {
+                    match settings.refresh_token(&req) {
+                        Ok(refresh_token) => HttpResponse::Ok().body(refresh_token),
+                        Err(e) => e.error_response(),
+                    }
+                },
+            ),
+        );
+    }
+
+    #[actix_web::test]
+    async fn delivers_http_only_tokens_and_a_readable_csrf_token() {
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(settings(None)))
+                .configure(routes),
+        )
+        .await;
+        let response =
+            test::call_service(&app, test::TestRequest::get().uri("/login").to_request()).await;
+        let cookies: Vec<_> = response.response().cookies().collect();
+        let cookie = |name: &str| cookies.iter().find(|cookie| cookie.name() == name).unwrap();
+
+        assert_eq!(cookie("kuri_access").value(), "access");
+        assert_eq!(cookie("kuri_access").http_only(), Some(true));
+        assert_eq!(cookie("kuri_refresh").http_only(), Some(true));
+        assert_eq!(cookie("kuri_refresh").path(), Some("/auth/token/refresh"));
+        assert_ne!(cookie("kuri_csrf").http_only(), Some(true));
+        assert!(cookies.iter().all(|cookie| cookie.secure() == Some(true)));
+        assert!(cookies.iter().all(|cookie| cookie.domain().is_none()));
+    }
+
+    #[actix_web::test]
+    async fn scopes_cookies_to_the_configured_domain() {
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(settings(Some("example.com"))))
+                .configure(routes),
+        )
+        .await;
+        let response =
+            test::call_service(&app, test::TestRequest::get().uri("/login").to_request()).await;
+        assert!(response
+            .response()
+            .cookies()
+            .all(|cookie| cookie.domain() == Some("example.com")));
+    }
+
+    #[actix_web::test]
+    async fn refreshing_from_cookies_needs_the_csrf_header() {
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(settings(None)))
+                .configure(routes),
+        )
+        .await;
+        let refresh = |csrf_header: Option<&str>| {
+            let mut req = test::TestRequest::post()
+                .uri("/auth/token/refresh")
+                .cookie(Cookie::new("kuri_refresh", "refresh"))
+                .cookie(Cookie::new("kuri_csrf", "csrf-token"));
+            if let Some(csrf_header) = csrf_header {
+                req = req.insert_header((kuri_auth::CSRF_HEADER, csrf_header));
+            }
+            req.to_request()
+        };
+
+        let missing = test::call_service(&app, refresh(None)).await;
+        assert_eq!(missing.status(), StatusCode::FORBIDDEN);
+        let forged = test::call_service(&app, refresh(Some("guess"))).await;
+        assert_eq!(forged.status(), StatusCode::FORBIDDEN);
+        let empty = test::call_service(&app, refresh(Some(""))).await;
+        assert_eq!(empty.status(), StatusCode::FORBIDDEN);
+        let valid = test::call_service(&app, refresh(Some("csrf-token"))).await;
+        assert_eq!(valid.status(), StatusCode::OK);
+        assert_eq!(test::read_body(valid).await, "refresh");
+    }
+
+    #[actix_web::test]
+    async fn refreshing_without_a_cookie_is_unauthorized() {
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(settings(None)))
+                .configure(routes),
+        )
+        .await;
+        let response = test::call_service(
+            &app,
+            test::TestRequest::post()
+                .uri("/auth/token/refresh")
+                .cookie(Cookie::new("kuri_csrf", "csrf-token"))
+                .insert_header((kuri_auth::CSRF_HEADER, "csrf-token"))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -1,19 +1,51 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
 
-use actix_web::{http::header::LOCATION, web, HttpResponse, Responder, ResponseError};
+use actix_web::{
+    http::header::{AUTHORIZATION, CACHE_CONTROL, LOCATION, USER_AGENT},
+    web, HttpRequest, HttpResponse, Responder, ResponseError,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use jsonwebtoken::Algorithm;
+use oauth2::url::{form_urlencoded, Url};
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
 
-use crate::modules::auth::AppService;
+use crate::{
+    error::AppError,
+    modules::{
+        auth::{
+            AppService, AuthError, AuthorizeOutcome, AuthorizeRequest, LoginOutcome,
+            RegisteredClient, TokenPair,
+        },
+        session::ClientInfo,
+    },
+};
+
+use super::{AuthenticatedUser, CookieSettings, FirstPartyUser};
 
 pub async fn login(
     app_service: web::Data<Arc<AppService>>,
+    cookie_settings: web::Data<CookieSettings>,
     provider_name: web::Path<String>,
+    query: web::Query<HashMap<String, String>>,
 ) -> impl Responder {
     match app_service
-        .initiate_oauth(get_provider_id(&provider_name.to_string()))
+        .initiate_oauth(
+            &provider_name,
+            query.get("client").map(String::as_str),
+            query.get("return_to").map(String::as_str),
+            query.get("code_challenge").map(|challenge| {
+                (
+                    challenge.as_str(),
+                    query.get("code_challenge_method").map(String::as_str),
+                )
+            }),
+        )
         .await
     {
-        Ok(auth_url) => HttpResponse::Found()
-            .append_header((LOCATION, auth_url))
+        Ok(redirect) => HttpResponse::Found()
+            .append_header((LOCATION, redirect.url))
+            .cookie(cookie_settings.state_cookie(&redirect.state))
             .finish(),
         Err(e) => e.error_response(),
     }
@@ -21,28 +53,750 @@ pub async fn login(
 
 pub async fn oauth_callback(
     app_service: web::Data<Arc<AppService>>,
+    cookie_settings: web::Data<CookieSettings>,
     provider_name: web::Path<String>,
     query: web::Query<HashMap<String, String>>,
+    req: HttpRequest,
+) -> impl Responder {
+    let Some(code) = query.get("code") else {
+        return HttpResponse::BadRequest().body("Missing authorization code.");
+    };
+    let Some(state) = query.get("state") else {
+        return HttpResponse::BadRequest().body("Missing state parameter.");
+    };
+    let state_cookie = req.cookie(cookie_settings.state_cookie_name());
+
+    let mut response = match app_service
+        .oauth_login(
+            code.to_string(),
+            state.to_string(),
+            state_cookie.as_ref().map(|cookie| cookie.value()),
+            &provider_name,
+            &client_info(&req),
+        )
+        .await
+    {
+        Ok(LoginOutcome::Json(tokens)) => HttpResponse::Ok().json(tokens),
+        Ok(LoginOutcome::Cookie {
+            tokens,
+            redirect_url,
+        }) => {
+            let mut response = HttpResponse::Found();
+            response.append_header((LOCATION, redirect_url));
+            for cookie in cookie_settings.session_cookies(&tokens) {
+                response.cookie(cookie);
+            }
+            response.finish()
+        }
+        Ok(LoginOutcome::Fragment {
+            tokens,
+            redirect_url,
+        }) => match fragment_redirect(&redirect_url, &tokens) {
+            Some(location) => HttpResponse::Found()
+                .append_header((LOCATION, location))
+                .finish(),
+            None => HttpResponse::InternalServerError().finish(),
+        },
+        Ok(LoginOutcome::Linked {
+            redirect_url: Some(redirect_url),
+            ..
+        }) => HttpResponse::Found()
+            .append_header((LOCATION, redirect_url))
+            .finish(),
+        Ok(LoginOutcome::Linked { identity, .. }) => HttpResponse::Ok().json(identity),
+        Ok(LoginOutcome::Code { code, redirect_url }) => match Url::parse(&redirect_url) {
+            Ok(mut url) => {
+                url.query_pairs_mut().append_pair("code", &code);
+                HttpResponse::Found()
+                    .append_header((LOCATION, url.to_string()))
+                    .finish()
+            }
+            Err(_) => HttpResponse::InternalServerError().finish(),
+        },
+        Err(e) => e.error_response(),
+    };
+    if state_cookie.is_some() {
+        // The state is used up either way.
+        let _ = response.add_cookie(&cookie_settings.state_removal_cookie());
+    }
+    response
+}
+
+#[derive(Deserialize)]
+pub struct TokenRequest {
+    grant_type: String,
+    code: Option<String>,
+    code_verifier: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    refresh_token: Option<String>,
+    redirect_uri: Option<String>,
+}
+
+/// The OAuth token endpoint: redeems the codes handed out by `code` clients, and refreshes
+/// token pairs like `/auth/token/refresh`. Clients may authenticate with HTTP Basic.
+pub async fn token(
+    app_service: web::Data<Arc<AppService>>,
+    form: web::Form<TokenRequest>,
+    req: HttpRequest,
+) -> impl Responder {
+    let form = form.into_inner();
+    let (client_id, client_secret) = client_credentials(&req, form.client_id, form.client_secret);
+
+    let result = match form.grant_type.as_str() {
+        "authorization_code" => {
+            let (Some(code), Some(client_id)) = (form.code, client_id) else {
+                return HttpResponse::BadRequest().body("Missing code or client_id.");
+            };
+            app_service
+                .exchange_authorization_code(
+                    &code,
+                    &client_id,
+                    client_secret.as_deref(),
+                    form.code_verifier.as_deref(),
+                )
+                .await
+        }
+        "refresh_token" => {
+            let Some(refresh_token) = form.refresh_token else {
+                return HttpResponse::BadRequest().body("Missing refresh_token.");
+            };
+            app_service.refresh_tokens(&refresh_token, None).await
+        }
+        other => {
+            return HttpResponse::BadRequest().body(format!("Unsupported grant_type {}.", other))
+        }
+    };
+    match result {
+        Ok(tokens) => HttpResponse::Ok()
+            .append_header((CACHE_CONTROL, "no-store"))
+            .json(tokens),
+        Err(e) => e.error_response(),
+    }
+}
+
+/// `redirect_url` with the tokens form-encoded in its fragment, as in the OAuth implicit flow.
+fn fragment_redirect(redirect_url: &str, tokens: &TokenPair) -> Option<String> {
+    let mut url = Url::parse(redirect_url).ok()?;
+    let fragment = form_urlencoded::Serializer::new(String::new())
+        .append_pair("access_token", &tokens.access_token)
+        .append_pair("token_type", &tokens.token_type)
+        .append_pair("expires_in", &tokens.expires_in.to_string())
+        .append_pair("refresh_token", &tokens.refresh_token)
+        .finish();
+    url.set_fragment(Some(&fragment));
+    Some(url.to_string())
+}
+
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    refresh_token: String,
+}
+
+/// Refreshes a token pair sent as JSON, or a browser session kept in cookies. The cookie
+/// variant needs the CSRF header like any other cookie-authenticated request.
+pub async fn refresh_token(
+    app_service: web::Data<Arc<AppService>>,
+    cookie_settings: web::Data<CookieSettings>,
+    body: Option<web::Json<RefreshRequest>>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Some(body) = body {
+        return match app_service.refresh_tokens(&body.refresh_token, None).await {
+            Ok(tokens) => HttpResponse::Ok().json(tokens),
+            Err(e) => e.error_response(),
+        };
+    }
+
+    let refresh_token = match cookie_settings.refresh_token(&req) {
+        Ok(refresh_token) => refresh_token,
+        Err(e) => return e.error_response(),
+    };
+    match app_service.refresh_tokens(&refresh_token, None).await {
+        Ok(tokens) => {
+            let mut response = HttpResponse::NoContent();
+            for cookie in cookie_settings.session_cookies(&tokens) {
+                response.cookie(cookie);
+            }
+            response.finish()
+        }
+        Err(e) => e.error_response(),
+    }
+}
+
+/// The OpenID Connect authorization endpoint. Signed-in browsers are sent straight back to the
+/// client with a code; others go through an upstream login first.
+pub async fn authorize(
+    app_service: web::Data<Arc<AppService>>,
+    cookie_settings: web::Data<CookieSettings>,
+    query: web::Query<AuthorizeRequest>,
+    user: Option<FirstPartyUser>,
+    req: HttpRequest,
+) -> impl Responder {
+    match app_service
+        .authorize(
+            &query,
+            req.query_string(),
+            user.as_deref(),
+            &client_info(&req),
+        )
+        .await
+    {
+        Ok(AuthorizeOutcome::Redirect(location)) => HttpResponse::Found()
+            .append_header((LOCATION, location))
+            .finish(),
+        Ok(AuthorizeOutcome::Login(redirect)) => HttpResponse::Found()
+            .append_header((LOCATION, redirect.url))
+            .cookie(cookie_settings.state_cookie(&redirect.state))
+            .finish(),
+        Err(e) => e.error_response(),
+    }
+}
+
+/// The token endpoint for registered OpenID Connect clients.
+pub async fn oauth_token(
+    app_service: web::Data<Arc<AppService>>,
+    form: web::Form<TokenRequest>,
+    req: HttpRequest,
+) -> impl Responder {
+    let form = form.into_inner();
+    let (client_id, client_secret) = client_credentials(&req, form.client_id, form.client_secret);
+    let Some(client_id) = client_id else {
+        return HttpResponse::BadRequest().body("Missing client_id.");
+    };
+    let client = match app_service
+        .authenticate_client(&client_id, client_secret.as_deref())
+        .await
+    {
+        Ok(client) => client,
+        Err(e) => return e.error_response(),
+    };
+
+    let result = match form.grant_type.as_str() {
+        "authorization_code" => {
+            let Some(code) = form.code else {
+                return HttpResponse::BadRequest().body("Missing code.");
+            };
+            app_service
+                .exchange_client_code(
+                    &code,
+                    &client,
+                    form.code_verifier.as_deref(),
+                    form.redirect_uri.as_deref(),
+                )
+                .await
+        }
+        "refresh_token" => {
+            let Some(refresh_token) = form.refresh_token else {
+                return HttpResponse::BadRequest().body("Missing refresh_token.");
+            };
+            app_service
+                .refresh_tokens(&refresh_token, Some(&client.client_id))
+                .await
+        }
+        other => {
+            return HttpResponse::BadRequest().body(format!("Unsupported grant_type {}.", other))
+        }
+    };
+    match result {
+        Ok(tokens) => HttpResponse::Ok()
+            .append_header((CACHE_CONTROL, "no-store"))
+            .json(tokens),
+        Err(e) => e.error_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct IntrospectionRequest {
+    token: String,
+    token_type_hint: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+}
+
+/// Token introspection (RFC 7662) for resource servers, which authenticate as confidential
+/// clients.
+pub async fn introspect(
+    app_service: web::Data<Arc<AppService>>,
+    form: web::Form<IntrospectionRequest>,
+    req: HttpRequest,
 ) -> impl Responder {
-    if let Some(code) = query.get("code") {
-        match app_service
-            .oauth_login(
-                code.to_string(),
-                get_provider_id(&provider_name.to_string()),
-            )
+    let form = form.into_inner();
+    let (client_id, client_secret) = client_credentials(&req, form.client_id, form.client_secret);
+    let (Some(client_id), Some(client_secret)) = (client_id, client_secret) else {
+        return AppError::from(AuthError::Unauthorized).error_response();
+    };
+    match app_service
+        .authenticate_client(&client_id, Some(&client_secret))
+        .await
+    {
+        Ok(client) if client.is_confidential() => {}
+        Ok(_) => return AppError::from(AuthError::InvalidClient(client_id)).error_response(),
+        Err(e) => return e.error_response(),
+    }
+
+    match app_service
+        .introspect(&form.token, form.token_type_hint.as_deref())
+        .await
+    {
+        Ok(introspection) => HttpResponse::Ok()
+            .append_header((CACHE_CONTROL, "no-store"))
+            .json(introspection),
+        Err(e) => e.error_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RevocationRequest {
+    token: String,
+    token_type_hint: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+}
+
+/// Token revocation (RFC 7009). Clients authenticate to revoke tokens they were granted;
+/// tokens of KuriLogin's own logins can be revoked without credentials.
+pub async fn revoke(
+    app_service: web::Data<Arc<AppService>>,
+    form: web::Form<RevocationRequest>,
+    req: HttpRequest,
+) -> impl Responder {
+    let form = form.into_inner();
+    let client = match client_credentials(&req, form.client_id, form.client_secret) {
+        (Some(client_id), client_secret) => match app_service
+            .authenticate_client(&client_id, client_secret.as_deref())
             .await
         {
-            Ok(jwt) => HttpResponse::Ok().json(jwt),
-            Err(e) => e.error_response(),
+            Ok(client) => Some(client),
+            Err(e) => return e.error_response(),
+        },
+        (None, _) => None,
+    };
+
+    match app_service
+        .revoke_token(
+            &form.token,
+            form.token_type_hint.as_deref(),
+            client.as_ref().map(|client| client.client_id.as_str()),
+        )
+        .await
+    {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(e) => e.error_response(),
+    }
+}
+
+pub async fn userinfo(
+    app_service: web::Data<Arc<AppService>>,
+    user: AuthenticatedUser,
+) -> impl Responder {
+    match app_service.user_info(&user).await {
+        Ok(user_info) => HttpResponse::Ok().json(user_info),
+        Err(e) => e.error_response(),
+    }
+}
+
+pub async fn openid_configuration(app_service: web::Data<Arc<AppService>>) -> impl Responder {
+    HttpResponse::Ok()
+        .append_header((CACHE_CONTROL, "public, max-age=300"))
+        .json(app_service.openid_configuration())
+}
+
+pub async fn jwks(app_service: web::Data<Arc<AppService>>) -> impl Responder {
+    HttpResponse::Ok()
+        .append_header((CACHE_CONTROL, "public, max-age=300"))
+        .json(app_service.jwks())
+}
+
+#[derive(Deserialize)]
+pub struct GenerateKeyRequest {
+    algorithm: Option<Algorithm>,
+}
+
+pub async fn list_signing_keys(
+    app_service: web::Data<Arc<AppService>>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Err(e) = app_service.authorize_admin(bearer_token(&req)) {
+        return e.error_response();
+    }
+    match app_service.list_signing_keys().await {
+        Ok(keys) => HttpResponse::Ok().json(keys),
+        Err(e) => e.error_response(),
+    }
+}
+
+pub async fn generate_signing_key(
+    app_service: web::Data<Arc<AppService>>,
+    req: HttpRequest,
+    body: Option<web::Json<GenerateKeyRequest>>,
+) -> impl Responder {
+    if let Err(e) = app_service.authorize_admin(bearer_token(&req)) {
+        return e.error_response();
+    }
+    let algorithm = body.and_then(|body| body.algorithm);
+    match app_service.generate_signing_key(algorithm).await {
+        Ok(key) => HttpResponse::Created().json(key),
+        Err(e) => e.error_response(),
+    }
+}
+
+pub async fn promote_signing_key(
+    app_service: web::Data<Arc<AppService>>,
+    req: HttpRequest,
+    kid: web::Path<String>,
+) -> impl Responder {
+    if let Err(e) = app_service.authorize_admin(bearer_token(&req)) {
+        return e.error_response();
+    }
+    match app_service.promote_signing_key(&kid).await {
+        Ok(key) => HttpResponse::Ok().json(key),
+        Err(e) => e.error_response(),
+    }
+}
+
+pub async fn retire_signing_key(
+    app_service: web::Data<Arc<AppService>>,
+    req: HttpRequest,
+    kid: web::Path<String>,
+) -> impl Responder {
+    if let Err(e) = app_service.authorize_admin(bearer_token(&req)) {
+        return e.error_response();
+    }
+    match app_service.retire_signing_key(&kid).await {
+        Ok(key) => HttpResponse::Ok().json(key),
+        Err(e) => e.error_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RegisterClientRequest {
+    name: String,
+    redirect_uris: Vec<String>,
+    #[serde(default = "default_client_scopes")]
+    allowed_scopes: Vec<String>,
+    /// Public clients (single-page and native apps) get no secret and must use PKCE.
+    #[serde(default)]
+    public: bool,
+    /// Only trusted clients can sign users in through `/oauth/authorize`, which does not ask
+    /// for consent.
+    #[serde(default)]
+    trusted: bool,
+}
+
+fn default_client_scopes() -> Vec<String> {
+    ["openid", "profile", "email"].map(String::from).to_vec()
+}
+
+#[derive(Serialize)]
+struct RegisteredClientResponse {
+    #[serde(flatten)]
+    client: RegisteredClient,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_secret: Option<String>,
+}
+
+pub async fn list_clients(
+    app_service: web::Data<Arc<AppService>>,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Err(e) = app_service.authorize_admin(bearer_token(&req)) {
+        return e.error_response();
+    }
+    match app_service.list_clients().await {
+        Ok(clients) => HttpResponse::Ok().json(clients),
+        Err(e) => e.error_response(),
+    }
+}
+
+pub async fn register_client(
+    app_service: web::Data<Arc<AppService>>,
+    req: HttpRequest,
+    body: web::Json<RegisterClientRequest>,
+) -> impl Responder {
+    if let Err(e) = app_service.authorize_admin(bearer_token(&req)) {
+        return e.error_response();
+    }
+    let body = body.into_inner();
+    match app_service
+        .register_client(
+            body.name,
+            body.redirect_uris,
+            body.allowed_scopes,
+            !body.public,
+            body.trusted,
+        )
+        .await
+    {
+        Ok((client, client_secret)) => HttpResponse::Created().json(RegisteredClientResponse {
+            client,
+            client_secret,
+        }),
+        Err(e) => e.error_response(),
+    }
+}
+
+pub async fn delete_client(
+    app_service: web::Data<Arc<AppService>>,
+    req: HttpRequest,
+    client_id: web::Path<String>,
+) -> impl Responder {
+    if let Err(e) = app_service.authorize_admin(bearer_token(&req)) {
+        return e.error_response();
+    }
+    match app_service.delete_client(&client_id).await {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(e) => e.error_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PasswordRegisterRequest {
+    email: String,
+    password: String,
+    name: Option<String>,
+}
+
+/// Registers an email and password account. Only the password policy is checked up front; the
+/// answer is `202 Accepted` whether or not the email is known, and the outcome arrives by
+/// email (a verification link for a new account, a sign-in hint for an existing one).
+pub async fn password_register(
+    app_service: web::Data<Arc<AppService>>,
+    body: web::Json<PasswordRegisterRequest>,
+) -> impl Responder {
+    match app_service.register_password(&body.email, &body.password, body.name.as_deref()) {
+        Ok(()) => HttpResponse::Accepted().finish(),
+        Err(e) => e.error_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PasswordLoginRequest {
+    email: String,
+    password: String,
+}
+
+pub async fn password_login(
+    app_service: web::Data<Arc<AppService>>,
+    body: web::Json<PasswordLoginRequest>,
+    req: HttpRequest,
+) -> impl Responder {
+    match app_service
+        .login_password(&body.email, &body.password, &client_info(&req))
+        .await
+    {
+        Ok(tokens) => HttpResponse::Ok().json(tokens),
+        Err(e) => e.error_response(),
+    }
+}
+
+/// Emails the signed-in user a new verification link.
+pub async fn request_email_verification(
+    app_service: web::Data<Arc<AppService>>,
+    user: FirstPartyUser,
+) -> impl Responder {
+    match app_service.request_email_verification(&user).await {
+        Ok(()) => HttpResponse::Accepted().finish(),
+        Err(e) => e.error_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AccountTokenRequest {
+    token: String,
+}
+
+pub async fn confirm_email_verification(
+    app_service: web::Data<Arc<AppService>>,
+    body: web::Json<AccountTokenRequest>,
+) -> impl Responder {
+    match app_service.confirm_email_verification(&body.token).await {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(e) => e.error_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PasswordResetRequest {
+    email: String,
+}
+
+/// Answers `202` whether or not the email has an account, so it cannot be used to find them.
+pub async fn request_password_reset(
+    app_service: web::Data<Arc<AppService>>,
+    body: web::Json<PasswordResetRequest>,
+) -> impl Responder {
+    app_service.request_password_reset(&body.email);
+    HttpResponse::Accepted().finish()
+}
+
+#[derive(Deserialize)]
+pub struct PasswordResetConfirmation {
+    token: String,
+    password: String,
+}
+
+pub async fn confirm_password_reset(
+    app_service: web::Data<Arc<AppService>>,
+    body: web::Json<PasswordResetConfirmation>,
+) -> impl Responder {
+    match app_service
+        .reset_password(&body.token, &body.password)
+        .await
+    {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(e) => e.error_response(),
+    }
+}
+
+/// Sends the signed-in user to a provider to link another account. From a cookie session the
+/// request must carry the CSRF token as `?csrf=`: linking is a state-changing `GET`, and
+/// another site could otherwise link an account it controls to the user. Like a login, the
+/// link is bound to the browser by the state cookie, so the provider account is only attached
+/// when the callback returns to the browser that asked for it.
+pub async fn link_identity(
+    app_service: web::Data<Arc<AppService>>,
+    cookie_settings: web::Data<CookieSettings>,
+    provider_name: web::Path<String>,
+    query: web::Query<HashMap<String, String>>,
+    user: FirstPartyUser,
+    req: HttpRequest,
+) -> impl Responder {
+    if req.headers().get(AUTHORIZATION).is_none() {
+        let csrf_cookie = req.cookie(&cookie_settings.csrf_cookie);
+        let valid = match (csrf_cookie, query.get("csrf")) {
+            (Some(cookie), Some(given)) => {
+                bool::from(cookie.value().as_bytes().ct_eq(given.as_bytes()))
+            }
+            _ => false,
+        };
+        if !valid {
+            return AppError::from(AuthError::Forbidden("missing or invalid CSRF token".into()))
+                .error_response();
         }
-    } else {
-        HttpResponse::BadRequest().body("Missing authorization code.")
+    }
+
+    match app_service
+        .initiate_link(
+            &provider_name,
+            &user,
+            query.get("return_to").map(String::as_str),
+        )
+        .await
+    {
+        Ok(redirect) => HttpResponse::Found()
+            .append_header((LOCATION, redirect.url))
+            .cookie(cookie_settings.state_cookie(&redirect.state))
+            .finish(),
+        Err(e) => e.error_response(),
     }
 }
 
-fn get_provider_id(provider_name: &str) -> i32 {
-    match provider_name {
-        "google" => 1,
-        _ => 0,
+pub async fn list_identities(
+    app_service: web::Data<Arc<AppService>>,
+    user: FirstPartyUser,
+) -> impl Responder {
+    match app_service.list_identities(&user).await {
+        Ok(identities) => HttpResponse::Ok().json(identities),
+        Err(e) => e.error_response(),
+    }
+}
+
+pub async fn unlink_identity(
+    app_service: web::Data<Arc<AppService>>,
+    identity_id: web::Path<i32>,
+    user: FirstPartyUser,
+) -> impl Responder {
+    match app_service.unlink_identity(&user, *identity_id).await {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(e) => e.error_response(),
+    }
+}
+
+pub async fn logout(
+    app_service: web::Data<Arc<AppService>>,
+    cookie_settings: web::Data<CookieSettings>,
+    user: FirstPartyUser,
+) -> impl Responder {
+    match app_service.logout(&user).await {
+        Ok(()) => signed_out(&cookie_settings),
+        Err(e) => e.error_response(),
     }
 }
+
+pub async fn logout_all(
+    app_service: web::Data<Arc<AppService>>,
+    cookie_settings: web::Data<CookieSettings>,
+    user: FirstPartyUser,
+) -> impl Responder {
+    match app_service.logout_all(&user).await {
+        Ok(()) => signed_out(&cookie_settings),
+        Err(e) => e.error_response(),
+    }
+}
+
+/// Clears the session cookies in case the session was a browser one.
+fn signed_out(cookie_settings: &CookieSettings) -> HttpResponse {
+    let mut response = HttpResponse::NoContent();
+    for cookie in cookie_settings.removal_cookies() {
+        response.cookie(cookie);
+    }
+    response.finish()
+}
+
+/// The device a login comes from. Behind a proxy the address is taken from `Forwarded` or
+/// `X-Forwarded-For`, so it is informational only.
+fn client_info(req: &HttpRequest) -> ClientInfo {
+    ClientInfo {
+        user_agent: req
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|agent| agent.to_str().ok())
+            .map(String::from),
+        ip_address: req.connection_info().realip_remote_addr().map(|addr| {
+            addr.parse::<SocketAddr>()
+                .map(|addr| addr.ip().to_string())
+                .unwrap_or_else(|_| addr.to_string())
+        }),
+    }
+}
+
+/// The client id and secret, from HTTP Basic or else the form fields. An empty secret is no
+/// secret.
+fn client_credentials(
+    req: &HttpRequest,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+) -> (Option<String>, Option<String>) {
+    let (client_id, client_secret) = match basic_credentials(req) {
+        Some((client_id, client_secret)) => (Some(client_id), Some(client_secret)),
+        None => (client_id, client_secret),
+    };
+    (client_id, client_secret.filter(|secret| !secret.is_empty()))
+}
+
+/// Client credentials sent as `Authorization: Basic`, form-encoded as RFC 6749 requires.
+fn basic_credentials(req: &HttpRequest) -> Option<(String, String)> {
+    let encoded = req
+        .headers()
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(STANDARD.decode(encoded).ok()?).ok()?;
+    let (client_id, client_secret) = decoded.split_once(':')?;
+    let decode = |value: &str| {
+        form_urlencoded::parse(format!("v={}", value).as_bytes())
+            .next()
+            .map(|(_, value)| value.into_owned())
+    };
+    Some((decode(client_id)?, decode(client_secret)?))
+}
+
+fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
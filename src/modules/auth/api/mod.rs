@@ -1,4 +1,9 @@
 mod handler;
 
+mod cookies;
+pub use cookies::*;
+
+pub use kuri_auth::{AuthenticatedUser, AuthenticatedUserConfig, FirstPartyUser};
+
 mod routes_config;
 pub use routes_config::*;
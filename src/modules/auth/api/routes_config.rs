@@ -1,11 +1,74 @@
 use actix_web::web;
 
-use super::handler::{login, oauth_callback};
+use super::handler::{
+    authorize, confirm_email_verification, confirm_password_reset, delete_client,
+    generate_signing_key, introspect, jwks, link_identity, list_clients, list_identities,
+    list_signing_keys, login, logout, logout_all, oauth_callback, oauth_token,
+    openid_configuration, password_login, password_register, promote_signing_key, refresh_token,
+    register_client, request_email_verification, request_password_reset, retire_signing_key,
+    revoke, token, unlink_identity, userinfo,
+};
 
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/auth")
+            .route("/token", web::post().to(token))
+            .route("/token/refresh", web::post().to(refresh_token))
+            .route("/logout", web::post().to(logout))
+            .route("/logout-all", web::post().to(logout_all))
+            // Before the provider routes, which would otherwise take `password` as a provider.
+            .route("/password/register", web::post().to(password_register))
+            .route("/password/login", web::post().to(password_login))
+            .route(
+                "/password/verify/request",
+                web::post().to(request_email_verification),
+            )
+            .route(
+                "/password/verify/confirm",
+                web::post().to(confirm_email_verification),
+            )
+            .route(
+                "/password/reset/request",
+                web::post().to(request_password_reset),
+            )
+            .route(
+                "/password/reset/confirm",
+                web::post().to(confirm_password_reset),
+            )
             .route("/{provider_name}/login", web::get().to(login))
             .route("/{provider_name}/callback", web::get().to(oauth_callback)),
+    )
+    .service(
+        web::scope("/oauth")
+            .route("/authorize", web::get().to(authorize))
+            .route("/token", web::post().to(oauth_token))
+            .route("/introspect", web::post().to(introspect))
+            .route("/revoke", web::post().to(revoke))
+            .route("/userinfo", web::get().to(userinfo))
+            .route("/userinfo", web::post().to(userinfo)),
+    )
+    .service(
+        web::scope("/me/identities")
+            .route("", web::get().to(list_identities))
+            .route("/{provider_name}/link", web::get().to(link_identity))
+            .route("/{identity_id}", web::delete().to(unlink_identity)),
+    )
+    .service(
+        web::scope("/admin/keys")
+            .route("", web::get().to(list_signing_keys))
+            .route("", web::post().to(generate_signing_key))
+            .route("/{kid}/promote", web::post().to(promote_signing_key))
+            .route("/{kid}/retire", web::post().to(retire_signing_key)),
+    )
+    .service(
+        web::scope("/admin/clients")
+            .route("", web::get().to(list_clients))
+            .route("", web::post().to(register_client))
+            .route("/{client_id}", web::delete().to(delete_client)),
+    )
+    .route("/.well-known/jwks.json", web::get().to(jwks))
+    .route(
+        "/.well-known/openid-configuration",
+        web::get().to(openid_configuration),
     );
 }
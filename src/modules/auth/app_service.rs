@@ -1,65 +1,308 @@
 use std::{collections::HashMap, sync::Arc};
 
-use oauth2::TokenResponse;
+use chrono::Utc;
+use jsonwebtoken::{jwk::JwkSet, Algorithm};
+use oauth2::{
+    url::{form_urlencoded, Url},
+    PkceCodeVerifier, TokenResponse,
+};
+use serde_json::{json, Value};
+use subtle::ConstantTimeEq;
 
 use crate::{
     error::AppError,
-    modules::user::{self, UserBuilder},
+    modules::{
+        session::{self, ClientInfo, SessionBuilder},
+        user::{self, User, UserBuilder, UserError},
+    },
+    utils::token::{generate_token, hash_token},
 };
 
 use super::{
-    ports::{Provider, Repository},
-    AuthError, JwtManager, OAuthAuthorizationBuilder,
+    ports::{Mailer, Provider, Repository},
+    AccountToken, AccountTokenPurpose, AuthError, AuthSettings, AuthorizationCode,
+    AuthorizationRedirect, AuthorizeOutcome, AuthorizeRequest, Claims, ClientSettings, Email,
+    Grant, IdTokenClaims, Identity, JwtManager, Keyring, LoginOutcome, OAuthAuthorization,
+    OAuthAuthorizationBuilder, PendingAuthorization, PendingAuthorizationBuilder, RefreshToken,
+    RegisteredClient, SigningKey, SigningKeyRecord, TokenDelivery, TokenIntrospection, TokenPair,
+    UserInfo, UserProfile,
 };
 
 pub struct AppService {
     providers: HashMap<i32, Arc<dyn Provider>>,
     repo: Arc<dyn Repository>,
+    mailer: Arc<dyn Mailer>,
     user_service: Arc<user::AppService>,
+    session_service: Arc<session::AppService>,
     jwt_manager: Arc<JwtManager>,
+    /// The key configured through the environment, used until a stored key is promoted.
+    bootstrap_key: SigningKey,
+    settings: AuthSettings,
 }
 impl AppService {
     pub fn new(
         providers: Vec<Arc<dyn Provider>>,
         repo: Arc<dyn Repository>,
+        mailer: Arc<dyn Mailer>,
         user_service: Arc<user::AppService>,
+        session_service: Arc<session::AppService>,
         jwt_manager: Arc<JwtManager>,
+        settings: AuthSettings,
     ) -> Self {
-        let mut providers_map = HashMap::new();
-        providers.iter().for_each(|provider| {
-            providers_map.insert(provider.provider_id(), provider.clone());
-        });
+        let mut providers_map: HashMap<i32, Arc<dyn Provider>> = HashMap::new();
+        for provider in providers {
+            if providers_map
+                .values()
+                .any(|other| other.name() == provider.name())
+            {
+                panic!("Two providers are named {}", provider.name());
+            }
+            if let Some(other) = providers_map.insert(provider.provider_id(), provider.clone()) {
+                panic!(
+                    "Providers {} and {} have the same provider id {}",
+                    other.name(),
+                    provider.name(),
+                    provider.provider_id()
+                );
+            }
+        }
 
         Self {
             providers: providers_map,
             repo,
+            mailer,
             user_service,
+            session_service,
+            bootstrap_key: jwt_manager.current_key(),
             jwt_manager,
+            settings,
         }
     }
 }
 
 impl AppService {
+    /// Records every configured provider in `oauth_providers`, which authorizations reference.
+    pub async fn register_providers(&self) -> Result<(), AppError> {
+        for provider in self.providers.values() {
+            self.repo
+                .register_provider(provider.provider_id(), provider.name())
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// The public keys that verify the JWTs this service issues.
+    pub fn jwks(&self) -> JwkSet {
+        self.jwt_manager.jwks()
+    }
+
+    fn provider(&self, provider_name: &str) -> Result<&Arc<dyn Provider>, AppError> {
+        self.providers
+            .values()
+            .find(|provider| provider.name() == provider_name)
+            .ok_or_else(|| AuthError::ProviderNotFound(provider_name.to_string()).into())
+    }
+
+    fn client(&self, client_id: &str) -> Result<&ClientSettings, AppError> {
+        self.settings
+            .clients
+            .iter()
+            .find(|client| client.client_id == client_id)
+            .ok_or_else(|| AuthError::InvalidClient(client_id.to_string()).into())
+    }
+
+    /// Checks that `return_to` points at an allowed origin, so the login cannot be used to send
+    /// users (and their tokens) to an arbitrary site.
+    fn validate_return_to(
+        &self,
+        client: Option<&ClientSettings>,
+        return_to: &str,
+    ) -> Result<(), AppError> {
+        let origin = |url: &str| Url::parse(url).ok().map(|url| url.origin());
+        let target = origin(return_to)
+            .filter(|origin| origin.is_tuple())
+            .ok_or_else(|| AuthError::InvalidReturnTo(return_to.to_string()))?;
+
+        let allowed = self
+            .settings
+            .return_to_origins
+            .iter()
+            .chain(client.iter().flat_map(|client| &client.redirect_urls))
+            .chain(std::iter::once(&self.settings.base_url))
+            .any(|allowed| origin(allowed).as_ref() == Some(&target));
+        if !allowed {
+            return Err(AuthError::InvalidReturnTo(return_to.to_string()).into());
+        }
+        Ok(())
+    }
+
+    /// Whether `url` points at this service itself.
+    fn is_own_url(&self, url: &str) -> bool {
+        match (Url::parse(url), Url::parse(&self.settings.base_url)) {
+            (Ok(url), Ok(base_url)) => url.origin() == base_url.origin(),
+            _ => false,
+        }
+    }
+
     /// Initiates the OAuth process by generating the URL to redirect the user for authentication.
-    pub async fn initiate_oauth(&self, provider_id: i32) -> Result<String, AppError> {
-        let provider = self.providers.get(&provider_id).ok_or(AppError::AuthError(
-            AuthError::ProviderNotFound(provider_id),
-        ))?;
-        let (auth_url, _) = provider.get_authorization_url().await;
-        Ok(auth_url)
+    /// Logins started without a client get their tokens as JSON, or in the fragment of the
+    /// `return_to` redirect. Public clients receiving a code must send a PKCE `S256` challenge.
+    pub async fn initiate_oauth(
+        &self,
+        provider_name: &str,
+        client_id: Option<&str>,
+        return_to: Option<&str>,
+        code_challenge: Option<(&str, Option<&str>)>,
+    ) -> Result<AuthorizationRedirect, AppError> {
+        let provider = self.provider(provider_name)?;
+        let client = client_id
+            .map(|client_id| self.client(client_id))
+            .transpose()?;
+        if let Some(return_to) = return_to {
+            self.validate_return_to(client, return_to)?;
+        }
+        match (client, code_challenge) {
+            // An absent method means `plain`, which would leak the verifier in the redirect.
+            (_, Some((_, method))) if method != Some("S256") => {
+                return Err(AuthError::InvalidGrant(
+                    "only the S256 code challenge method is supported".into(),
+                )
+                .into());
+            }
+            (Some(client), None)
+                if client.delivery == TokenDelivery::Code && !client.is_confidential() =>
+            {
+                return Err(AuthError::InvalidGrant(format!(
+                    "public client {} must send a code challenge",
+                    client.client_id
+                ))
+                .into());
+            }
+            _ => {}
+        }
+        let mut pending_builder = PendingAuthorizationBuilder::new();
+        if let Some(client_id) = client_id {
+            pending_builder = pending_builder.client_id(client_id);
+        }
+        if let Some(return_to) = return_to {
+            pending_builder = pending_builder.return_to(return_to);
+        }
+        if let Some((code_challenge, _)) = code_challenge {
+            pending_builder = pending_builder.code_challenge(code_challenge);
+        }
+        self.start_authorization(provider, pending_builder).await
+    }
+
+    /// Starts linking another provider account to the signed-in user. The callback attaches
+    /// the account to them instead of logging in.
+    pub async fn initiate_link(
+        &self,
+        provider_name: &str,
+        claims: &Claims,
+        return_to: Option<&str>,
+    ) -> Result<AuthorizationRedirect, AppError> {
+        if claims.client_id.is_some() {
+            return Err(AuthError::Forbidden(
+                "tokens granted to clients cannot link identities".into(),
+            )
+            .into());
+        }
+        let provider = self.provider(provider_name)?;
+        let mut pending_builder = PendingAuthorizationBuilder::new().link_user_id(claims.sub);
+        if let Some(return_to) = return_to {
+            self.validate_return_to(None, return_to)?;
+            pending_builder = pending_builder.return_to(return_to);
+        }
+        self.start_authorization(provider, pending_builder).await
+    }
+
+    /// Records the pending authorization and returns the provider URL to send the user to.
+    async fn start_authorization(
+        &self,
+        provider: &Arc<dyn Provider>,
+        pending_builder: PendingAuthorizationBuilder,
+    ) -> Result<AuthorizationRedirect, AppError> {
+        let authorization = provider.get_authorization_url().await;
+
+        let expired_before =
+            chrono::Utc::now() - chrono::Duration::seconds(PendingAuthorization::TTL_SECONDS);
+        self.repo
+            .delete_pending_authorizations_before(expired_before)
+            .await?;
+
+        let pending = {
+            let mut pending_builder = pending_builder
+                .state(authorization.csrf_token.secret())
+                .provider_id(provider.provider_id())
+                .pkce_verifier(authorization.pkce_verifier.secret())
+                .created_at(chrono::Utc::now());
+            if let Some(nonce) = authorization.nonce {
+                pending_builder = pending_builder.nonce(nonce);
+            }
+            pending_builder.build()
+        };
+        self.repo.insert_pending_authorization(&pending).await?;
+
+        Ok(AuthorizationRedirect {
+            url: authorization.url,
+            state: pending.state,
+        })
     }
 
+    /// Consumes the pending authorization matching `state`, rejecting unknown, reused,
+    /// expired or cross-provider states.
+    async fn consume_state(
+        &self,
+        state: &str,
+        provider_id: i32,
+    ) -> Result<PendingAuthorization, AppError> {
+        let pending = self
+            .repo
+            .take_pending_authorization(state)
+            .await?
+            .ok_or_else(|| AuthError::InvalidState("unknown or already used state".into()))?;
+
+        if pending.provider_id != provider_id {
+            return Err(AuthError::InvalidState(
+                "state was issued for a different provider".into(),
+            )
+            .into());
+        }
+
+        if pending.is_expired() {
+            return Err(AuthError::InvalidState("state has expired".into()).into());
+        }
+
+        Ok(pending)
+    }
+
+    /// Completes a login at the provider's callback. `state_cookie` is the state cookie the
+    /// browser sent along, which must match the state of the login it started.
     pub async fn oauth_login(
         &self,
         auth_code: String,
-        provider_id: i32,
-    ) -> Result<String, AppError> {
+        state: String,
+        state_cookie: Option<&str>,
+        provider_name: &str,
+        client: &ClientInfo,
+    ) -> Result<LoginOutcome, AppError> {
         log::debug!("Received auth code: {}", auth_code);
-        let provider = self.providers.get(&provider_id).ok_or(AppError::AuthError(
-            AuthError::ProviderNotFound(provider_id),
-        ))?;
+        let provider = self.provider(provider_name)?;
+        let pending = self.consume_state(&state, provider.provider_id()).await?;
+        if !pending.is_bound_to(state_cookie) {
+            return Err(AuthError::InvalidState(
+                "the login was not started in this browser".into(),
+            )
+            .into());
+        }
+        let pkce_verifier = pending
+            .pkce_verifier
+            .clone()
+            .map(PkceCodeVerifier::new)
+            .ok_or_else(|| AuthError::InvalidState("missing PKCE verifier".into()))?;
+
         let token_response = provider
-            .exchange_token(auth_code)
+            .exchange_token(auth_code, pkce_verifier)
             .await
             .map_err(|e| AppError::AuthError(AuthError::TokenExchangeError(e.to_string())))?;
 
@@ -67,53 +310,42 @@ impl AppService {
 
         log::debug!("Access token: {}", access_token);
 
+        // Not every provider issues refresh tokens (GitHub OAuth apps don't).
         let refresh_token = token_response
             .refresh_token()
-            .map(|token| token.secret().to_string())
-            .ok_or_else(|| AuthError::InvalidTokenError("No refresh token received".to_string()))?;
-        let user_info = provider
-            .fetch_user_info(access_token.clone())
-            .await
-            .map_err(|e| AppError::NetworkError(e.to_string()))?;
-
-        let provider_user_id =
-            user_info
-                .get("sub")
-                .and_then(|val| val.as_str())
-                .ok_or(AppError::AuthError(AuthError::AuthenticationFailed(
-                    "User ID not found".into(),
-                )))?;
+            .map(|token| token.secret().to_string());
+        let profile = provider
+            .fetch_user_profile(&token_response, pending.nonce.as_deref())
+            .await?;
 
-        let user = {
-            let mut user_builder = UserBuilder::new();
-            if let Some(name) = user_info.get("given_name").and_then(|val| val.as_str()) {
-                user_builder = user_builder.name(name);
+        let existing = self
+            .repo
+            .find_oauth_by_subject(provider.provider_id(), &profile.provider_user_id)
+            .await?;
+        let user_id = match (pending.link_user_id, &existing) {
+            (Some(link_user_id), Some(identity)) if identity.user_id != link_user_id => {
+                return Err(AuthError::IdentityAlreadyLinked(provider.name().to_string()).into());
             }
-
-            if let Some(email) = user_info.get("email").and_then(|val| val.as_str()) {
-                user_builder = user_builder.email(email);
-            }
-
-            if let Some(picture) = user_info.get("picture").and_then(|val| val.as_str()) {
-                user_builder = user_builder.avatar_url(picture);
+            (Some(link_user_id), _) => link_user_id,
+            (None, Some(identity)) => identity.user_id,
+            (None, None) => {
+                self.user_for_new_identity(provider.name(), &profile)
+                    .await?
             }
-            user_builder.build()
         };
-
-        log::debug!("User info: {:?}", user);
-
-        let user = self.user_service.upsert_user(&user).await?;
-
         let auth_data = {
             let mut auth_builder = OAuthAuthorizationBuilder::new()
-                .user_id(user.user_id)
+                .user_id(user_id)
                 .provider_id(provider.provider_id())
-                .provider_user_id(provider_user_id)
+                .provider_user_id(&profile.provider_user_id)
                 .access_token(access_token)
-                .refresh_token(refresh_token)
                 .created_at(chrono::Utc::now())
                 .updated_at(chrono::Utc::now());
 
+            if let Some(refresh_token) = refresh_token {
+                auth_builder = auth_builder.refresh_token(refresh_token);
+            }
+
             if let Some(expires_in) = token_response.expires_in() {
                 auth_builder = auth_builder.expires_in(chrono::Utc::now() + expires_in);
             }
@@ -130,11 +362,1334 @@ impl AppService {
             auth_builder.build()
         };
 
-        let _ = self.repo.upsert_oauth(&auth_data).await?;
-        log::debug!("OAuth data upserted: {:?}", auth_data);
+        let stored = self.repo.upsert_oauth(&auth_data).await?;
+        log::debug!("OAuth data upserted: {:?}", stored);
+
+        if pending.link_user_id.is_some() {
+            log::info!("Linked {} account to user {}", provider.name(), user_id);
+            return Ok(LoginOutcome::Linked {
+                identity: self.identity(stored),
+                redirect_url: pending.return_to,
+            });
+        }
+
+        let session = SessionBuilder::new()
+            .user_id(auth_data.user_id)
+            .provider_id(provider.provider_id())
+            .refresh_family_id(generate_token(16))
+            .client(client)
+            .build();
+        let session = self.session_service.start_session(&session).await?;
+
+        let client = pending
+            .client_id
+            .as_deref()
+            .map(|client_id| self.client(client_id))
+            .transpose()?;
+        let delivery = match client {
+            Some(client) => client.delivery,
+            // Logins started by `/oauth/authorize` return there with a browser session.
+            None if pending
+                .return_to
+                .as_deref()
+                .is_some_and(|return_to| self.is_own_url(return_to)) =>
+            {
+                TokenDelivery::Cookie
+            }
+            None => TokenDelivery::Json,
+        };
+        // `return_to` was validated when the login started.
+        let redirect_url = pending
+            .return_to
+            .clone()
+            .or_else(|| client.and_then(|client| client.redirect_urls.first().cloned()));
+
+        let Some(redirect_url) = redirect_url else {
+            if delivery != TokenDelivery::Json {
+                return Err(AuthError::InvalidClient(format!(
+                    "{} has no redirect URL",
+                    pending.client_id.unwrap_or_default()
+                ))
+                .into());
+            }
+            let tokens = self
+                .issue_tokens(
+                    auth_data.user_id,
+                    session.refresh_family_id,
+                    Grant::default(),
+                )
+                .await?;
+            return Ok(LoginOutcome::Json(tokens));
+        };
+
+        if let (TokenDelivery::Code, Some(client)) = (delivery, client) {
+            let (authorization_code, code) = AuthorizationCode::issue(
+                client.client_id.clone(),
+                auth_data.user_id,
+                session.refresh_family_id,
+                pending.code_challenge,
+                redirect_url.clone(),
+                None,
+                None,
+            );
+            self.repo
+                .delete_authorization_codes_before(Utc::now())
+                .await?;
+            self.repo
+                .insert_authorization_code(&authorization_code)
+                .await?;
+            return Ok(LoginOutcome::Code { code, redirect_url });
+        }
+
+        let tokens = self
+            .issue_tokens(
+                auth_data.user_id,
+                session.refresh_family_id,
+                Grant::default(),
+            )
+            .await?;
+        if delivery == TokenDelivery::Cookie {
+            Ok(LoginOutcome::Cookie {
+                tokens,
+                redirect_url,
+            })
+        } else {
+            Ok(LoginOutcome::Fragment {
+                tokens,
+                redirect_url,
+            })
+        }
+    }
+
+    /// The user a provider account signs in as the first time it is seen. It joins the user
+    /// with the same email only as far as the email linking policy allows, and never changes
+    /// that user's profile.
+    async fn user_for_new_identity(
+        &self,
+        provider_name: &str,
+        profile: &UserProfile,
+    ) -> Result<i32, AppError> {
+        let mut user_builder = UserBuilder::new();
+        if let Some(name) = profile
+            .given_name
+            .as_ref()
+            .or(profile.display_name.as_ref())
+        {
+            user_builder = user_builder.name(name);
+        }
+        if let Some(avatar_url) = &profile.avatar_url {
+            user_builder = user_builder.avatar_url(avatar_url);
+        }
+
+        if let Some(email) = &profile.email {
+            if let Some(existing) = self.user_service.find_user_by_email(email).await? {
+                if !self.settings.email_linking.allows(profile, &existing) {
+                    log::warn!(
+                        "Refusing to link {} account to user {} by an unverified email",
+                        provider_name,
+                        existing.user_id
+                    );
+                    return Err(AuthError::LinkRequired(provider_name.to_string()).into());
+                }
+                log::info!(
+                    "Linking {} account to user {} by verified email",
+                    provider_name,
+                    existing.user_id
+                );
+                return Ok(existing.user_id);
+            }
+            // Unverified addresses are not kept, so a user's email is always one they own.
+            if profile.email_verified {
+                user_builder = user_builder.email(email).email_verified(true);
+            }
+        }
+
+        let user = user_builder.build();
+        log::debug!("User info: {:?}", user);
+        Ok(self.user_service.create_user(&user).await?.user_id)
+    }
+
+    /// Exchanges an authorization code for a token pair. Confidential clients prove
+    /// themselves with their secret, public ones with the PKCE verifier of the login.
+    pub async fn exchange_authorization_code(
+        &self,
+        code: &str,
+        client_id: &str,
+        client_secret: Option<&str>,
+        code_verifier: Option<&str>,
+    ) -> Result<TokenPair, AppError> {
+        let client = self.client(client_id)?;
+        if !client.authenticate(client_secret) {
+            return Err(AuthError::InvalidClient(client_id.to_string()).into());
+        }
+
+        let stored = self
+            .redeem_authorization_code(code, client_id, client.is_confidential(), code_verifier)
+            .await?;
+        self.issue_tokens(stored.user_id, stored.refresh_family_id, Grant::default())
+            .await
+    }
+
+    /// Marks a code as used and checks it against the client redeeming it.
+    async fn redeem_authorization_code(
+        &self,
+        code: &str,
+        client_id: &str,
+        confidential: bool,
+        code_verifier: Option<&str>,
+    ) -> Result<AuthorizationCode, AppError> {
+        let code_hash = hash_token(code);
+        let Some(stored) = self.repo.redeem_authorization_code(&code_hash).await? else {
+            if let Some(stored) = self.repo.find_authorization_code(&code_hash).await? {
+                // A code presented twice was intercepted: end the session it started.
+                log::warn!(
+                    "Authorization code reuse detected for user {}, revoking family {}",
+                    stored.user_id,
+                    stored.refresh_family_id
+                );
+                self.repo
+                    .revoke_refresh_token_family(&stored.refresh_family_id)
+                    .await?;
+                self.session_service
+                    .end_session(&stored.refresh_family_id)
+                    .await?;
+                self.jwt_manager
+                    .revoke_session(&stored.refresh_family_id)
+                    .await?;
+                return Err(AuthError::InvalidGrant("code has already been used".into()).into());
+            }
+            return Err(AuthError::InvalidGrant("unknown code".into()).into());
+        };
+
+        stored.check(client_id, confidential, code_verifier)?;
+        Ok(stored)
+    }
+
+    /// Lifetime of refresh tokens, e.g. for the cookie that carries them.
+    pub fn refresh_token_ttl(&self) -> chrono::Duration {
+        self.settings.refresh_token_ttl
+    }
+
+    /// Exchanges a refresh token for a new token pair, rotating the refresh token. Tokens
+    /// granted to an OAuth client can only be refreshed by that client.
+    pub async fn refresh_tokens(
+        &self,
+        refresh_token: &str,
+        client_id: Option<&str>,
+    ) -> Result<TokenPair, AppError> {
+        let stored = self
+            .repo
+            .find_refresh_token(&hash_token(refresh_token))
+            .await?
+            .ok_or(AuthError::InvalidRefreshToken)?;
+        if stored.client_id.as_deref() != client_id {
+            return Err(AuthError::InvalidRefreshToken.into());
+        }
+
+        if stored.used_at.is_some() {
+            return Err(self.reject_reused_refresh_token(&stored).await);
+        }
+        if stored.revoked_at.is_some() || stored.is_expired() {
+            return Err(AuthError::InvalidRefreshToken.into());
+        }
+        // Another request may have rotated the token since we read it.
+        if !self.repo.mark_refresh_token_used(stored.token_id).await? {
+            return Err(self.reject_reused_refresh_token(&stored).await);
+        }
+        if !self
+            .session_service
+            .touch_session(&stored.family_id)
+            .await?
+        {
+            self.repo
+                .revoke_refresh_token_family(&stored.family_id)
+                .await?;
+            return Err(AuthError::InvalidRefreshToken.into());
+        }
+
+        let grant = Grant {
+            client_id: stored.client_id,
+            scope: stored.scope,
+        };
+        self.issue_tokens(stored.user_id, stored.family_id, grant)
+            .await
+    }
+
+    /// A rotated token being presented again means it leaked: end the whole family.
+    async fn reject_reused_refresh_token(&self, stored: &RefreshToken) -> AppError {
+        log::warn!(
+            "Refresh token reuse detected for user {}, revoking family {}",
+            stored.user_id,
+            stored.family_id
+        );
+        match self
+            .repo
+            .revoke_refresh_token_family(&stored.family_id)
+            .await
+        {
+            Ok(_) => AuthError::RefreshTokenReused.into(),
+            Err(e) => e.into(),
+        }
+    }
+
+    /// Ends the session the access token belongs to: the token itself, every other access
+    /// token already issued for the session, and its refresh tokens.
+    pub async fn logout(&self, claims: &Claims) -> Result<(), AppError> {
+        self.jwt_manager.revoke(claims).await?;
+        if let Some(session_id) = &claims.sid {
+            self.jwt_manager.revoke_session(session_id).await?;
+            self.repo.revoke_refresh_token_family(session_id).await?;
+            self.session_service.end_session(session_id).await?;
+        }
+        Ok(())
+    }
+
+    /// Ends every session of the access token's user.
+    pub async fn logout_all(&self, claims: &Claims) -> Result<(), AppError> {
+        self.end_all_sessions(claims.sub).await
+    }
+
+    async fn end_all_sessions(&self, user_id: i32) -> Result<(), AppError> {
+        self.jwt_manager.revoke_all(user_id).await?;
+        self.repo.revoke_user_refresh_tokens(user_id).await?;
+        self.session_service.end_all_sessions(user_id).await?;
+        Ok(())
+    }
+
+    /// Drops revocations of tokens that have expired since; they can no longer verify anyway.
+    pub async fn prune_revocations(&self) -> Result<u64, AppError> {
+        Ok(self.jwt_manager.prune_revocations().await?)
+    }
+
+    async fn issue_tokens(
+        &self,
+        user_id: i32,
+        family_id: String,
+        grant: Grant,
+    ) -> Result<TokenPair, AppError> {
+        let mut claims = self.jwt_manager.claims(user_id, &family_id);
+        claims.client_id = grant.client_id.clone();
+        claims.scope = grant.scope.clone();
+        let access_token = self.jwt_manager.sign(&claims)?;
+        let scope = grant.scope.clone();
+        let (stored, refresh_token) =
+            RefreshToken::issue(user_id, family_id, grant, self.settings.refresh_token_ttl);
+        self.repo.insert_refresh_token(&stored).await?;
+
+        Ok(TokenPair {
+            access_token,
+            token_type: "Bearer".to_string(),
+            expires_in: self.jwt_manager.ttl_seconds(),
+            refresh_token,
+            scope,
+            id_token: None,
+        })
+    }
+}
+
+/// The provider accounts each user can sign in with.
+impl AppService {
+    pub async fn list_identities(&self, claims: &Claims) -> Result<Vec<Identity>, AppError> {
+        Ok(self
+            .repo
+            .list_oauth_authorizations(claims.sub)
+            .await?
+            .into_iter()
+            .map(|authorization| self.identity(authorization))
+            .collect())
+    }
+
+    /// Removes one of the user's provider accounts, unless it is their last way to sign in. A
+    /// password counts as one.
+    pub async fn unlink_identity(&self, claims: &Claims, identity_id: i32) -> Result<(), AppError> {
+        let authorizations = self.repo.list_oauth_authorizations(claims.sub).await?;
+        let authorization = authorizations
+            .iter()
+            .find(|authorization| authorization.auth_id == identity_id)
+            .ok_or(AuthError::IdentityNotFound(identity_id))?;
+        if authorizations.len() == 1 && self.repo.find_credentials(claims.sub).await?.is_none() {
+            return Err(AuthError::LastIdentity.into());
+        }
+
+        if self.settings.revoke_upstream_tokens {
+            if let Some(provider) = self.providers.get(&authorization.provider_id) {
+                if let Err(e) = provider.revoke_authorization(authorization).await {
+                    log::warn!(
+                        "Failed to revoke {} tokens of user {}: {}",
+                        provider.name(),
+                        claims.sub,
+                        e
+                    );
+                }
+            }
+        }
+        if !self
+            .repo
+            .delete_oauth_authorization(claims.sub, identity_id)
+            .await?
+        {
+            return Err(AuthError::IdentityNotFound(identity_id).into());
+        }
+        log::info!("Unlinked identity {} of user {}", identity_id, claims.sub);
+        Ok(())
+    }
+
+    fn identity(&self, authorization: OAuthAuthorization) -> Identity {
+        Identity {
+            identity_id: authorization.auth_id,
+            provider: self.providers.get(&authorization.provider_id).map_or_else(
+                || authorization.provider_id.to_string(),
+                |provider| provider.name().to_string(),
+            ),
+            provider_user_id: authorization.provider_user_id,
+            created_at: authorization.created_at,
+            updated_at: authorization.updated_at,
+        }
+    }
+}
+
+/// Accounts that sign in with an email and password instead of a provider.
+impl AppService {
+    /// Registers a password account once the password passes the policy. The answer is the
+    /// same whether or not the email already has an account: the account is created, or the
+    /// owner of the existing one is told about the attempt, in the background. Either way the
+    /// user signs in once they have read their email.
+    pub fn register_password(
+        self: &Arc<Self>,
+        email: &str,
+        password: &str,
+        name: Option<&str>,
+    ) -> Result<(), AppError> {
+        let email = normalize_email(email)
+            .ok_or_else(|| UserError::InvalidUserData("invalid email address".to_string()))?;
+        self.settings.password_policy.check(password, &email)?;
+
+        let app_service = Arc::clone(self);
+        let password = password.to_string();
+        let name = name.map(String::from);
+        tokio::spawn(async move {
+            if let Err(e) = app_service
+                .create_password_account(&email, &password, name.as_deref())
+                .await
+            {
+                log::error!("Failed to handle a registration: {}", e);
+            }
+        });
+        Ok(())
+    }
+
+    async fn create_password_account(
+        &self,
+        email: &str,
+        password: &str,
+        name: Option<&str>,
+    ) -> Result<(), AppError> {
+        // Attaching a password to someone else's account would hand it over; existing users
+        // link sign-in methods from their account instead.
+        if let Some(existing) = self.user_service.find_user_by_email(email).await? {
+            log::info!(
+                "Registration attempted for the email of user {}",
+                existing.user_id
+            );
+            let message = registration_attempt_email(email, &self.settings.password_reset_url);
+            return Ok(self.mailer.send(&message).await?);
+        }
+
+        let password_hash = self.hash_password(password).await?;
+        let mut user_builder = UserBuilder::new().email(email);
+        if let Some(name) = name {
+            user_builder = user_builder.name(name);
+        }
+        let user = self.user_service.create_user(&user_builder.build()).await?;
+        self.repo
+            .insert_credentials(user.user_id, &password_hash)
+            .await?;
+        log::info!("Registered password account for user {}", user.user_id);
+        self.send_verification_email(user.user_id, email).await
+    }
+
+    /// Signs a user in with their password. Unknown emails, users without a password and
+    /// wrong passwords fail alike and take the same time.
+    pub async fn login_password(
+        &self,
+        email: &str,
+        password: &str,
+        client_info: &ClientInfo,
+    ) -> Result<TokenPair, AppError> {
+        let user = match normalize_email(email) {
+            Some(email) => self.user_service.find_user_by_email(&email).await?,
+            None => None,
+        };
+        let credentials = match user {
+            Some(user) => self.repo.find_credentials(user.user_id).await?,
+            None => None,
+        };
+
+        let hasher = self.settings.password_hasher.clone();
+        let password = password.to_string();
+        let stored_hash = credentials
+            .as_ref()
+            .map(|credentials| credentials.password_hash.clone());
+        let (verified, rehashed) = tokio::task::spawn_blocking(move || {
+            if !hasher.verify(&password, stored_hash.as_deref()) {
+                return (false, None);
+            }
+            // Hashes made before the Argon2 parameters were changed are upgraded on the way.
+            let rehashed = stored_hash
+                .filter(|hash| hasher.needs_rehash(hash))
+                .and_then(|_| hasher.hash(&password).ok());
+            (true, rehashed)
+        })
+        .await
+        .map_err(|_| AppError::Unexpected)?;
+        let Some(credentials) = credentials.filter(|_| verified) else {
+            return Err(AuthError::InvalidCredentials.into());
+        };
+        if let Some(password_hash) = rehashed {
+            self.repo
+                .update_password_hash(credentials.user_id, &password_hash)
+                .await?;
+        }
+
+        self.start_password_session(credentials.user_id, client_info)
+            .await
+    }
+
+    async fn start_password_session(
+        &self,
+        user_id: i32,
+        client_info: &ClientInfo,
+    ) -> Result<TokenPair, AppError> {
+        let session = SessionBuilder::new()
+            .user_id(user_id)
+            .refresh_family_id(generate_token(16))
+            .client(client_info)
+            .build();
+        let session = self.session_service.start_session(&session).await?;
+        self.issue_tokens(user_id, session.refresh_family_id, Grant::default())
+            .await
+    }
+
+    /// Sends the signed-in user a new email verification link, unless their email is verified.
+    pub async fn request_email_verification(&self, claims: &Claims) -> Result<(), AppError> {
+        let credentials = self
+            .repo
+            .find_credentials(claims.sub)
+            .await?
+            .ok_or_else(|| AuthError::Forbidden("account has no password".to_string()))?;
+        if credentials.email_verified_at.is_some() {
+            return Ok(());
+        }
+        let user = self.user_service.get_user(claims.sub).await?;
+        let email = user
+            .email
+            .ok_or_else(|| AuthError::Forbidden("account has no email".to_string()))?;
+        self.send_verification_email(claims.sub, &email).await
+    }
+
+    pub async fn confirm_email_verification(&self, token: &str) -> Result<(), AppError> {
+        let account_token = self
+            .repo
+            .redeem_account_token(
+                &hash_token(token),
+                AccountTokenPurpose::VerifyEmail.as_str(),
+            )
+            .await?
+            .filter(AccountToken::is_redeemable)
+            .ok_or(AuthError::InvalidAccountToken)?;
+        self.repo.mark_email_verified(account_token.user_id).await?;
+        self.user_service
+            .mark_email_verified(account_token.user_id)
+            .await?;
+        log::info!("Verified email of user {}", account_token.user_id);
+        Ok(())
+    }
+
+    /// Emails a password reset link if `email` belongs to a password account. The lookup, the
+    /// token and the email all happen in the background, so the caller answers in the same
+    /// time whether or not the account exists.
+    pub fn request_password_reset(self: &Arc<Self>, email: &str) {
+        let app_service = Arc::clone(self);
+        let email = email.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = app_service.send_password_reset(&email).await {
+                log::error!("Failed to handle a password reset request: {}", e);
+            }
+        });
+    }
+
+    async fn send_password_reset(&self, email: &str) -> Result<(), AppError> {
+        let user = match normalize_email(email) {
+            Some(email) => self.user_service.find_user_by_email(&email).await?,
+            None => None,
+        };
+        let Some((user_id, email)) = user.and_then(|user| Some((user.user_id, user.email?))) else {
+            return Ok(());
+        };
+        if self.repo.find_credentials(user_id).await?.is_none() {
+            return Ok(());
+        }
+
+        let message = self
+            .account_token_email(user_id, &email, AccountTokenPurpose::ResetPassword)
+            .await?;
+        Ok(self.mailer.send(&message).await?)
+    }
+
+    /// Sets a new password with an emailed reset token. The reset also proves the user reads
+    /// the account's email, and signs out every session in case the old password leaked.
+    pub async fn reset_password(&self, token: &str, password: &str) -> Result<(), AppError> {
+        let token_hash = hash_token(token);
+        let purpose = AccountTokenPurpose::ResetPassword.as_str();
+        // Checked before the token is used up, so a rejected password can be retried.
+        let account_token = self
+            .repo
+            .find_account_token(&token_hash, purpose)
+            .await?
+            .filter(AccountToken::is_redeemable)
+            .ok_or(AuthError::InvalidAccountToken)?;
+        let user = self.user_service.get_user(account_token.user_id).await?;
+        self.settings
+            .password_policy
+            .check(password, user.email.as_deref().unwrap_or_default())?;
+        let password_hash = self.hash_password(password).await?;
+
+        if self
+            .repo
+            .redeem_account_token(&token_hash, purpose)
+            .await?
+            .is_none()
+        {
+            return Err(AuthError::InvalidAccountToken.into());
+        }
+        self.repo
+            .update_password_hash(user.user_id, &password_hash)
+            .await?;
+        self.repo.mark_email_verified(user.user_id).await?;
+        self.user_service.mark_email_verified(user.user_id).await?;
+        self.repo
+            .delete_account_tokens(user.user_id, purpose)
+            .await?;
+        self.end_all_sessions(user.user_id).await?;
+        log::info!("Reset password of user {}", user.user_id);
+        Ok(())
+    }
+
+    async fn send_verification_email(&self, user_id: i32, email: &str) -> Result<(), AppError> {
+        let message = self
+            .account_token_email(user_id, email, AccountTokenPurpose::VerifyEmail)
+            .await?;
+        Ok(self.mailer.send(&message).await?)
+    }
+
+    /// Issues a token for `purpose`, replacing the user's earlier ones, and writes the email
+    /// carrying its link.
+    async fn account_token_email(
+        &self,
+        user_id: i32,
+        email: &str,
+        purpose: AccountTokenPurpose,
+    ) -> Result<Email, AppError> {
+        let (account_token, token) = AccountToken::issue(user_id, purpose);
+        self.repo.delete_account_tokens_before(Utc::now()).await?;
+        self.repo
+            .delete_account_tokens(user_id, purpose.as_str())
+            .await?;
+        self.repo.insert_account_token(&account_token).await?;
+
+        let url = match purpose {
+            AccountTokenPurpose::VerifyEmail => &self.settings.email_verification_url,
+            AccountTokenPurpose::ResetPassword => &self.settings.password_reset_url,
+        };
+        account_email(email, purpose, url, &token)
+    }
+
+    /// Argon2 is deliberately slow, so it runs off the async workers.
+    async fn hash_password(&self, password: &str) -> Result<String, AppError> {
+        let hasher = self.settings.password_hasher.clone();
+        let password = password.to_string();
+        tokio::task::spawn_blocking(move || hasher.hash(&password))
+            .await
+            .map_err(|_| AppError::Unexpected)?
+            .map_err(|e| {
+                log::error!("Failed to hash password: {}", e);
+                AppError::Unexpected
+            })
+    }
+}
+
+/// KuriLogin as an OpenID Connect provider for registered clients.
+impl AppService {
+    /// Handles an authentication request to `/oauth/authorize`. Until the client and its
+    /// redirect URI are known, failures are plain errors; after that they are sent back to the
+    /// client as OAuth errors. Users without a browser session are sent to sign in upstream
+    /// first, which returns them to the same request.
+    pub async fn authorize(
+        &self,
+        request: &AuthorizeRequest,
+        query_string: &str,
+        user: Option<&Claims>,
+        client_info: &ClientInfo,
+    ) -> Result<AuthorizeOutcome, AppError> {
+        let client = self
+            .repo
+            .find_client(&request.client_id)
+            .await?
+            .ok_or_else(|| AuthError::InvalidClient(request.client_id.clone()))?;
+        let redirect_uri = request.redirect_uri_for(&client)?;
+        let state = request.state.as_deref();
+        let error = |error: &str, description: &str| {
+            Ok(AuthorizeOutcome::Redirect(client_redirect(
+                &redirect_uri,
+                &[("error", error), ("error_description", description)],
+                state,
+            )))
+        };
+
+        let scope = match request.check(&client) {
+            Ok(scope) => scope,
+            Err((code, description)) => return error(code, description),
+        };
+
+        // Tokens granted to other clients do not count as a KuriLogin session.
+        let Some(user) = user.filter(|user| user.client_id.is_none()) else {
+            if request.prompt.as_deref() == Some("none") {
+                return error("login_required", "the user is not signed in");
+            }
+            let provider = request
+                .provider
+                .as_deref()
+                .unwrap_or(&self.settings.default_provider);
+            let return_to = format!(
+                "{}/oauth/authorize?{}",
+                self.settings.base_url.trim_end_matches('/'),
+                query_string
+            );
+            let redirect = self
+                .initiate_oauth(provider, None, Some(&return_to), None)
+                .await?;
+            return Ok(AuthorizeOutcome::Login(redirect));
+        };
+
+        let session = SessionBuilder::new()
+            .user_id(user.sub)
+            .refresh_family_id(generate_token(16))
+            .client(client_info)
+            .build();
+        let session = self.session_service.start_session(&session).await?;
+        let (authorization_code, code) = AuthorizationCode::issue(
+            client.client_id,
+            user.sub,
+            session.refresh_family_id,
+            request.code_challenge.clone(),
+            redirect_uri.clone(),
+            Some(scope),
+            request.nonce.clone(),
+        );
+        self.repo
+            .delete_authorization_codes_before(Utc::now())
+            .await?;
+        self.repo
+            .insert_authorization_code(&authorization_code)
+            .await?;
+        Ok(AuthorizeOutcome::Redirect(client_redirect(
+            &redirect_uri,
+            &[("code", &code)],
+            state,
+        )))
+    }
+
+    /// Checks a registered client's credentials. Public clients only need to exist.
+    pub async fn authenticate_client(
+        &self,
+        client_id: &str,
+        client_secret: Option<&str>,
+    ) -> Result<RegisteredClient, AppError> {
+        self.repo
+            .find_client(client_id)
+            .await?
+            .filter(|client| client.authenticate(client_secret))
+            .ok_or_else(|| AuthError::InvalidClient(client_id.to_string()).into())
+    }
+
+    /// The `authorization_code` grant of `/oauth/token`. Codes granted the `openid` scope
+    /// also get an ID token.
+    pub async fn exchange_client_code(
+        &self,
+        code: &str,
+        client: &RegisteredClient,
+        code_verifier: Option<&str>,
+        redirect_uri: Option<&str>,
+    ) -> Result<TokenPair, AppError> {
+        let stored = self
+            .redeem_authorization_code(
+                code,
+                &client.client_id,
+                client.is_confidential(),
+                code_verifier,
+            )
+            .await?;
+        if redirect_uri.is_some_and(|redirect_uri| redirect_uri != stored.redirect_url) {
+            return Err(AuthError::InvalidGrant("redirect_uri does not match".into()).into());
+        }
+
+        let grant = Grant {
+            client_id: Some(client.client_id.clone()),
+            scope: stored.scope.clone(),
+        };
+        let mut tokens = self
+            .issue_tokens(stored.user_id, stored.refresh_family_id.clone(), grant)
+            .await?;
+        if scope_includes(stored.scope.as_deref(), "openid") {
+            let user = self.user_service.get_user(stored.user_id).await?;
+            let now = Utc::now();
+            let claims = IdTokenClaims {
+                iss: self.issuer(),
+                aud: client.client_id.clone(),
+                exp: now.timestamp() + self.jwt_manager.ttl_seconds(),
+                iat: now.timestamp(),
+                nonce: stored.nonce,
+                sid: Some(stored.refresh_family_id),
+                user_info: user_info(&user, stored.scope.as_deref()),
+            };
+            tokens.id_token = Some(self.jwt_manager.sign(&claims)?);
+        }
+        Ok(tokens)
+    }
+
+    /// The claims `/oauth/userinfo` returns for an access token. Tokens of KuriLogin's own
+    /// logins carry no scope and see every claim.
+    pub async fn user_info(&self, claims: &Claims) -> Result<UserInfo, AppError> {
+        // Client tokens only ever reach what their granted scopes cover.
+        let scoped = claims.client_id.is_some() || claims.scope.is_some();
+        if scoped && !claims.has_scope("openid") {
+            return Err(AuthError::Forbidden("the token lacks the openid scope".into()).into());
+        }
+        let user = self.user_service.get_user(claims.sub).await?;
+        Ok(user_info(&user, claims.scope.as_deref()))
+    }
+
+    /// Describes a token for a resource server (RFC 7662). Access tokens must verify and not
+    /// be revoked; refresh tokens must be unused, unexpired and belong to a live session.
+    pub async fn introspect(
+        &self,
+        token: &str,
+        token_type_hint: Option<&str>,
+    ) -> Result<TokenIntrospection, AppError> {
+        let introspection = if token_type_hint == Some("refresh_token") {
+            match self.introspect_refresh_token(token).await? {
+                Some(introspection) => Some(introspection),
+                None => self.introspect_access_token(token).await?,
+            }
+        } else {
+            match self.introspect_access_token(token).await? {
+                Some(introspection) => Some(introspection),
+                None => self.introspect_refresh_token(token).await?,
+            }
+        };
+        Ok(introspection.unwrap_or_default())
+    }
+
+    async fn introspect_access_token(
+        &self,
+        token: &str,
+    ) -> Result<Option<TokenIntrospection>, AppError> {
+        let claims = match self.jwt_manager.verify_jwt(token).await {
+            Ok(claims) => claims,
+            // The revocation store being down must not make tokens look inactive.
+            Err(e @ kuri_auth::Error::Storage(_)) => return Err(AuthError::from(e).into()),
+            Err(_) => return Ok(None),
+        };
+        Ok(Some(TokenIntrospection {
+            active: true,
+            token_type: Some("access_token".to_string()),
+            scope: claims.scope,
+            client_id: claims.client_id,
+            sub: Some(claims.sub.to_string()),
+            exp: Some(claims.exp),
+            iat: Some(claims.iat),
+            iss: claims.iss,
+            aud: claims.aud,
+            jti: claims.jti,
+            sid: claims.sid,
+        }))
+    }
+
+    async fn introspect_refresh_token(
+        &self,
+        token: &str,
+    ) -> Result<Option<TokenIntrospection>, AppError> {
+        let Some(stored) = self.repo.find_refresh_token(&hash_token(token)).await? else {
+            return Ok(None);
+        };
+        if stored.used_at.is_some()
+            || stored.revoked_at.is_some()
+            || stored.is_expired()
+            || !self
+                .session_service
+                .is_session_active(&stored.family_id)
+                .await?
+        {
+            return Ok(None);
+        }
+        Ok(Some(TokenIntrospection {
+            active: true,
+            token_type: Some("refresh_token".to_string()),
+            scope: stored.scope,
+            client_id: stored.client_id,
+            sub: Some(stored.user_id.to_string()),
+            exp: Some(stored.expires_at.timestamp()),
+            iat: Some(stored.created_at.timestamp()),
+            iss: Some(self.issuer()),
+            aud: None,
+            jti: None,
+            sid: Some(stored.family_id),
+        }))
+    }
+
+    /// Revokes a token (RFC 7009). Tokens granted to a client can only be revoked by that
+    /// client; those of KuriLogin's own logins by whoever holds them. Revoking a refresh token
+    /// ends its session, access tokens included. Unknown tokens are ignored.
+    pub async fn revoke_token(
+        &self,
+        token: &str,
+        token_type_hint: Option<&str>,
+        client_id: Option<&str>,
+    ) -> Result<(), AppError> {
+        // The hint only decides which kind of token is tried first.
+        let revoked = match token_type_hint {
+            Some("refresh_token") => {
+                self.revoke_refresh_token(token, client_id).await?
+                    || self.revoke_access_token(token, client_id).await?
+            }
+            _ => {
+                self.revoke_access_token(token, client_id).await?
+                    || self.revoke_refresh_token(token, client_id).await?
+            }
+        };
+        if !revoked {
+            log::debug!("Ignoring revocation of an unknown token");
+        }
+        Ok(())
+    }
+
+    async fn revoke_access_token(
+        &self,
+        token: &str,
+        client_id: Option<&str>,
+    ) -> Result<bool, AppError> {
+        let claims = match self.jwt_manager.verify_jwt(token).await {
+            Ok(claims) => claims,
+            Err(e @ kuri_auth::Error::Storage(_)) => return Err(AuthError::from(e).into()),
+            // Expired, revoked or foreign tokens need no revoking.
+            Err(_) => return Ok(false),
+        };
+        if claims.client_id.as_deref() != client_id {
+            return Err(AuthError::InvalidClient(client_id.unwrap_or_default().to_string()).into());
+        }
+        self.jwt_manager.revoke(&claims).await?;
+        Ok(true)
+    }
+
+    async fn revoke_refresh_token(
+        &self,
+        token: &str,
+        client_id: Option<&str>,
+    ) -> Result<bool, AppError> {
+        let Some(stored) = self.repo.find_refresh_token(&hash_token(token)).await? else {
+            return Ok(false);
+        };
+        if stored.client_id.as_deref() != client_id {
+            return Err(AuthError::InvalidClient(client_id.unwrap_or_default().to_string()).into());
+        }
+
+        self.repo
+            .revoke_refresh_token_family(&stored.family_id)
+            .await?;
+        self.jwt_manager.revoke_session(&stored.family_id).await?;
+        let session = self.session_service.find_session(&stored.family_id).await?;
+        self.session_service.end_session(&stored.family_id).await?;
 
-        let jwt = self.jwt_manager.create_jwt(auth_data.user_id)?;
+        if let (true, Some(provider_id)) = (
+            self.settings.revoke_upstream_tokens,
+            session.and_then(|session| session.provider_id),
+        ) {
+            // Our tokens are revoked either way; the provider's are best effort.
+            if let Err(e) = self
+                .revoke_upstream_tokens(stored.user_id, provider_id)
+                .await
+            {
+                log::warn!(
+                    "Failed to revoke provider {} tokens of user {}: {}",
+                    provider_id,
+                    stored.user_id,
+                    e
+                );
+            }
+        }
+        Ok(true)
+    }
+
+    async fn revoke_upstream_tokens(
+        &self,
+        user_id: i32,
+        provider_id: i32,
+    ) -> Result<(), AuthError> {
+        let (Some(provider), Some(authorization)) = (
+            self.providers.get(&provider_id),
+            self.repo
+                .find_oauth_authorization(user_id, provider_id)
+                .await?,
+        ) else {
+            return Ok(());
+        };
+        provider.revoke_authorization(&authorization).await?;
+        log::info!("Revoked {} tokens of user {}", provider.name(), user_id);
+        Ok(())
+    }
+
+    /// The OpenID Connect discovery document.
+    pub fn openid_configuration(&self) -> Value {
+        let base_url = self.settings.base_url.trim_end_matches('/');
+        json!({
+            "issuer": self.issuer(),
+            "authorization_endpoint": format!("{}/oauth/authorize", base_url),
+            "token_endpoint": format!("{}/oauth/token", base_url),
+            "userinfo_endpoint": format!("{}/oauth/userinfo", base_url),
+            "introspection_endpoint": format!("{}/oauth/introspect", base_url),
+            "revocation_endpoint": format!("{}/oauth/revoke", base_url),
+            "jwks_uri": format!("{}/.well-known/jwks.json", base_url),
+            "response_types_supported": ["code"],
+            "grant_types_supported": ["authorization_code", "refresh_token"],
+            "subject_types_supported": ["public"],
+            "id_token_signing_alg_values_supported": [
+                format!("{:?}", self.jwt_manager.current_key().algorithm())
+            ],
+            "scopes_supported": ["openid", "profile", "email"],
+            "claims_supported": ["sub", "name", "picture", "email"],
+            "token_endpoint_auth_methods_supported":
+                ["client_secret_basic", "client_secret_post", "none"],
+            "code_challenge_methods_supported": ["S256"],
+        })
+    }
+
+    fn issuer(&self) -> String {
+        self.jwt_manager
+            .issuer()
+            .unwrap_or(&self.settings.base_url)
+            .to_string()
+    }
+
+    /// Registers a client and returns it with its secret, which cannot be retrieved later.
+    pub async fn register_client(
+        &self,
+        name: String,
+        redirect_uris: Vec<String>,
+        allowed_scopes: Vec<String>,
+        confidential: bool,
+        trusted: bool,
+    ) -> Result<(RegisteredClient, Option<String>), AppError> {
+        if redirect_uris.is_empty() {
+            return Err(AuthError::InvalidRedirectUri(String::new()).into());
+        }
+        // Redirect URIs are compared exactly, and codes are appended to their query.
+        if let Some(invalid) = redirect_uris.iter().find(|redirect_uri| {
+            Url::parse(redirect_uri).map_or(true, |url| url.fragment().is_some())
+        }) {
+            return Err(AuthError::InvalidRedirectUri(invalid.clone()).into());
+        }
+
+        let (client, secret) =
+            RegisteredClient::register(name, redirect_uris, allowed_scopes, confidential, trusted);
+        self.repo.insert_client(&client).await?;
+        log::info!("Registered client {} ({})", client.client_id, client.name);
+        Ok((client, secret))
+    }
+
+    pub async fn list_clients(&self) -> Result<Vec<RegisteredClient>, AppError> {
+        Ok(self.repo.list_clients().await?)
+    }
+
+    pub async fn delete_client(&self, client_id: &str) -> Result<(), AppError> {
+        if !self.repo.delete_client(client_id).await? {
+            return Err(AuthError::ClientNotFound(client_id.to_string()).into());
+        }
+        log::info!("Deleted client {}", client_id);
+        Ok(())
+    }
+}
+
+impl AppService {
+    /// Checks the bearer token sent to an admin endpoint.
+    pub fn authorize_admin(&self, bearer_token: Option<&str>) -> Result<(), AppError> {
+        match (&self.settings.admin_token, bearer_token) {
+            (Some(expected), Some(given))
+                if bool::from(expected.as_bytes().ct_eq(given.as_bytes())) =>
+            {
+                Ok(())
+            }
+            _ => Err(AuthError::Unauthorized.into()),
+        }
+    }
+
+    /// Rebuilds the JWT keyring from the stored signing keys.
+    pub async fn reload_signing_keys(&self) -> Result<(), AppError> {
+        let records = self.repo.list_signing_keys().await?;
+        let now = Utc::now();
+
+        let current = records
+            .iter()
+            .filter(|record| record.promoted_at.is_some() && record.retires_at.is_none())
+            .max_by_key(|record| record.promoted_at);
+
+        let mut keyring = match current {
+            Some(record) => {
+                // The bootstrap key was superseded by the first promotion.
+                let first_promotion = records.iter().filter_map(|record| record.promoted_at).min();
+                Keyring::new(record.signing_key()?).with_verification_key(
+                    self.bootstrap_key.clone(),
+                    first_promotion.map(|at| at + self.settings.key_retirement),
+                )
+            }
+            None => Keyring::new(self.bootstrap_key.clone()),
+        };
+
+        for record in records.iter().filter(|record| {
+            Some(&record.kid) != current.map(|current| &current.kid)
+                && record.retires_at.map_or(true, |at| at > now)
+        }) {
+            keyring = keyring.with_verification_key(record.signing_key()?, record.retires_at);
+        }
+
+        self.jwt_manager.replace_keyring(keyring);
+        Ok(())
+    }
+
+    pub async fn list_signing_keys(&self) -> Result<Vec<SigningKeyRecord>, AppError> {
+        Ok(self.repo.list_signing_keys().await?)
+    }
+
+    /// Generates a pending key. It is published in the JWKS right away but only signs tokens
+    /// once promoted, so verifiers can pick it up first.
+    pub async fn generate_signing_key(
+        &self,
+        algorithm: Option<Algorithm>,
+    ) -> Result<SigningKeyRecord, AppError> {
+        let algorithm = algorithm.unwrap_or(self.settings.key_algorithm);
+        let (key, key_material) = SigningKey::generate(algorithm)?;
+        let record = SigningKeyRecord {
+            kid: key.kid().to_string(),
+            algorithm: format!("{:?}", algorithm),
+            key_material,
+            created_at: Utc::now(),
+            promoted_at: None,
+            retires_at: None,
+        };
+        self.repo.insert_signing_key(&record).await?;
+        self.reload_signing_keys().await?;
+        log::info!("Generated signing key {}", record.kid);
+        Ok(record)
+    }
+
+    /// Makes `kid` the signing key. The previous one keeps verifying for the retirement period.
+    pub async fn promote_signing_key(&self, kid: &str) -> Result<SigningKeyRecord, AppError> {
+        let existing = self.find_signing_key(kid).await?;
+        if existing.retires_at.is_some_and(|at| at <= Utc::now()) {
+            return Err(
+                AuthError::KeyRotationConflict(format!("key {} has been retired", kid)).into(),
+            );
+        }
+
+        let record = self
+            .repo
+            .promote_signing_key(kid, Utc::now() + self.settings.key_retirement)
+            .await?
+            .ok_or_else(|| AuthError::SigningKeyNotFound(kid.to_string()))?;
+        self.reload_signing_keys().await?;
+        log::info!("Promoted signing key {}", kid);
+        Ok(record)
+    }
+
+    /// Stops `kid` from verifying tokens immediately.
+    pub async fn retire_signing_key(&self, kid: &str) -> Result<SigningKeyRecord, AppError> {
+        self.find_signing_key(kid).await?;
+        if self.jwt_manager.current_key().kid() == kid {
+            return Err(AuthError::KeyRotationConflict(
+                "promote another key before retiring the current one".to_string(),
+            )
+            .into());
+        }
+
+        let record = self
+            .repo
+            .retire_signing_key(kid, Utc::now())
+            .await?
+            .ok_or_else(|| AuthError::SigningKeyNotFound(kid.to_string()))?;
+        self.reload_signing_keys().await?;
+        log::info!("Retired signing key {}", kid);
+        Ok(record)
+    }
+
+    async fn find_signing_key(&self, kid: &str) -> Result<SigningKeyRecord, AppError> {
+        self.repo
+            .list_signing_keys()
+            .await?
+            .into_iter()
+            .find(|record| record.kid == kid)
+            .ok_or_else(|| AuthError::SigningKeyNotFound(kid.to_string()).into())
+    }
+}
+
+/// The lowercased `email`, if it looks like an address at all.
+fn normalize_email(email: &str) -> Option<String> {
+    let email = email.trim().to_lowercase();
+    match email.split_once('@') {
+        Some((local, domain)) if !local.is_empty() && domain.contains('.') => Some(email),
+        _ => None,
+    }
+}
+
+/// Tells the owner of `email` that someone tried to register it, which they would otherwise
+/// learn from a `409` that anyone could probe for.
+fn registration_attempt_email(email: &str, password_reset_url: &str) -> Email {
+    Email {
+        to: email.to_string(),
+        subject: "Someone tried to register with your email".to_string(),
+        body: format!(
+            "Someone tried to create an account with this email address, which already has \
+             one. If it was you, sign in instead, or reset your password at:\n\n{}\n\n\
+             If it was not you, you can ignore this email.",
+            password_reset_url
+        ),
+    }
+}
+
+/// The email carrying `token` as the `?token=` of the page at `url`.
+fn account_email(
+    email: &str,
+    purpose: AccountTokenPurpose,
+    url: &str,
+    token: &str,
+) -> Result<Email, AppError> {
+    let (subject, action) = match purpose {
+        AccountTokenPurpose::VerifyEmail => {
+            ("Verify your email address", "verify your email address")
+        }
+        AccountTokenPurpose::ResetPassword => ("Reset your password", "choose a new password"),
+    };
+    let mut link = Url::parse(url).map_err(|e| {
+        log::error!("Invalid account link URL {}: {}", url, e);
+        AppError::Unexpected
+    })?;
+    link.query_pairs_mut().append_pair("token", token);
+
+    Ok(Email {
+        to: email.to_string(),
+        subject: subject.to_string(),
+        body: format!(
+            "Open this link to {}:\n\n{}\n\nThe link works once and expires in {} hours. \
+             If you did not ask for it, you can ignore this email.",
+            action,
+            link,
+            purpose.ttl().num_hours()
+        ),
+    })
+}
+
+/// `redirect_uri` with `params` and the client's `state` added to its query.
+fn client_redirect(redirect_uri: &str, params: &[(&str, &str)], state: Option<&str>) -> String {
+    let mut query = form_urlencoded::Serializer::new(String::new());
+    query.extend_pairs(params);
+    if let Some(state) = state {
+        query.append_pair("state", state);
+    }
+    let separator = if redirect_uri.contains('?') { '&' } else { '?' };
+    format!("{}{}{}", redirect_uri, separator, query.finish())
+}
+
+/// Whether a space-separated `scope` includes `name`. Tokens without a scope include all.
+fn scope_includes(scope: Option<&str>, name: &str) -> bool {
+    scope.map_or(true, |scope| {
+        scope.split_whitespace().any(|scope| scope == name)
+    })
+}
+
+/// The claims about `user` that `scope` covers.
+fn user_info(user: &User, scope: Option<&str>) -> UserInfo {
+    let profile = scope_includes(scope, "profile");
+    let email = scope_includes(scope, "email");
+    UserInfo {
+        sub: user.user_id.to_string(),
+        name: user.name.clone().filter(|_| profile),
+        picture: user.avatar_url.clone().filter(|_| profile),
+        email: user.email.clone().filter(|_| email),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+
+    fn user() -> User {
+        User {
+            user_id: 7,
+            email: Some("ada@example.com".to_string()),
+            email_verified: true,
+            name: Some("Ada".to_string()),
+            avatar_url: Some("https://example.com/ada.png".to_string()),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn userinfo_only_shows_granted_scopes() {
+        let info = user_info(&user(), Some("openid"));
+        assert_eq!(info.sub, "7");
+        assert_eq!(info.name, None);
+        assert_eq!(info.email, None);
+
+        let info = user_info(&user(), Some("openid email"));
+        assert_eq!(info.email.as_deref(), Some("ada@example.com"));
+        assert_eq!(info.picture, None);
+    }
+
+    #[test]
+    fn first_party_tokens_see_the_whole_profile() {
+        let info = user_info(&user(), None);
+        assert_eq!(info.name.as_deref(), Some("Ada"));
+        assert_eq!(info.email.as_deref(), Some("ada@example.com"));
+    }
+
+    #[test]
+    fn account_emails_link_to_the_page_with_the_token() {
+        let email = account_email(
+            "ada@example.com",
+            AccountTokenPurpose::ResetPassword,
+            "https://app.example.com/reset?lang=en",
+            "t0ken",
+        )
+        .unwrap();
+        assert_eq!(email.to, "ada@example.com");
+        assert_eq!(email.subject, "Reset your password");
+        assert!(email
+            .body
+            .contains("https://app.example.com/reset?lang=en&token=t0ken"));
+        assert!(email.body.contains("expires in 1 hours"));
+    }
+
+    #[test]
+    fn registration_attempts_point_at_the_password_reset() {
+        let email = registration_attempt_email("ada@example.com", "https://app.example.com/reset");
+        assert_eq!(email.to, "ada@example.com");
+        assert!(email.body.contains("https://app.example.com/reset"));
+    }
 
-        Ok(jwt)
+    #[test]
+    fn scopes_match_whole_names() {
+        assert!(scope_includes(Some("openid email"), "email"));
+        assert!(!scope_includes(Some("openid emails"), "email"));
     }
 }
@@ -31,5 +31,101 @@ pub enum AuthError {
     OAuth2RequestTokenError(String),
 
     #[error("Provider not found: {0}")]
-    ProviderNotFound(i32),
+    ProviderNotFound(String),
+
+    #[error("Invalid signing key: {0}")]
+    InvalidSigningKey(String),
+
+    #[error("Signing key not found: {0}")]
+    SigningKeyNotFound(String),
+
+    #[error("Key rotation conflict: {0}")]
+    KeyRotationConflict(String),
+
+    #[error("Unauthorized")]
+    Unauthorized,
+
+    #[error("Invalid refresh token")]
+    InvalidRefreshToken,
+
+    #[error("Refresh token reused")]
+    RefreshTokenReused,
+
+    #[error("Token revoked")]
+    TokenRevoked,
+
+    #[error("Token expired")]
+    TokenExpired,
+
+    #[error("Invalid OAuth state: {0}")]
+    InvalidState(String),
+
+    #[error("Unknown client: {0}")]
+    InvalidClient(String),
+
+    #[error("return_to not allowed: {0}")]
+    InvalidReturnTo(String),
+
+    #[error("redirect_uri not registered: {0}")]
+    InvalidRedirectUri(String),
+
+    #[error("Client not found: {0}")]
+    ClientNotFound(String),
+
+    #[error("Invalid grant: {0}")]
+    InvalidGrant(String),
+
+    #[error("Upstream token revocation failed: {0}")]
+    RevocationFailed(String),
+
+    #[error("Identity is already linked to another user: {0}")]
+    IdentityAlreadyLinked(String),
+
+    #[error("A user with this email exists; link the {0} account from it")]
+    LinkRequired(String),
+
+    #[error("Invalid email or password")]
+    InvalidCredentials,
+
+    #[error("Password {0}")]
+    WeakPassword(String),
+
+    #[error("Invalid or expired account token")]
+    InvalidAccountToken,
+
+    #[error("Failed to send email: {0}")]
+    MailDeliveryFailed(String),
+
+    #[error("Identity not found: {0}")]
+    IdentityNotFound(i32),
+
+    #[error("Cannot unlink the last way to sign in")]
+    LastIdentity,
+
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
+    #[error("Token verification unavailable: {0}")]
+    TokenVerificationUnavailable(String),
+}
+
+impl From<kuri_auth::Error> for AuthError {
+    fn from(error: kuri_auth::Error) -> Self {
+        match error {
+            kuri_auth::Error::MissingToken => AuthError::Unauthorized,
+            kuri_auth::Error::InvalidToken(msg) => AuthError::InvalidTokenError(msg),
+            kuri_auth::Error::Jwt(e) => AuthError::JwtError(e),
+            kuri_auth::Error::TokenExpired => AuthError::TokenExpired,
+            kuri_auth::Error::TokenRevoked => AuthError::TokenRevoked,
+            kuri_auth::Error::Forbidden(msg) => AuthError::Forbidden(msg),
+            kuri_auth::Error::InvalidSigningKey(msg) => AuthError::InvalidSigningKey(msg),
+            kuri_auth::Error::JwtCreationFailed(msg) => AuthError::JwtCreationFailed(msg),
+            kuri_auth::Error::Network(e) => AuthError::NetworkError(e),
+            kuri_auth::Error::KeySetUnavailable(source) => {
+                AuthError::TokenVerificationUnavailable(format!("key set {} unavailable", source))
+            }
+            kuri_auth::Error::NotConfigured(msg) => AuthError::TokenVerificationUnavailable(msg),
+            kuri_auth::Error::Storage(e) => AuthError::TokenVerificationUnavailable(e.to_string()),
+        }
+    }
 }
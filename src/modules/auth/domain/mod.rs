@@ -1,6 +1,10 @@
 mod models;
 pub use models::*;
 
+pub use kuri_auth::{
+    Claims, InMemoryRevocationStore, JwtManager, JwtManagerBuilder, Keyring, SigningKey,
+};
+
 pub mod ports;
 
 mod error;
@@ -1,9 +1,335 @@
+use std::{collections::HashSet, sync::Arc};
+
 use chrono::{DateTime, Utc};
-use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use jsonwebtoken::Algorithm;
+use oauth2::{
+    basic::BasicTokenType, CsrfToken, ExtraTokenFields, PkceCodeVerifier, StandardTokenResponse,
+};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use sqlx::FromRow;
+use subtle::ConstantTimeEq;
+
+use crate::{
+    modules::user::User,
+    utils::{
+        password::PasswordHasher,
+        token::{generate_token, hash_token, pkce_challenge},
+    },
+};
+
+use super::{AuthError, SigningKey};
+
+/// Tunables for the auth service that are not tied to a single provider.
+#[derive(Debug, Clone)]
+pub struct AuthSettings {
+    /// Bearer token for the `/admin` endpoints, which are disabled without one.
+    pub admin_token: Option<String>,
+    /// Algorithm of signing keys generated through the admin endpoints.
+    pub key_algorithm: Algorithm,
+    /// How long a superseded signing key keeps verifying tokens.
+    pub key_retirement: chrono::Duration,
+    /// Lifetime of each refresh token; every use issues a new one.
+    pub refresh_token_ttl: chrono::Duration,
+    /// Applications that may start logins with `?client=`.
+    pub clients: Vec<ClientSettings>,
+    /// Origins `?return_to=` may point at, besides those of the client's redirect URLs.
+    pub return_to_origins: Vec<String>,
+    /// Where this service is reachable, e.g. `https://login.example.com`.
+    pub base_url: String,
+    /// Provider `/oauth/authorize` sends users to when they are not signed in.
+    pub default_provider: String,
+    /// Whether revoking a session through `/oauth/revoke` also revokes the provider tokens
+    /// stored for it.
+    pub revoke_upstream_tokens: bool,
+    pub email_linking: EmailLinking,
+    /// Hashes the passwords of `/auth/password` accounts.
+    pub password_hasher: PasswordHasher,
+    pub password_policy: PasswordPolicy,
+    /// Page the email verification link opens; it gets the token as `?token=`.
+    pub email_verification_url: String,
+    /// Page the password reset link opens; it gets the token as `?token=`.
+    pub password_reset_url: String,
+}
+
+/// How a client receives the tokens of a completed login.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenDelivery {
+    /// The callback answers with the token pair as JSON.
+    Json,
+    /// The callback sets session cookies and redirects the browser back to the client.
+    Cookie,
+    /// The callback redirects back to the client with the tokens in the URL fragment, which
+    /// browsers do not send to servers.
+    Fragment,
+    /// The callback redirects back to the client with a single-use code, which the client
+    /// exchanges for tokens at `POST /auth/token`.
+    Code,
+}
+
+/// An application that starts logins.
+///
+/// These are KuriLogin's own front-ends, configured through the environment: they start logins
+/// at `/auth/{provider}/login?client=` and choose how the tokens are delivered. Third-party
+/// applications are [`RegisteredClient`]s instead, which use the OpenID Connect endpoints and
+/// get tokens limited to their scopes. Both check secrets the same way.
+#[derive(Debug, Clone)]
+pub struct ClientSettings {
+    pub client_id: String,
+    /// Confidential clients authenticate with the secret hashed here; public ones must use
+    /// PKCE.
+    pub secret_hash: Option<String>,
+    pub delivery: TokenDelivery,
+    /// Where the browser may be sent after login; the first one is the default.
+    pub redirect_urls: Vec<String>,
+}
+
+impl ClientSettings {
+    pub fn is_confidential(&self) -> bool {
+        self.secret_hash.is_some()
+    }
+
+    /// Whether `secret` is this client's secret. Public clients have none to check.
+    pub fn authenticate(&self, secret: Option<&str>) -> bool {
+        secret_matches(self.secret_hash.as_deref(), secret)
+    }
+}
+
+/// Whether `secret` hashes to `secret_hash`, compared in constant time. An empty secret never
+/// matches; without a hash there is nothing to match.
+fn secret_matches(secret_hash: Option<&str>, secret: Option<&str>) -> bool {
+    match (secret_hash, secret) {
+        (Some(secret_hash), Some(secret)) if !secret.is_empty() => {
+            bool::from(hash_token(secret).as_bytes().ct_eq(secret_hash.as_bytes()))
+        }
+        (Some(_), _) => false,
+        (None, _) => true,
+    }
+}
+
+/// What the OAuth callback hands back to the browser.
+#[derive(Debug)]
+pub enum LoginOutcome {
+    Json(TokenPair),
+    Cookie {
+        tokens: TokenPair,
+        redirect_url: String,
+    },
+    Fragment {
+        tokens: TokenPair,
+        redirect_url: String,
+    },
+    Code {
+        code: String,
+        redirect_url: String,
+    },
+    /// The login linked another identity to a signed-in user; no tokens are issued.
+    Linked {
+        identity: Identity,
+        redirect_url: Option<String>,
+    },
+}
+
+/// When a first login through a provider may attach to an existing user with the same email.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailLinking {
+    /// Only when the provider reports the email as verified and the existing user verified it
+    /// too; otherwise whoever claimed the address first could take over the other account.
+    Verified,
+    /// Never; the login fails with `LinkRequired` and the user links the identity explicitly
+    /// through `/me/identities`.
+    Never,
+}
+
+impl EmailLinking {
+    /// Whether a first login with `profile` may join `existing`, which has the same email.
+    pub fn allows(&self, profile: &UserProfile, existing: &User) -> bool {
+        match self {
+            EmailLinking::Verified => {
+                profile.email.is_some() && profile.email_verified && existing.email_verified
+            }
+            EmailLinking::Never => false,
+        }
+    }
+}
+
+/// What `/auth/password/register` demands of new passwords. Length is what makes passwords
+/// hard to guess, so there are no composition rules.
+#[derive(Clone)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    /// Bounds the work a single login can cause.
+    pub max_length: usize,
+    /// Lowercase passwords to refuse, such as a list of the most common breached ones.
+    pub blocklist: Arc<HashSet<String>>,
+}
+
+impl PasswordPolicy {
+    /// Rejects `password` for the account of `email` with the reason it is too weak.
+    pub fn check(&self, password: &str, email: &str) -> Result<(), AuthError> {
+        let length = password.chars().count();
+        if length < self.min_length {
+            return Err(AuthError::WeakPassword(format!(
+                "must be at least {} characters long",
+                self.min_length
+            )));
+        }
+        if length > self.max_length {
+            return Err(AuthError::WeakPassword(format!(
+                "must be at most {} characters long",
+                self.max_length
+            )));
+        }
+
+        let lowercase = password.to_lowercase();
+        if self.blocklist.contains(&lowercase) {
+            return Err(AuthError::WeakPassword("is too common".to_string()));
+        }
+        let mut chars = lowercase.chars();
+        let first = chars.next();
+        if chars.all(|c| Some(c) == first) {
+            return Err(AuthError::WeakPassword(
+                "must not repeat a single character".to_string(),
+            ));
+        }
+        let local_part = email.split('@').next().unwrap_or_default().to_lowercase();
+        if local_part.chars().count() >= 4 && lowercase.contains(&local_part) {
+            return Err(AuthError::WeakPassword(
+                "must not contain the email address".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Reads a blocklist with one password per line, ignoring blank lines.
+    pub fn parse_blocklist(contents: &str) -> HashSet<String> {
+        contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_lowercase)
+            .collect()
+    }
+}
+
+impl std::fmt::Debug for PasswordPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PasswordPolicy")
+            .field("min_length", &self.min_length)
+            .field("max_length", &self.max_length)
+            .field("blocklist", &self.blocklist.len())
+            .finish()
+    }
+}
+
+/// The password of a user who signs in with their email instead of a provider.
+#[derive(Debug, Clone, FromRow)]
+pub struct UserCredentials {
+    pub user_id: i32,
+    pub password_hash: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// When the user proved they receive mail at the account's email.
+    pub email_verified_at: Option<DateTime<Utc>>,
+}
+
+/// What an emailed account token lets its holder do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountTokenPurpose {
+    VerifyEmail,
+    ResetPassword,
+}
+
+impl AccountTokenPurpose {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccountTokenPurpose::VerifyEmail => "verify_email",
+            AccountTokenPurpose::ResetPassword => "reset_password",
+        }
+    }
+
+    /// Reset tokens hand over the account, so they are short-lived.
+    pub fn ttl(&self) -> chrono::Duration {
+        match self {
+            AccountTokenPurpose::VerifyEmail => chrono::Duration::hours(24),
+            AccountTokenPurpose::ResetPassword => chrono::Duration::hours(1),
+        }
+    }
+}
+
+/// A single-use token sent to a user's email, stored only as a hash.
+#[derive(FromRow, Debug, Clone)]
+pub struct AccountToken {
+    pub token_hash: String,
+    pub user_id: i32,
+    pub purpose: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+}
+
+impl AccountToken {
+    /// Creates a token and returns it with its plaintext value, which only the email carries.
+    pub fn issue(user_id: i32, purpose: AccountTokenPurpose) -> (Self, String) {
+        let token = generate_token(32);
+        let now = Utc::now();
+        let account_token = AccountToken {
+            token_hash: hash_token(&token),
+            user_id,
+            purpose: purpose.as_str().to_string(),
+            created_at: now,
+            expires_at: now + purpose.ttl(),
+            used_at: None,
+        };
+        (account_token, token)
+    }
 
-use super::AuthError;
+    /// Whether the token may still be used: once, and before it expires. The repository only
+    /// returns such tokens, and the service checks again before acting on one.
+    pub fn is_redeemable(&self) -> bool {
+        self.used_at.is_none() && self.expires_at > Utc::now()
+    }
+}
+
+/// A plain-text email for a `Mailer` to deliver.
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// A provider account a user can sign in with, as listed under `/me/identities`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Identity {
+    pub identity_id: i32,
+    pub provider: String,
+    pub provider_user_id: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// What a successful login or refresh returns to the client.
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenPair {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: i64,
+    pub refresh_token: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    /// Only for OpenID Connect clients that asked for the `openid` scope.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+}
+
+/// The OAuth client and scopes tokens were granted to. Tokens from KuriLogin's own logins
+/// have neither.
+#[derive(Debug, Clone, Default)]
+pub struct Grant {
+    pub client_id: Option<String>,
+    pub scope: Option<String>,
+}
 
 #[derive(FromRow)]
 pub struct OAuthProvider {
@@ -108,57 +434,815 @@ impl OAuthAuthorizationBuilder {
     }
 }
 
+/// Everything a provider produces to start one login attempt.
+pub struct AuthorizationRequest {
+    pub url: String,
+    pub csrf_token: CsrfToken,
+    pub pkce_verifier: PkceCodeVerifier,
+    pub nonce: Option<String>,
+}
+
+/// A user's profile as reported by a provider, normalized so the app service does not need
+/// to know each provider's claim names.
+#[derive(Debug, Clone, Serialize)]
+pub struct UserProfile {
+    pub provider_user_id: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub display_name: Option<String>,
+    pub given_name: Option<String>,
+    pub family_name: Option<String>,
+    pub avatar_url: Option<String>,
+    pub locale: Option<String>,
+    pub raw_claims: Value,
+}
+
+/// Extra token response fields returned by OpenID Connect providers.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OidcTokenFields {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>,
+}
+impl ExtraTokenFields for OidcTokenFields {}
+
+pub type ProviderTokenResponse = StandardTokenResponse<OidcTokenFields, BasicTokenType>;
+
+#[derive(FromRow, Debug, Clone)]
+pub struct PendingAuthorization {
+    pub state: String,
+    pub provider_id: i32,
+    pub return_to: Option<String>,
+    pub pkce_verifier: Option<String>,
+    pub nonce: Option<String>,
+    pub client_id: Option<String>,
+    pub code_challenge: Option<String>,
+    /// Set when a signed-in user links another identity instead of logging in.
+    pub link_user_id: Option<i32>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl PendingAuthorization {
+    /// How long a login attempt may take before its state is rejected.
+    pub const TTL_SECONDS: i64 = 600;
+
+    pub fn is_expired(&self) -> bool {
+        self.created_at + chrono::Duration::seconds(Self::TTL_SECONDS) < Utc::now()
+    }
+
+    /// Whether the browser returning to the callback holds the state cookie set when this
+    /// authorization started, so a callback URL from someone else's login is refused.
+    pub fn is_bound_to(&self, state_cookie: Option<&str>) -> bool {
+        state_cookie.is_some_and(|state_cookie| {
+            bool::from(
+                hash_token(&self.state)
+                    .as_bytes()
+                    .ct_eq(state_cookie.as_bytes()),
+            )
+        })
+    }
+}
+
+/// The provider URL that starts a login, and the `state` it carries.
 #[derive(Debug, Clone)]
-pub struct JwtManager {
-    secret: String,
-    algorithm: Algorithm,
-    validation: Validation,
-}
-
-impl JwtManager {
-    // Constructor to initialize JwtManager
-    pub fn new(secret: String) -> Self {
-        JwtManager {
-            secret,
-            algorithm: Algorithm::HS256,
-            validation: {
-                let mut validation = Validation::new(Algorithm::HS256);
-                validation.validate_exp = false;
-                validation
-            },
-        }
-    }
-
-    // Create a JWT for a given user
-    pub fn create_jwt(&self, user_id: i32) -> Result<String, AuthError> {
-        let expiration_seconds = 3600; // Expiration in 1 hour
-        let claims = Claims {
-            sub: user_id,
-            exp: (Utc::now() + chrono::Duration::seconds(expiration_seconds)).timestamp(), // Unix timestamp
+pub struct AuthorizationRedirect {
+    pub url: String,
+    pub state: String,
+}
+
+pub struct PendingAuthorizationBuilder {
+    state: Option<String>,
+    provider_id: Option<i32>,
+    return_to: Option<String>,
+    pkce_verifier: Option<String>,
+    nonce: Option<String>,
+    client_id: Option<String>,
+    code_challenge: Option<String>,
+    link_user_id: Option<i32>,
+    created_at: Option<DateTime<Utc>>,
+}
+impl PendingAuthorizationBuilder {
+    pub fn new() -> Self {
+        Self {
+            state: None,
+            provider_id: None,
+            return_to: None,
+            pkce_verifier: None,
+            nonce: None,
+            client_id: None,
+            code_challenge: None,
+            link_user_id: None,
+            created_at: None,
+        }
+    }
+    pub fn state<S: Into<String>>(mut self, state: S) -> Self {
+        self.state = Some(state.into());
+        self
+    }
+    pub fn provider_id(mut self, provider_id: i32) -> Self {
+        self.provider_id = Some(provider_id);
+        self
+    }
+    pub fn return_to<S: Into<String>>(mut self, return_to: S) -> Self {
+        self.return_to = Some(return_to.into());
+        self
+    }
+    pub fn pkce_verifier<S: Into<String>>(mut self, pkce_verifier: S) -> Self {
+        self.pkce_verifier = Some(pkce_verifier.into());
+        self
+    }
+    pub fn nonce<S: Into<String>>(mut self, nonce: S) -> Self {
+        self.nonce = Some(nonce.into());
+        self
+    }
+    pub fn client_id<S: Into<String>>(mut self, client_id: S) -> Self {
+        self.client_id = Some(client_id.into());
+        self
+    }
+    pub fn code_challenge<S: Into<String>>(mut self, code_challenge: S) -> Self {
+        self.code_challenge = Some(code_challenge.into());
+        self
+    }
+    pub fn link_user_id(mut self, link_user_id: i32) -> Self {
+        self.link_user_id = Some(link_user_id);
+        self
+    }
+    pub fn created_at(mut self, created_at: DateTime<Utc>) -> Self {
+        self.created_at = Some(created_at);
+        self
+    }
+    pub fn build(self) -> PendingAuthorization {
+        PendingAuthorization {
+            state: self.state.unwrap_or_default(),
+            provider_id: self.provider_id.unwrap_or(0),
+            return_to: self.return_to,
+            pkce_verifier: self.pkce_verifier,
+            nonce: self.nonce,
+            client_id: self.client_id,
+            code_challenge: self.code_challenge,
+            link_user_id: self.link_user_id,
+            created_at: self.created_at.unwrap_or_else(Utc::now),
+        }
+    }
+}
+
+/// A signing key stored for rotation. A key is pending until promoted, current while it is the
+/// latest promoted key, and verifies tokens until `retires_at` once superseded or retired.
+#[derive(FromRow, Debug, Clone, Serialize)]
+pub struct SigningKeyRecord {
+    pub kid: String,
+    pub algorithm: String,
+    #[serde(skip_serializing)]
+    pub key_material: String,
+    pub created_at: DateTime<Utc>,
+    pub promoted_at: Option<DateTime<Utc>>,
+    pub retires_at: Option<DateTime<Utc>>,
+}
+
+impl SigningKeyRecord {
+    pub fn signing_key(&self) -> Result<SigningKey, AuthError> {
+        let algorithm = self
+            .algorithm
+            .parse::<Algorithm>()
+            .map_err(|e| AuthError::InvalidSigningKey(e.to_string()))?;
+        Ok(SigningKey::from_material(
+            Some(self.kid.clone()),
+            algorithm,
+            &self.key_material,
+        )?)
+    }
+}
+
+/// A stored refresh token. Only its hash is kept. Each use rotates it into a new token of the
+/// same family, and presenting an already rotated token revokes the whole family.
+#[derive(FromRow, Debug, Clone)]
+pub struct RefreshToken {
+    pub token_id: i32,
+    pub user_id: i32,
+    pub family_id: String,
+    pub token_hash: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub client_id: Option<String>,
+    pub scope: Option<String>,
+}
+
+impl RefreshToken {
+    /// Creates a token for `user_id` in `family_id` and returns it with its plaintext value,
+    /// which is handed to the client and never stored.
+    pub fn issue(
+        user_id: i32,
+        family_id: String,
+        grant: Grant,
+        ttl: chrono::Duration,
+    ) -> (Self, String) {
+        let token = generate_token(32);
+        let now = Utc::now();
+        let refresh_token = RefreshToken {
+            token_id: 0,
+            user_id,
+            family_id,
+            token_hash: hash_token(&token),
+            created_at: now,
+            expires_at: now + ttl,
+            used_at: None,
+            revoked_at: None,
+            client_id: grant.client_id,
+            scope: grant.scope,
         };
+        (refresh_token, token)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at < Utc::now()
+    }
+}
+
+/// A single-use code handed to a client after login, stored only as a hash.
+#[derive(FromRow, Debug, Clone)]
+pub struct AuthorizationCode {
+    pub code_hash: String,
+    pub client_id: String,
+    pub user_id: i32,
+    pub refresh_family_id: String,
+    pub code_challenge: Option<String>,
+    pub redirect_url: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+    pub scope: Option<String>,
+    /// The OpenID Connect `nonce` to repeat in the ID token.
+    pub nonce: Option<String>,
+}
 
-        encode(
-            &Header::new(self.algorithm),
-            &claims,
-            &EncodingKey::from_secret(self.secret.as_ref()),
-        )
-        .map_err(|err| AuthError::JwtCreationFailed(err.to_string()))
+impl AuthorizationCode {
+    /// How long a client has to redeem its code.
+    pub const TTL_SECONDS: i64 = 60;
+
+    /// Creates a code and returns it with its plaintext value, which only the client gets.
+    pub fn issue(
+        client_id: String,
+        user_id: i32,
+        refresh_family_id: String,
+        code_challenge: Option<String>,
+        redirect_url: String,
+        scope: Option<String>,
+        nonce: Option<String>,
+    ) -> (Self, String) {
+        let code = generate_token(32);
+        let now = Utc::now();
+        let authorization_code = AuthorizationCode {
+            code_hash: hash_token(&code),
+            client_id,
+            user_id,
+            refresh_family_id,
+            code_challenge,
+            redirect_url,
+            created_at: now,
+            expires_at: now + chrono::Duration::seconds(Self::TTL_SECONDS),
+            used_at: None,
+            scope,
+            nonce,
+        };
+        (authorization_code, code)
     }
 
-    // Verify a JWT and return the associated claims
-    pub fn verify_jwt(&self, token: &str) -> Result<Claims, AuthError> {
-        decode::<Claims>(
-            token,
-            &DecodingKey::from_secret(self.secret.as_ref()),
-            &self.validation,
-        )
-        .map(|data| data.claims)
-        .map_err(|err| AuthError::JwtError(err))
+    pub fn is_expired(&self) -> bool {
+        self.expires_at < Utc::now()
     }
+
+    /// Checks a just-redeemed code against the client presenting it. Confidential clients
+    /// must have been authenticated already; public ones need the PKCE verifier.
+    pub fn check(
+        &self,
+        client_id: &str,
+        confidential: bool,
+        code_verifier: Option<&str>,
+    ) -> Result<(), AuthError> {
+        if self.is_expired() {
+            return Err(AuthError::InvalidGrant("code has expired".into()));
+        }
+        if self.client_id != client_id {
+            return Err(AuthError::InvalidGrant(
+                "code was issued to a different client".into(),
+            ));
+        }
+        match (&self.code_challenge, code_verifier) {
+            (Some(challenge), Some(verifier)) => {
+                if !bool::from(
+                    pkce_challenge(verifier)
+                        .as_bytes()
+                        .ct_eq(challenge.as_bytes()),
+                ) {
+                    return Err(AuthError::InvalidGrant(
+                        "code verifier does not match".into(),
+                    ));
+                }
+            }
+            (Some(_), None) => {
+                return Err(AuthError::InvalidGrant("missing code verifier".into()));
+            }
+            (None, _) if !confidential => {
+                return Err(AuthError::InvalidGrant("missing code challenge".into()));
+            }
+            (None, _) => {}
+        }
+        Ok(())
+    }
+}
+
+/// An application registered to sign users in through KuriLogin's OpenID Connect endpoints.
+#[derive(FromRow, Debug, Clone, Serialize)]
+pub struct RegisteredClient {
+    pub client_id: String,
+    pub name: String,
+    #[serde(skip)]
+    pub secret_hash: Option<String>,
+    pub redirect_uris: Vec<String>,
+    pub allowed_scopes: Vec<String>,
+    /// Whether the client may receive codes without asking the user. KuriLogin has no consent
+    /// step, so `/oauth/authorize` only serves trusted clients; others can still introspect
+    /// and revoke tokens.
+    pub trusted: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+impl RegisteredClient {
+    /// Creates a client and returns it with its plaintext secret, which is shown only once.
+    /// Public clients (single-page and native apps) get no secret.
+    pub fn register(
+        name: String,
+        redirect_uris: Vec<String>,
+        allowed_scopes: Vec<String>,
+        confidential: bool,
+        trusted: bool,
+    ) -> (Self, Option<String>) {
+        let secret = confidential.then(|| generate_token(32));
+        let client = RegisteredClient {
+            client_id: generate_token(16),
+            name,
+            secret_hash: secret.as_deref().map(hash_token),
+            redirect_uris,
+            allowed_scopes,
+            trusted,
+            created_at: Utc::now(),
+        };
+        (client, secret)
+    }
+
+    pub fn is_confidential(&self) -> bool {
+        self.secret_hash.is_some()
+    }
+
+    /// Whether `secret` is this client's secret. Public clients have none to check.
+    pub fn authenticate(&self, secret: Option<&str>) -> bool {
+        secret_matches(self.secret_hash.as_deref(), secret)
+    }
+
+    pub fn allows_scope(&self, scope: &str) -> bool {
+        scope
+            .split_whitespace()
+            .all(|scope| self.allowed_scopes.iter().any(|allowed| allowed == scope))
+    }
+}
+
+/// The query of an OpenID Connect authentication request to `/oauth/authorize`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AuthorizeRequest {
+    pub response_type: Option<String>,
+    pub client_id: String,
+    pub redirect_uri: Option<String>,
+    pub scope: Option<String>,
+    pub state: Option<String>,
+    pub nonce: Option<String>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
+    pub prompt: Option<String>,
+    /// Upstream provider to sign in with, instead of the default one.
+    pub provider: Option<String>,
+}
+
+impl AuthorizeRequest {
+    /// The registered redirect URI the request names, or the client's only one. Until it is
+    /// known, errors cannot be sent back to the client.
+    pub fn redirect_uri_for(&self, client: &RegisteredClient) -> Result<String, AuthError> {
+        match &self.redirect_uri {
+            Some(redirect_uri) if client.redirect_uris.contains(redirect_uri) => {
+                Ok(redirect_uri.clone())
+            }
+            None if client.redirect_uris.len() == 1 => Ok(client.redirect_uris[0].clone()),
+            redirect_uri => Err(AuthError::InvalidRedirectUri(
+                redirect_uri.clone().unwrap_or_default(),
+            )),
+        }
+    }
+
+    /// Checks the request against the client's registration and returns the scope to grant,
+    /// or the OAuth `error` and description to send back.
+    pub fn check(&self, client: &RegisteredClient) -> Result<String, (&'static str, &'static str)> {
+        if self.response_type.as_deref() != Some("code") {
+            return Err((
+                "unsupported_response_type",
+                "only the code flow is supported",
+            ));
+        }
+        if !client.trusted {
+            return Err((
+                "consent_required",
+                "the client is not trusted to sign users in without their consent",
+            ));
+        }
+        let scope = self.scope.as_deref().unwrap_or("openid");
+        if !client.allows_scope(scope) {
+            return Err(("invalid_scope", "the client may not request this scope"));
+        }
+        match (&self.code_challenge, self.code_challenge_method.as_deref()) {
+            (Some(_), Some("S256")) => {}
+            (Some(_), _) => {
+                return Err((
+                    "invalid_request",
+                    "only the S256 code challenge method is supported",
+                ));
+            }
+            (None, _) if !client.is_confidential() => {
+                return Err((
+                    "invalid_request",
+                    "public clients must send a code challenge",
+                ));
+            }
+            (None, _) => {}
+        }
+        Ok(scope.to_string())
+    }
+}
+
+/// Where `/oauth/authorize` sends the browser next.
+pub enum AuthorizeOutcome {
+    /// Back to the client, with a code or an OAuth error.
+    Redirect(String),
+    /// To an upstream login, which returns to the same authorization request afterwards.
+    Login(AuthorizationRedirect),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Claims {
-    pub sub: i32,
+/// The claims of an OpenID Connect ID token.
+#[derive(Debug, Serialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub aud: String,
     pub exp: i64,
+    pub iat: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+    /// Carries `sub` and the profile claims.
+    #[serde(flatten)]
+    pub user_info: UserInfo,
+}
+
+/// The `/oauth/userinfo` response: the subject plus the claims its scopes cover.
+#[derive(Debug, Default, Serialize)]
+pub struct UserInfo {
+    pub sub: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub picture: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+}
+
+/// An RFC 7662 introspection response. Inactive tokens reveal nothing but `active`.
+#[derive(Debug, Default, Serialize)]
+pub struct TokenIntrospection {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iat: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jti: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::user::UserBuilder;
+
+    fn client(secret: Option<&str>) -> ClientSettings {
+        ClientSettings {
+            client_id: "web".to_string(),
+            secret_hash: secret.map(hash_token),
+            delivery: TokenDelivery::Code,
+            redirect_urls: vec!["https://app.test/callback".to_string()],
+        }
+    }
+
+    fn registered(confidential: bool, trusted: bool) -> RegisteredClient {
+        let (client, _) = RegisteredClient::register(
+            "App".to_string(),
+            vec!["https://app.test/callback".to_string()],
+            vec!["openid".to_string(), "email".to_string()],
+            confidential,
+            trusted,
+        );
+        client
+    }
+
+    fn request(scope: Option<&str>, code_challenge: Option<&str>) -> AuthorizeRequest {
+        AuthorizeRequest {
+            response_type: Some("code".to_string()),
+            client_id: "app".to_string(),
+            redirect_uri: None,
+            scope: scope.map(String::from),
+            state: None,
+            nonce: None,
+            code_challenge: code_challenge.map(String::from),
+            code_challenge_method: code_challenge.map(|_| "S256".to_string()),
+            prompt: None,
+            provider: None,
+        }
+    }
+
+    fn code(client_id: &str, code_verifier: Option<&str>) -> AuthorizationCode {
+        let (code, _) = AuthorizationCode::issue(
+            client_id.to_string(),
+            1,
+            "family".to_string(),
+            code_verifier.map(pkce_challenge),
+            "https://app.test/callback".to_string(),
+            Some("openid".to_string()),
+            None,
+        );
+        code
+    }
+
+    #[test]
+    fn confidential_clients_need_their_secret() {
+        let client = client(Some("s3cret"));
+        assert!(client.is_confidential());
+        assert!(client.authenticate(Some("s3cret")));
+        assert!(!client.authenticate(Some("other")));
+        assert!(!client.authenticate(None));
+    }
+
+    #[test]
+    fn empty_secrets_never_authenticate() {
+        assert!(!client(Some("s3cret")).authenticate(Some("")));
+        // A secret configured as `CLIENT_WEB_SECRET=` must not turn `client_secret=` into a
+        // way around PKCE.
+        assert!(!client(Some("")).authenticate(Some("")));
+        assert!(!client(Some("")).authenticate(None));
+    }
+
+    #[test]
+    fn public_clients_have_no_secret_to_check() {
+        assert!(!client(None).is_confidential());
+        assert!(client(None).authenticate(None));
+    }
+
+    #[test]
+    fn registered_clients_check_secrets_like_configured_ones() {
+        let (client, secret) = RegisteredClient::register(
+            "App".to_string(),
+            vec!["https://app.test/callback".to_string()],
+            vec!["openid".to_string()],
+            true,
+            true,
+        );
+        let secret = secret.expect("confidential clients get a secret");
+        assert!(client.authenticate(Some(&secret)));
+        assert!(!client.authenticate(Some("")));
+        assert!(!client.authenticate(None));
+    }
+
+    #[test]
+    fn scopes_are_limited_to_the_registered_ones() {
+        let client = registered(true, true);
+        assert!(client.allows_scope("openid email"));
+        assert!(!client.allows_scope("openid profile"));
+    }
+
+    #[test]
+    fn redirect_uri_must_be_registered() {
+        let client = registered(true, true);
+        let mut request = request(None, None);
+        assert_eq!(
+            request.redirect_uri_for(&client).unwrap(),
+            "https://app.test/callback"
+        );
+        request.redirect_uri = Some("https://evil.test/callback".to_string());
+        assert!(matches!(
+            request.redirect_uri_for(&client),
+            Err(AuthError::InvalidRedirectUri(_))
+        ));
+    }
+
+    #[test]
+    fn authorize_grants_the_requested_scope() {
+        let client = registered(true, true);
+        assert_eq!(request(None, None).check(&client).unwrap(), "openid");
+        assert_eq!(
+            request(Some("openid email"), None).check(&client).unwrap(),
+            "openid email"
+        );
+        assert_eq!(
+            request(Some("openid profile"), None).check(&client),
+            Err(("invalid_scope", "the client may not request this scope"))
+        );
+    }
+
+    #[test]
+    fn authorize_only_serves_trusted_clients() {
+        let (error, _) = request(None, None)
+            .check(&registered(true, false))
+            .unwrap_err();
+        assert_eq!(error, "consent_required");
+    }
+
+    #[test]
+    fn authorize_needs_pkce_from_public_clients() {
+        let client = registered(false, true);
+        let (error, _) = request(None, None).check(&client).unwrap_err();
+        assert_eq!(error, "invalid_request");
+        assert!(request(None, Some("challenge")).check(&client).is_ok());
+
+        let mut plain = request(None, Some("challenge"));
+        plain.code_challenge_method = Some("plain".to_string());
+        assert_eq!(plain.check(&client).unwrap_err().0, "invalid_request");
+    }
+
+    #[test]
+    fn authorize_only_supports_the_code_flow() {
+        let mut request = request(None, None);
+        request.response_type = Some("token".to_string());
+        let (error, _) = request.check(&registered(true, true)).unwrap_err();
+        assert_eq!(error, "unsupported_response_type");
+    }
+
+    #[test]
+    fn logins_are_bound_to_the_browser_holding_the_state_cookie() {
+        let pending = PendingAuthorizationBuilder::new()
+            .state("state")
+            .provider_id(1)
+            .created_at(Utc::now())
+            .build();
+        assert!(pending.is_bound_to(Some(&hash_token("state"))));
+        assert!(!pending.is_bound_to(Some(&hash_token("other"))));
+        // The state itself is in the callback URL; the cookie must hold its hash.
+        assert!(!pending.is_bound_to(Some("state")));
+        assert!(!pending.is_bound_to(None));
+    }
+
+    #[test]
+    fn account_tokens_are_stored_hashed_and_expire_by_purpose() {
+        let (account_token, token) = AccountToken::issue(7, AccountTokenPurpose::ResetPassword);
+        assert_eq!(account_token.token_hash, hash_token(&token));
+        assert_eq!(account_token.purpose, "reset_password");
+        assert_eq!(
+            account_token.expires_at - account_token.created_at,
+            chrono::Duration::hours(1)
+        );
+        let (account_token, _) = AccountToken::issue(7, AccountTokenPurpose::VerifyEmail);
+        assert_eq!(
+            account_token.expires_at - account_token.created_at,
+            chrono::Duration::hours(24)
+        );
+        assert!(account_token.is_redeemable());
+    }
+
+    #[test]
+    fn redeemed_or_expired_account_tokens_are_refused() {
+        let (mut redeemed, _) = AccountToken::issue(7, AccountTokenPurpose::ResetPassword);
+        redeemed.used_at = Some(Utc::now());
+        assert!(!redeemed.is_redeemable());
+
+        let (mut expired, _) = AccountToken::issue(7, AccountTokenPurpose::ResetPassword);
+        expired.expires_at = Utc::now() - chrono::Duration::seconds(1);
+        assert!(!expired.is_redeemable());
+    }
+
+    fn profile(email_verified: bool) -> UserProfile {
+        UserProfile {
+            provider_user_id: "42".to_string(),
+            email: Some("ada@example.com".to_string()),
+            email_verified,
+            display_name: None,
+            given_name: None,
+            family_name: None,
+            avatar_url: None,
+            locale: None,
+            raw_claims: Value::Null,
+        }
+    }
+
+    #[test]
+    fn email_linking_needs_both_emails_verified() {
+        let verified = UserBuilder::new()
+            .email("ada@example.com")
+            .email_verified(true)
+            .build();
+        let unverified = UserBuilder::new().email("ada@example.com").build();
+        assert!(EmailLinking::Verified.allows(&profile(true), &verified));
+        assert!(!EmailLinking::Verified.allows(&profile(false), &verified));
+        assert!(!EmailLinking::Verified.allows(&profile(true), &unverified));
+        assert!(!EmailLinking::Never.allows(&profile(true), &verified));
+    }
+
+    fn policy() -> PasswordPolicy {
+        PasswordPolicy {
+            min_length: 12,
+            max_length: 64,
+            blocklist: Arc::new(PasswordPolicy::parse_blocklist(
+                "correcthorsebatterystaple\n\n  Password1234  \n",
+            )),
+        }
+    }
+
+    #[test]
+    fn password_policy_accepts_long_unlisted_passwords() {
+        assert!(policy()
+            .check("plum kettle orbit 42", "ada@example.com")
+            .is_ok());
+    }
+
+    #[test]
+    fn password_policy_enforces_the_length_bounds() {
+        let policy = policy();
+        assert!(policy.check("short pass1", "ada@example.com").is_err());
+        assert!(policy.check("twelve chars", "ada@example.com").is_ok());
+        assert!(policy.check(&"ab".repeat(33), "ada@example.com").is_err());
+    }
+
+    #[test]
+    fn password_policy_refuses_blocklisted_passwords_in_any_case() {
+        let policy = policy();
+        assert_eq!(policy.blocklist.len(), 2);
+        assert!(policy
+            .check("CorrectHorseBatteryStaple", "ada@example.com")
+            .is_err());
+        assert!(policy.check("password1234", "ada@example.com").is_err());
+    }
+
+    #[test]
+    fn password_policy_refuses_repetition_and_the_email() {
+        let policy = policy();
+        assert!(policy.check("zzzzzzzzzzzzzz", "ada@example.com").is_err());
+        assert!(policy
+            .check("lovelace-plum-kettle", "lovelace@example.com")
+            .is_err());
+    }
+
+    #[test]
+    fn codes_check_the_pkce_verifier() {
+        let code = code("app", Some("verifier"));
+        assert!(code.check("app", false, Some("verifier")).is_ok());
+        assert!(matches!(
+            code.check("app", false, Some("other")),
+            Err(AuthError::InvalidGrant(_))
+        ));
+        assert!(matches!(
+            code.check("app", true, None),
+            Err(AuthError::InvalidGrant(_))
+        ));
+    }
+
+    #[test]
+    fn codes_without_a_challenge_need_a_confidential_client() {
+        let code = code("app", None);
+        assert!(code.check("app", true, None).is_ok());
+        assert!(matches!(
+            code.check("app", false, None),
+            Err(AuthError::InvalidGrant(_))
+        ));
+    }
+
+    #[test]
+    fn codes_are_bound_to_their_client_and_expire() {
+        let mut code = code("app", None);
+        assert!(matches!(
+            code.check("other", true, None),
+            Err(AuthError::InvalidGrant(_))
+        ));
+        code.expires_at = Utc::now() - chrono::Duration::seconds(1);
+        assert!(matches!(
+            code.check("app", true, None),
+            Err(AuthError::InvalidGrant(_))
+        ));
+    }
 }
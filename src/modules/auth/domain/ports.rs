@@ -1,27 +1,201 @@
-use super::{AuthError, OAuthAuthorization};
+use super::{
+    AccountToken, AuthError, AuthorizationCode, AuthorizationRequest, Email, OAuthAuthorization,
+    PendingAuthorization, ProviderTokenResponse, RefreshToken, RegisteredClient, SigningKeyRecord,
+    UserCredentials, UserProfile,
+};
 use async_trait::async_trait;
-use oauth2::{basic::BasicTokenType, CsrfToken, EmptyExtraTokenFields, StandardTokenResponse};
-
-use serde_json::Value;
+use chrono::{DateTime, Utc};
+use oauth2::PkceCodeVerifier;
 
 #[async_trait]
 pub trait Provider: Send + Sync {
-    /// Generates the URL to which the user should be redirected to initiate the OAuth flow.
-    async fn get_authorization_url(&self) -> (String, CsrfToken);
+    /// Generates the URL to which the user should be redirected to initiate the OAuth flow,
+    /// along with the state and the PKCE verifier that must be kept until the callback.
+    async fn get_authorization_url(&self) -> AuthorizationRequest;
 
     /// Handles the exchange of the authorization code for an access token.
     async fn exchange_token(
         &self,
         code: String,
-    ) -> Result<StandardTokenResponse<EmptyExtraTokenFields, BasicTokenType>, AuthError>;
+        pkce_verifier: PkceCodeVerifier,
+    ) -> Result<ProviderTokenResponse, AuthError>;
+
+    /// Fetches the user's profile for a token response, verifying the provider's `id_token`
+    /// (including `nonce`) when it issues one.
+    async fn fetch_user_profile(
+        &self,
+        token_response: &ProviderTokenResponse,
+        nonce: Option<&str>,
+    ) -> Result<UserProfile, AuthError>;
 
-    /// Fetches the user data using the access token.
-    async fn fetch_user_info(&self, access_token: String) -> Result<Value, reqwest::Error>;
+    /// Revokes the tokens the provider issued for a stored authorization. Providers without
+    /// a revocation endpoint keep the default, which does nothing.
+    async fn revoke_authorization(
+        &self,
+        _authorization: &OAuthAuthorization,
+    ) -> Result<(), AuthError> {
+        Ok(())
+    }
 
     fn provider_id(&self) -> i32;
+
+    /// The name used in the provider's routes, e.g. `/auth/{name}/login`.
+    fn name(&self) -> &str;
 }
 
 #[async_trait]
 pub trait Repository: Send + Sync {
-    async fn upsert_oauth(&self, auth: &OAuthAuthorization) -> Result<(), AuthError>;
+    /// Stores the authorization of a provider account, keyed by provider and subject, and
+    /// returns the stored row.
+    async fn upsert_oauth(
+        &self,
+        auth: &OAuthAuthorization,
+    ) -> Result<OAuthAuthorization, AuthError>;
+
+    /// The authorization of a provider account, whichever user it belongs to. Subject ids are
+    /// only unique within their provider.
+    async fn find_oauth_by_subject(
+        &self,
+        provider_id: i32,
+        provider_user_id: &str,
+    ) -> Result<Option<OAuthAuthorization>, AuthError>;
+
+    async fn list_oauth_authorizations(
+        &self,
+        user_id: i32,
+    ) -> Result<Vec<OAuthAuthorization>, AuthError>;
+
+    /// Returns whether the user had an authorization with that id.
+    async fn delete_oauth_authorization(
+        &self,
+        user_id: i32,
+        auth_id: i32,
+    ) -> Result<bool, AuthError>;
+
+    /// The user's most recently updated authorization with a provider.
+    async fn find_oauth_authorization(
+        &self,
+        user_id: i32,
+        provider_id: i32,
+    ) -> Result<Option<OAuthAuthorization>, AuthError>;
+
+    async fn insert_credentials(&self, user_id: i32, password_hash: &str) -> Result<(), AuthError>;
+
+    async fn find_credentials(&self, user_id: i32) -> Result<Option<UserCredentials>, AuthError>;
+
+    async fn update_password_hash(
+        &self,
+        user_id: i32,
+        password_hash: &str,
+    ) -> Result<(), AuthError>;
+
+    async fn mark_email_verified(&self, user_id: i32) -> Result<(), AuthError>;
+
+    async fn insert_account_token(&self, token: &AccountToken) -> Result<(), AuthError>;
+
+    /// An unexpired, unused token.
+    async fn find_account_token(
+        &self,
+        token_hash: &str,
+        purpose: &str,
+    ) -> Result<Option<AccountToken>, AuthError>;
+
+    /// Marks an unexpired, unused token as used and returns it; `None` if it cannot be used.
+    async fn redeem_account_token(
+        &self,
+        token_hash: &str,
+        purpose: &str,
+    ) -> Result<Option<AccountToken>, AuthError>;
+
+    /// Invalidates the user's outstanding tokens for `purpose`, e.g. when a new one is sent.
+    async fn delete_account_tokens(&self, user_id: i32, purpose: &str) -> Result<u64, AuthError>;
+
+    async fn delete_account_tokens_before(
+        &self,
+        expired_before: DateTime<Utc>,
+    ) -> Result<u64, AuthError>;
+
+    /// Makes sure `oauth_providers` has a row for a configured provider.
+    async fn register_provider(&self, provider_id: i32, name: &str) -> Result<(), AuthError>;
+
+    async fn insert_pending_authorization(
+        &self,
+        pending: &PendingAuthorization,
+    ) -> Result<(), AuthError>;
+
+    /// Removes and returns the pending authorization for `state`, so each state is usable once.
+    async fn take_pending_authorization(
+        &self,
+        state: &str,
+    ) -> Result<Option<PendingAuthorization>, AuthError>;
+
+    async fn delete_pending_authorizations_before(
+        &self,
+        created_before: DateTime<Utc>,
+    ) -> Result<u64, AuthError>;
+
+    async fn insert_signing_key(&self, key: &SigningKeyRecord) -> Result<(), AuthError>;
+
+    async fn list_signing_keys(&self) -> Result<Vec<SigningKeyRecord>, AuthError>;
+
+    /// Makes `kid` the current key and schedules the previous current key to retire at
+    /// `previous_retires_at`.
+    async fn promote_signing_key(
+        &self,
+        kid: &str,
+        previous_retires_at: DateTime<Utc>,
+    ) -> Result<Option<SigningKeyRecord>, AuthError>;
+
+    async fn retire_signing_key(
+        &self,
+        kid: &str,
+        retires_at: DateTime<Utc>,
+    ) -> Result<Option<SigningKeyRecord>, AuthError>;
+
+    async fn insert_refresh_token(&self, token: &RefreshToken) -> Result<(), AuthError>;
+
+    async fn find_refresh_token(&self, token_hash: &str)
+        -> Result<Option<RefreshToken>, AuthError>;
+
+    /// Marks a refresh token as rotated. Returns `false` if it was already used or revoked.
+    async fn mark_refresh_token_used(&self, token_id: i32) -> Result<bool, AuthError>;
+
+    async fn revoke_refresh_token_family(&self, family_id: &str) -> Result<u64, AuthError>;
+
+    async fn revoke_user_refresh_tokens(&self, user_id: i32) -> Result<u64, AuthError>;
+
+    async fn insert_authorization_code(&self, code: &AuthorizationCode) -> Result<(), AuthError>;
+
+    /// Marks a code as redeemed and returns it. Returns `None` for unknown or used codes.
+    async fn redeem_authorization_code(
+        &self,
+        code_hash: &str,
+    ) -> Result<Option<AuthorizationCode>, AuthError>;
+
+    async fn find_authorization_code(
+        &self,
+        code_hash: &str,
+    ) -> Result<Option<AuthorizationCode>, AuthError>;
+
+    async fn delete_authorization_codes_before(
+        &self,
+        expired_before: DateTime<Utc>,
+    ) -> Result<u64, AuthError>;
+
+    async fn insert_client(&self, client: &RegisteredClient) -> Result<(), AuthError>;
+
+    async fn find_client(&self, client_id: &str) -> Result<Option<RegisteredClient>, AuthError>;
+
+    async fn list_clients(&self) -> Result<Vec<RegisteredClient>, AuthError>;
+
+    /// Returns whether a client was deleted.
+    async fn delete_client(&self, client_id: &str) -> Result<bool, AuthError>;
+}
+
+pub use kuri_auth::RevocationStore;
+
+/// Delivers the emails of account verification and password reset.
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: &Email) -> Result<(), AuthError>;
 }
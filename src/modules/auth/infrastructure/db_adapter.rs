@@ -1,37 +1,733 @@
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 
 use crate::{
-    modules::auth::{ports::Repository, AuthError, OAuthAuthorization},
+    modules::auth::{
+        ports::{Repository, RevocationStore},
+        AccountToken, AuthError, AuthorizationCode, Claims, OAuthAuthorization,
+        PendingAuthorization, RefreshToken, RegisteredClient, SigningKeyRecord, UserCredentials,
+    },
     utils::postgres::PostgresRepository,
 };
 
 #[async_trait]
 impl Repository for PostgresRepository {
-    async fn upsert_oauth(&self, authorization: &OAuthAuthorization) -> Result<(), AuthError> {
+    async fn upsert_oauth(
+        &self,
+        authorization: &OAuthAuthorization,
+    ) -> Result<OAuthAuthorization, AuthError> {
         let query = "
             INSERT INTO oauth_authorizations (user_id, provider_id, provider_user_id, access_token, refresh_token, expires_in, scope, created_at, updated_at)
             VALUES ($1, $2, $3, $4, $5, $6, $7, NOW(), NOW())
-            ON CONFLICT (provider_user_id) DO UPDATE
-            SET access_token = EXCLUDED.access_token, refresh_token = EXCLUDED.refresh_token, expires_in = EXCLUDED.expires_in, scope = EXCLUDED.scope, updated_at = NOW()
+            ON CONFLICT (provider_id, provider_user_id) DO UPDATE
+            SET access_token = EXCLUDED.access_token, refresh_token = COALESCE(EXCLUDED.refresh_token, oauth_authorizations.refresh_token), expires_in = EXCLUDED.expires_in, scope = EXCLUDED.scope, updated_at = NOW()
             RETURNING *;
         ";
         let result = sqlx::query_as::<_, OAuthAuthorization>(query)
-            .bind(&authorization.user_id)
-            .bind(&authorization.provider_id)
+            .bind(authorization.user_id)
+            .bind(authorization.provider_id)
             .bind(&authorization.provider_user_id)
             .bind(&authorization.access_token)
             .bind(&authorization.refresh_token)
-            .bind(&authorization.expires_in)
+            .bind(authorization.expires_in)
             .bind(&authorization.scope)
             .fetch_one(&*self.pg_pool)
             .await;
 
         match result {
-            Ok(_record) => Ok(()),
+            Ok(record) => Ok(record),
             Err(e) => {
                 log::error!("Failed to upsert oauth authorization: {}", e);
                 Err(AuthError::from(e))
             }
         }
     }
+
+    async fn find_oauth_by_subject(
+        &self,
+        provider_id: i32,
+        provider_user_id: &str,
+    ) -> Result<Option<OAuthAuthorization>, AuthError> {
+        let query = "
+            SELECT * FROM oauth_authorizations
+            WHERE provider_id = $1 AND provider_user_id = $2;
+        ";
+        sqlx::query_as::<_, OAuthAuthorization>(query)
+            .bind(provider_id)
+            .bind(provider_user_id)
+            .fetch_optional(&*self.pg_pool)
+            .await
+            .map_err(AuthError::from)
+    }
+
+    async fn list_oauth_authorizations(
+        &self,
+        user_id: i32,
+    ) -> Result<Vec<OAuthAuthorization>, AuthError> {
+        let query = "
+            SELECT * FROM oauth_authorizations WHERE user_id = $1 ORDER BY created_at;
+        ";
+        sqlx::query_as::<_, OAuthAuthorization>(query)
+            .bind(user_id)
+            .fetch_all(&*self.pg_pool)
+            .await
+            .map_err(AuthError::from)
+    }
+
+    async fn delete_oauth_authorization(
+        &self,
+        user_id: i32,
+        auth_id: i32,
+    ) -> Result<bool, AuthError> {
+        let query = "
+            DELETE FROM oauth_authorizations WHERE user_id = $1 AND auth_id = $2;
+        ";
+        sqlx::query(query)
+            .bind(user_id)
+            .bind(auth_id)
+            .execute(&*self.pg_pool)
+            .await
+            .map(|result| result.rows_affected() > 0)
+            .map_err(AuthError::from)
+    }
+
+    async fn find_oauth_authorization(
+        &self,
+        user_id: i32,
+        provider_id: i32,
+    ) -> Result<Option<OAuthAuthorization>, AuthError> {
+        let query = "
+            SELECT * FROM oauth_authorizations
+            WHERE user_id = $1 AND provider_id = $2
+            ORDER BY updated_at DESC
+            LIMIT 1;
+        ";
+        sqlx::query_as::<_, OAuthAuthorization>(query)
+            .bind(user_id)
+            .bind(provider_id)
+            .fetch_optional(&*self.pg_pool)
+            .await
+            .map_err(AuthError::from)
+    }
+
+    async fn insert_credentials(&self, user_id: i32, password_hash: &str) -> Result<(), AuthError> {
+        let query = "
+            INSERT INTO user_credentials (user_id, password_hash, created_at, updated_at)
+            VALUES ($1, $2, NOW(), NOW());
+        ";
+        sqlx::query(query)
+            .bind(user_id)
+            .bind(password_hash)
+            .execute(&*self.pg_pool)
+            .await
+            .map(|_| ())
+            .map_err(AuthError::from)
+    }
+
+    async fn find_credentials(&self, user_id: i32) -> Result<Option<UserCredentials>, AuthError> {
+        let query = "
+            SELECT * FROM user_credentials WHERE user_id = $1;
+        ";
+        sqlx::query_as::<_, UserCredentials>(query)
+            .bind(user_id)
+            .fetch_optional(&*self.pg_pool)
+            .await
+            .map_err(AuthError::from)
+    }
+
+    async fn update_password_hash(
+        &self,
+        user_id: i32,
+        password_hash: &str,
+    ) -> Result<(), AuthError> {
+        let query = "
+            UPDATE user_credentials SET password_hash = $2, updated_at = NOW()
+            WHERE user_id = $1;
+        ";
+        sqlx::query(query)
+            .bind(user_id)
+            .bind(password_hash)
+            .execute(&*self.pg_pool)
+            .await
+            .map(|_| ())
+            .map_err(AuthError::from)
+    }
+
+    async fn mark_email_verified(&self, user_id: i32) -> Result<(), AuthError> {
+        let query = "
+            UPDATE user_credentials SET email_verified_at = COALESCE(email_verified_at, NOW())
+            WHERE user_id = $1;
+        ";
+        sqlx::query(query)
+            .bind(user_id)
+            .execute(&*self.pg_pool)
+            .await
+            .map(|_| ())
+            .map_err(AuthError::from)
+    }
+
+    async fn insert_account_token(&self, token: &AccountToken) -> Result<(), AuthError> {
+        let query = "
+            INSERT INTO account_tokens (token_hash, user_id, purpose, created_at, expires_at)
+            VALUES ($1, $2, $3, $4, $5);
+        ";
+        sqlx::query(query)
+            .bind(&token.token_hash)
+            .bind(token.user_id)
+            .bind(&token.purpose)
+            .bind(token.created_at)
+            .bind(token.expires_at)
+            .execute(&*self.pg_pool)
+            .await
+            .map(|_| ())
+            .map_err(|e| {
+                log::error!("Failed to insert account token: {}", e);
+                AuthError::from(e)
+            })
+    }
+
+    async fn find_account_token(
+        &self,
+        token_hash: &str,
+        purpose: &str,
+    ) -> Result<Option<AccountToken>, AuthError> {
+        let query = "
+            SELECT * FROM account_tokens
+            WHERE token_hash = $1 AND purpose = $2 AND used_at IS NULL AND expires_at > NOW();
+        ";
+        sqlx::query_as::<_, AccountToken>(query)
+            .bind(token_hash)
+            .bind(purpose)
+            .fetch_optional(&*self.pg_pool)
+            .await
+            .map_err(AuthError::from)
+    }
+
+    async fn redeem_account_token(
+        &self,
+        token_hash: &str,
+        purpose: &str,
+    ) -> Result<Option<AccountToken>, AuthError> {
+        let query = "
+            UPDATE account_tokens SET used_at = NOW()
+            WHERE token_hash = $1 AND purpose = $2 AND used_at IS NULL AND expires_at > NOW()
+            RETURNING *;
+        ";
+        sqlx::query_as::<_, AccountToken>(query)
+            .bind(token_hash)
+            .bind(purpose)
+            .fetch_optional(&*self.pg_pool)
+            .await
+            .map_err(AuthError::from)
+    }
+
+    async fn delete_account_tokens(&self, user_id: i32, purpose: &str) -> Result<u64, AuthError> {
+        let query = "
+            DELETE FROM account_tokens WHERE user_id = $1 AND purpose = $2;
+        ";
+        sqlx::query(query)
+            .bind(user_id)
+            .bind(purpose)
+            .execute(&*self.pg_pool)
+            .await
+            .map(|result| result.rows_affected())
+            .map_err(AuthError::from)
+    }
+
+    async fn delete_account_tokens_before(
+        &self,
+        expired_before: DateTime<Utc>,
+    ) -> Result<u64, AuthError> {
+        let query = "
+            DELETE FROM account_tokens WHERE expires_at < $1;
+        ";
+        sqlx::query(query)
+            .bind(expired_before)
+            .execute(&*self.pg_pool)
+            .await
+            .map(|result| result.rows_affected())
+            .map_err(AuthError::from)
+    }
+
+    async fn register_provider(&self, provider_id: i32, name: &str) -> Result<(), AuthError> {
+        let query = "
+            INSERT INTO oauth_providers (provider_id, name)
+            VALUES ($1, $2)
+            ON CONFLICT (provider_id) DO UPDATE
+            SET name = EXCLUDED.name;
+        ";
+        sqlx::query(query)
+            .bind(provider_id)
+            .bind(name)
+            .execute(&*self.pg_pool)
+            .await
+            .map(|_| ())
+            .map_err(|e| {
+                log::error!("Failed to register provider {}: {}", name, e);
+                AuthError::from(e)
+            })
+    }
+
+    async fn insert_pending_authorization(
+        &self,
+        pending: &PendingAuthorization,
+    ) -> Result<(), AuthError> {
+        let query = "
+            INSERT INTO pending_authorizations
+                (state, provider_id, return_to, pkce_verifier, nonce, client_id, code_challenge,
+                 link_user_id, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9);
+        ";
+        sqlx::query(query)
+            .bind(&pending.state)
+            .bind(pending.provider_id)
+            .bind(&pending.return_to)
+            .bind(&pending.pkce_verifier)
+            .bind(&pending.nonce)
+            .bind(&pending.client_id)
+            .bind(&pending.code_challenge)
+            .bind(pending.link_user_id)
+            .bind(pending.created_at)
+            .execute(&*self.pg_pool)
+            .await
+            .map(|_| ())
+            .map_err(|e| {
+                log::error!("Failed to insert pending authorization: {}", e);
+                AuthError::from(e)
+            })
+    }
+
+    async fn take_pending_authorization(
+        &self,
+        state: &str,
+    ) -> Result<Option<PendingAuthorization>, AuthError> {
+        let query = "
+            DELETE FROM pending_authorizations WHERE state = $1
+            RETURNING *;
+        ";
+        sqlx::query_as::<_, PendingAuthorization>(query)
+            .bind(state)
+            .fetch_optional(&*self.pg_pool)
+            .await
+            .map_err(AuthError::from)
+    }
+
+    async fn delete_pending_authorizations_before(
+        &self,
+        created_before: DateTime<Utc>,
+    ) -> Result<u64, AuthError> {
+        let query = "
+            DELETE FROM pending_authorizations WHERE created_at < $1;
+        ";
+        sqlx::query(query)
+            .bind(created_before)
+            .execute(&*self.pg_pool)
+            .await
+            .map(|result| result.rows_affected())
+            .map_err(AuthError::from)
+    }
+
+    async fn insert_signing_key(&self, key: &SigningKeyRecord) -> Result<(), AuthError> {
+        let query = "
+            INSERT INTO signing_keys (kid, algorithm, key_material, created_at)
+            VALUES ($1, $2, $3, $4);
+        ";
+        sqlx::query(query)
+            .bind(&key.kid)
+            .bind(&key.algorithm)
+            .bind(&key.key_material)
+            .bind(key.created_at)
+            .execute(&*self.pg_pool)
+            .await
+            .map(|_| ())
+            .map_err(|e| {
+                log::error!("Failed to insert signing key: {}", e);
+                AuthError::from(e)
+            })
+    }
+
+    async fn list_signing_keys(&self) -> Result<Vec<SigningKeyRecord>, AuthError> {
+        let query = "
+            SELECT * FROM signing_keys ORDER BY created_at;
+        ";
+        sqlx::query_as::<_, SigningKeyRecord>(query)
+            .fetch_all(&*self.pg_pool)
+            .await
+            .map_err(AuthError::from)
+    }
+
+    async fn promote_signing_key(
+        &self,
+        kid: &str,
+        previous_retires_at: DateTime<Utc>,
+    ) -> Result<Option<SigningKeyRecord>, AuthError> {
+        let mut tx = self.pg_pool.begin().await?;
+
+        let retire_previous = "
+            UPDATE signing_keys SET retires_at = $2
+            WHERE promoted_at IS NOT NULL AND retires_at IS NULL AND kid <> $1;
+        ";
+        sqlx::query(retire_previous)
+            .bind(kid)
+            .bind(previous_retires_at)
+            .execute(&mut *tx)
+            .await?;
+
+        let promote = "
+            UPDATE signing_keys SET promoted_at = NOW(), retires_at = NULL
+            WHERE kid = $1
+            RETURNING *;
+        ";
+        let promoted = sqlx::query_as::<_, SigningKeyRecord>(promote)
+            .bind(kid)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+        // Leave the previous key untouched when there was nothing to promote.
+        if promoted.is_some() {
+            tx.commit().await?;
+        }
+        Ok(promoted)
+    }
+
+    async fn retire_signing_key(
+        &self,
+        kid: &str,
+        retires_at: DateTime<Utc>,
+    ) -> Result<Option<SigningKeyRecord>, AuthError> {
+        let query = "
+            UPDATE signing_keys SET retires_at = $2
+            WHERE kid = $1
+            RETURNING *;
+        ";
+        sqlx::query_as::<_, SigningKeyRecord>(query)
+            .bind(kid)
+            .bind(retires_at)
+            .fetch_optional(&*self.pg_pool)
+            .await
+            .map_err(AuthError::from)
+    }
+
+    async fn insert_refresh_token(&self, token: &RefreshToken) -> Result<(), AuthError> {
+        let query = "
+            INSERT INTO refresh_tokens
+                (user_id, family_id, token_hash, created_at, expires_at, client_id, scope)
+            VALUES ($1, $2, $3, $4, $5, $6, $7);
+        ";
+        sqlx::query(query)
+            .bind(token.user_id)
+            .bind(&token.family_id)
+            .bind(&token.token_hash)
+            .bind(token.created_at)
+            .bind(token.expires_at)
+            .bind(&token.client_id)
+            .bind(&token.scope)
+            .execute(&*self.pg_pool)
+            .await
+            .map(|_| ())
+            .map_err(|e| {
+                log::error!("Failed to insert refresh token: {}", e);
+                AuthError::from(e)
+            })
+    }
+
+    async fn find_refresh_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<RefreshToken>, AuthError> {
+        let query = "
+            SELECT * FROM refresh_tokens WHERE token_hash = $1;
+        ";
+        sqlx::query_as::<_, RefreshToken>(query)
+            .bind(token_hash)
+            .fetch_optional(&*self.pg_pool)
+            .await
+            .map_err(AuthError::from)
+    }
+
+    async fn mark_refresh_token_used(&self, token_id: i32) -> Result<bool, AuthError> {
+        let query = "
+            UPDATE refresh_tokens SET used_at = NOW()
+            WHERE token_id = $1 AND used_at IS NULL AND revoked_at IS NULL;
+        ";
+        sqlx::query(query)
+            .bind(token_id)
+            .execute(&*self.pg_pool)
+            .await
+            .map(|result| result.rows_affected() == 1)
+            .map_err(AuthError::from)
+    }
+
+    async fn revoke_refresh_token_family(&self, family_id: &str) -> Result<u64, AuthError> {
+        let query = "
+            UPDATE refresh_tokens SET revoked_at = NOW()
+            WHERE family_id = $1 AND revoked_at IS NULL;
+        ";
+        sqlx::query(query)
+            .bind(family_id)
+            .execute(&*self.pg_pool)
+            .await
+            .map(|result| result.rows_affected())
+            .map_err(AuthError::from)
+    }
+
+    async fn revoke_user_refresh_tokens(&self, user_id: i32) -> Result<u64, AuthError> {
+        let query = "
+            UPDATE refresh_tokens SET revoked_at = NOW()
+            WHERE user_id = $1 AND revoked_at IS NULL;
+        ";
+        sqlx::query(query)
+            .bind(user_id)
+            .execute(&*self.pg_pool)
+            .await
+            .map(|result| result.rows_affected())
+            .map_err(AuthError::from)
+    }
+
+    async fn insert_authorization_code(&self, code: &AuthorizationCode) -> Result<(), AuthError> {
+        let query = "
+            INSERT INTO authorization_codes
+                (code_hash, client_id, user_id, refresh_family_id, code_challenge, redirect_url,
+                 created_at, expires_at, scope, nonce)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10);
+        ";
+        sqlx::query(query)
+            .bind(&code.code_hash)
+            .bind(&code.client_id)
+            .bind(code.user_id)
+            .bind(&code.refresh_family_id)
+            .bind(&code.code_challenge)
+            .bind(&code.redirect_url)
+            .bind(code.created_at)
+            .bind(code.expires_at)
+            .bind(&code.scope)
+            .bind(&code.nonce)
+            .execute(&*self.pg_pool)
+            .await
+            .map(|_| ())
+            .map_err(|e| {
+                log::error!("Failed to insert authorization code: {}", e);
+                AuthError::from(e)
+            })
+    }
+
+    async fn redeem_authorization_code(
+        &self,
+        code_hash: &str,
+    ) -> Result<Option<AuthorizationCode>, AuthError> {
+        let query = "
+            UPDATE authorization_codes SET used_at = NOW()
+            WHERE code_hash = $1 AND used_at IS NULL
+            RETURNING *;
+        ";
+        sqlx::query_as::<_, AuthorizationCode>(query)
+            .bind(code_hash)
+            .fetch_optional(&*self.pg_pool)
+            .await
+            .map_err(AuthError::from)
+    }
+
+    async fn find_authorization_code(
+        &self,
+        code_hash: &str,
+    ) -> Result<Option<AuthorizationCode>, AuthError> {
+        let query = "
+            SELECT * FROM authorization_codes WHERE code_hash = $1;
+        ";
+        sqlx::query_as::<_, AuthorizationCode>(query)
+            .bind(code_hash)
+            .fetch_optional(&*self.pg_pool)
+            .await
+            .map_err(AuthError::from)
+    }
+
+    async fn delete_authorization_codes_before(
+        &self,
+        expired_before: DateTime<Utc>,
+    ) -> Result<u64, AuthError> {
+        let query = "
+            DELETE FROM authorization_codes WHERE expires_at < $1;
+        ";
+        sqlx::query(query)
+            .bind(expired_before)
+            .execute(&*self.pg_pool)
+            .await
+            .map(|result| result.rows_affected())
+            .map_err(AuthError::from)
+    }
+
+    async fn insert_client(&self, client: &RegisteredClient) -> Result<(), AuthError> {
+        let query = "
+            INSERT INTO clients
+                (client_id, name, secret_hash, redirect_uris, allowed_scopes, trusted, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7);
+        ";
+        sqlx::query(query)
+            .bind(&client.client_id)
+            .bind(&client.name)
+            .bind(&client.secret_hash)
+            .bind(&client.redirect_uris)
+            .bind(&client.allowed_scopes)
+            .bind(client.trusted)
+            .bind(client.created_at)
+            .execute(&*self.pg_pool)
+            .await
+            .map(|_| ())
+            .map_err(|e| {
+                log::error!("Failed to insert client: {}", e);
+                AuthError::from(e)
+            })
+    }
+
+    async fn find_client(&self, client_id: &str) -> Result<Option<RegisteredClient>, AuthError> {
+        let query = "
+            SELECT * FROM clients WHERE client_id = $1;
+        ";
+        sqlx::query_as::<_, RegisteredClient>(query)
+            .bind(client_id)
+            .fetch_optional(&*self.pg_pool)
+            .await
+            .map_err(AuthError::from)
+    }
+
+    async fn list_clients(&self) -> Result<Vec<RegisteredClient>, AuthError> {
+        let query = "
+            SELECT * FROM clients ORDER BY created_at;
+        ";
+        sqlx::query_as::<_, RegisteredClient>(query)
+            .fetch_all(&*self.pg_pool)
+            .await
+            .map_err(AuthError::from)
+    }
+
+    async fn delete_client(&self, client_id: &str) -> Result<bool, AuthError> {
+        let query = "
+            DELETE FROM clients WHERE client_id = $1;
+        ";
+        sqlx::query(query)
+            .bind(client_id)
+            .execute(&*self.pg_pool)
+            .await
+            .map(|result| result.rows_affected() > 0)
+            .map_err(AuthError::from)
+    }
+}
+
+#[async_trait]
+impl RevocationStore for PostgresRepository {
+    async fn revoke_token(
+        &self,
+        jti: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), kuri_auth::Error> {
+        let query = "
+            INSERT INTO revoked_tokens (jti, expires_at)
+            VALUES ($1, $2)
+            ON CONFLICT (jti) DO NOTHING;
+        ";
+        sqlx::query(query)
+            .bind(jti)
+            .bind(expires_at)
+            .execute(&*self.pg_pool)
+            .await
+            .map(|_| ())
+            .map_err(|e| {
+                log::error!("Failed to revoke token: {}", e);
+                storage_error(e)
+            })
+    }
+
+    async fn revoke_session(
+        &self,
+        session_id: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), kuri_auth::Error> {
+        let query = "
+            INSERT INTO revoked_sessions (session_id, expires_at)
+            VALUES ($1, $2)
+            ON CONFLICT (session_id) DO NOTHING;
+        ";
+        sqlx::query(query)
+            .bind(session_id)
+            .bind(expires_at)
+            .execute(&*self.pg_pool)
+            .await
+            .map(|_| ())
+            .map_err(|e| {
+                log::error!("Failed to revoke session: {}", e);
+                storage_error(e)
+            })
+    }
+
+    async fn revoke_user_tokens(
+        &self,
+        user_id: i32,
+        issued_before: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), kuri_auth::Error> {
+        let query = "
+            INSERT INTO user_token_revocations (user_id, issued_before, expires_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (user_id) DO UPDATE
+            SET issued_before = EXCLUDED.issued_before, expires_at = EXCLUDED.expires_at;
+        ";
+        sqlx::query(query)
+            .bind(user_id)
+            .bind(issued_before)
+            .bind(expires_at)
+            .execute(&*self.pg_pool)
+            .await
+            .map(|_| ())
+            .map_err(|e| {
+                log::error!("Failed to revoke tokens of user {}: {}", user_id, e);
+                storage_error(e)
+            })
+    }
+
+    async fn is_revoked(&self, claims: &Claims) -> Result<bool, kuri_auth::Error> {
+        let query = "
+            SELECT EXISTS (SELECT 1 FROM revoked_tokens WHERE jti = $1)
+                OR EXISTS (SELECT 1 FROM revoked_sessions WHERE session_id = $4)
+                OR EXISTS (
+                    SELECT 1 FROM user_token_revocations
+                    WHERE user_id = $2 AND issued_before > $3
+                );
+        ";
+        sqlx::query_scalar::<_, bool>(query)
+            .bind(&claims.jti)
+            .bind(claims.sub)
+            .bind(claims.issued_at())
+            .bind(&claims.sid)
+            .fetch_one(&*self.pg_pool)
+            .await
+            .map_err(storage_error)
+    }
+
+    async fn prune_expired(&self, now: DateTime<Utc>) -> Result<u64, kuri_auth::Error> {
+        let mut tx = self.pg_pool.begin().await.map_err(storage_error)?;
+        let tokens = sqlx::query("DELETE FROM revoked_tokens WHERE expires_at < $1;")
+            .bind(now)
+            .execute(&mut *tx)
+            .await
+            .map_err(storage_error)?
+            .rows_affected();
+        let sessions = sqlx::query("DELETE FROM revoked_sessions WHERE expires_at < $1;")
+            .bind(now)
+            .execute(&mut *tx)
+            .await
+            .map_err(storage_error)?
+            .rows_affected();
+        let users = sqlx::query("DELETE FROM user_token_revocations WHERE expires_at < $1;")
+            .bind(now)
+            .execute(&mut *tx)
+            .await
+            .map_err(storage_error)?
+            .rows_affected();
+        tx.commit().await.map_err(storage_error)?;
+        Ok(tokens + sessions + users)
+    }
+}
+
+fn storage_error(e: sqlx::Error) -> kuri_auth::Error {
+    kuri_auth::Error::Storage(Box::new(e))
 }
@@ -0,0 +1,71 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use tokio::io::AsyncWriteExt;
+
+use crate::modules::auth::{ports::Mailer, AuthError, Email};
+
+/// Appends emails to a file instead of sending them, or only logs them without one. For
+/// development and tests, which read the links from there.
+pub struct FileMailer {
+    path: Option<PathBuf>,
+}
+
+impl FileMailer {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self { path }
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: &Email) -> Result<(), AuthError> {
+        let message = format!(
+            "To: {}\nSubject: {}\n\n{}\n\n",
+            email.to, email.subject, email.body
+        );
+        let Some(path) = &self.path else {
+            log::info!("Email not sent:\n{}", message);
+            return Ok(());
+        };
+
+        log::info!("Writing email to {} into {}", email.to, path.display());
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .map_err(|e| AuthError::MailDeliveryFailed(e.to_string()))?;
+        file.write_all(message.as_bytes())
+            .await
+            .map_err(|e| AuthError::MailDeliveryFailed(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::token::generate_token;
+
+    #[tokio::test]
+    async fn appends_emails_to_the_file() {
+        let path = std::env::temp_dir().join(format!("kuri-mail-{}.txt", generate_token(8)));
+        let mailer = FileMailer::new(Some(path.clone()));
+        for subject in ["First", "Second"] {
+            let email = Email {
+                to: "ada@example.com".to_string(),
+                subject: subject.to_string(),
+                body: "https://app.example.com/verify?token=t0ken".to_string(),
+            };
+            mailer.send(&email).await.unwrap();
+        }
+
+        let written = tokio::fs::read_to_string(&path).await.unwrap();
+        tokio::fs::remove_file(&path).await.unwrap();
+        assert_eq!(
+            written,
+            "To: ada@example.com\nSubject: First\n\nhttps://app.example.com/verify?token=t0ken\n\n\
+             To: ada@example.com\nSubject: Second\n\nhttps://app.example.com/verify?token=t0ken\n\n"
+        );
+    }
+}
@@ -0,0 +1,332 @@
+use async_trait::async_trait;
+use oauth2::{
+    reqwest::async_http_client, AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken,
+    PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, RevocationUrl, Scope, TokenResponse,
+    TokenUrl,
+};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{
+    modules::auth::{
+        ports::Provider, AuthError, AuthorizationRequest, OAuthAuthorization,
+        ProviderTokenResponse, UserProfile,
+    },
+    utils::config::{ClaimMappings, OidcProviderConfig},
+};
+
+use super::{
+    id_token, profile_from_claims, revoke_authorization, IdTokenVerifier, JwksCache, OidcClient,
+};
+
+/// The parts of `/.well-known/openid-configuration` the provider needs.
+#[derive(Debug, Deserialize)]
+struct DiscoveryDocument {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: Option<String>,
+    revocation_endpoint: Option<String>,
+    jwks_uri: String,
+}
+
+/// Any OpenID Connect provider (Keycloak, Auth0, Okta, Azure AD, ...) configured from its
+/// issuer's discovery document.
+pub struct GenericOidcProvider {
+    provider_id: i32,
+    name: String,
+    client: OidcClient,
+    id_token_verifier: IdTokenVerifier,
+    userinfo_endpoint: Option<String>,
+    scopes: Vec<String>,
+    claims: ClaimMappings,
+}
+
+impl GenericOidcProvider {
+    /// Reads the issuer's discovery document and builds the provider from it.
+    pub async fn discover(
+        config: OidcProviderConfig,
+        redirect_uri: String,
+    ) -> Result<Self, AuthError> {
+        let discovery_url = format!(
+            "{}/.well-known/openid-configuration",
+            config.issuer.trim_end_matches('/')
+        );
+        let document = reqwest::get(&discovery_url)
+            .await?
+            .error_for_status()?
+            .json::<DiscoveryDocument>()
+            .await?;
+
+        if document.issuer.trim_end_matches('/') != config.issuer.trim_end_matches('/') {
+            return Err(AuthError::AuthenticationFailed(format!(
+                "{}: discovery document issuer {} does not match {}",
+                config.name, document.issuer, config.issuer
+            )));
+        }
+
+        let invalid_endpoint = |e: oauth2::url::ParseError| {
+            AuthError::AuthenticationFailed(format!("{}: {}", config.name, e))
+        };
+        let mut client = OidcClient::new(
+            ClientId::new(config.client_id.clone()),
+            Some(ClientSecret::new(config.client_secret)),
+            AuthUrl::new(document.authorization_endpoint).map_err(invalid_endpoint)?,
+            Some(TokenUrl::new(document.token_endpoint).map_err(invalid_endpoint)?),
+        )
+        .set_redirect_uri(RedirectUrl::new(redirect_uri).map_err(invalid_endpoint)?);
+        if let Some(revocation_endpoint) = document.revocation_endpoint {
+            client = client.set_revocation_uri(
+                RevocationUrl::new(revocation_endpoint).map_err(invalid_endpoint)?,
+            );
+        }
+
+        let id_token_verifier = IdTokenVerifier::new(
+            JwksCache::new(document.jwks_uri),
+            vec![document.issuer],
+            config.client_id,
+        );
+
+        Ok(GenericOidcProvider {
+            provider_id: config.provider_id,
+            name: config.name,
+            client,
+            id_token_verifier,
+            userinfo_endpoint: document.userinfo_endpoint,
+            scopes: config.scopes,
+            claims: config.claims,
+        })
+    }
+
+    /// Profile claims that are filled in from the userinfo endpoint when the ID token lacks
+    /// them.
+    fn profile_claims(&self) -> [&str; 3] {
+        [&self.claims.email, &self.claims.name, &self.claims.picture]
+    }
+}
+
+#[async_trait]
+impl Provider for GenericOidcProvider {
+    async fn get_authorization_url(&self) -> AuthorizationRequest {
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        let nonce = CsrfToken::new_random().secret().to_string();
+        let (auth_url, csrf_token) = self
+            .scopes
+            .iter()
+            .fold(
+                self.client.authorize_url(CsrfToken::new_random),
+                |url, scope| url.add_scope(Scope::new(scope.to_string())),
+            )
+            .set_pkce_challenge(pkce_challenge)
+            .add_extra_param("nonce", &nonce)
+            .url();
+
+        AuthorizationRequest {
+            url: auth_url.to_string(),
+            csrf_token,
+            pkce_verifier,
+            nonce: Some(nonce),
+        }
+    }
+
+    async fn exchange_token(
+        &self,
+        code: String,
+        pkce_verifier: PkceCodeVerifier,
+    ) -> Result<ProviderTokenResponse, AuthError> {
+        self.client
+            .exchange_code(AuthorizationCode::new(code))
+            .set_pkce_verifier(pkce_verifier)
+            .request_async(async_http_client)
+            .await
+            .map_err(|err| {
+                log::error!("Failed to exchange code with {}: {:?}", self.name, err);
+                AuthError::OAuth2RequestTokenError(err.to_string())
+            })
+    }
+
+    async fn fetch_user_profile(
+        &self,
+        token_response: &ProviderTokenResponse,
+        nonce: Option<&str>,
+    ) -> Result<UserProfile, AuthError> {
+        let mut claims = self
+            .id_token_verifier
+            .verify(id_token(token_response)?, nonce)
+            .await?;
+
+        let missing_claims = self
+            .profile_claims()
+            .iter()
+            .any(|claim| !claims.contains_key(*claim));
+        if let (true, Some(userinfo_endpoint)) = (missing_claims, &self.userinfo_endpoint) {
+            let user_info = reqwest::Client::new()
+                .get(userinfo_endpoint)
+                .bearer_auth(token_response.access_token().secret())
+                .send()
+                .await?
+                .json::<Value>()
+                .await?;
+            if user_info.get("sub") != claims.get("sub") {
+                return Err(AuthError::InvalidTokenError(
+                    "userinfo subject does not match id_token".into(),
+                ));
+            }
+            if let Value::Object(user_info) = user_info {
+                for (key, value) in user_info {
+                    claims.entry(key).or_insert(value);
+                }
+            }
+        }
+
+        profile_from_claims(claims, &self.claims)
+    }
+
+    async fn revoke_authorization(
+        &self,
+        authorization: &OAuthAuthorization,
+    ) -> Result<(), AuthError> {
+        if self.client.revocation_url().is_none() {
+            return Ok(());
+        }
+        revoke_authorization(&self.client, authorization).await
+    }
+
+    fn provider_id(&self) -> i32 {
+        self.provider_id
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use actix_web::{web, App, HttpResponse, HttpServer};
+    use chrono::Utc;
+    use jsonwebtoken::Algorithm;
+    use kuri_auth::{JwtManagerBuilder, SigningKey};
+    use serde_json::json;
+
+    use super::*;
+    use crate::utils::token::generate_token;
+
+    /// The ID token the mock issuer's token endpoint returns.
+    type IdToken = Arc<Mutex<String>>;
+
+    /// Serves discovery, token and userinfo endpoints on a local port, with its keys in a
+    /// local JWKS file. Returns the issuer URL.
+    fn mock_issuer(jwks_path: String, id_token: IdToken) -> String {
+        let server = HttpServer::new(move || {
+            let jwks_path = jwks_path.clone();
+            let id_token = id_token.clone();
+            App::new()
+                .route(
+                    "/.well-known/openid-configuration",
+                    web::get().to(move |req: actix_web::HttpRequest| {
+                        let issuer = format!("http://{}", req.connection_info().host());
+                        let jwks_path = jwks_path.clone();
+                        async move {
+                            HttpResponse::Ok().json(json!({
+                                "issuer": issuer,
+                                "authorization_endpoint": format!("{}/authorize", issuer),
+                                "token_endpoint": format!("{}/token", issuer),
+                                "userinfo_endpoint": format!("{}/userinfo", issuer),
+                                "jwks_uri": jwks_path,
+                            }))
+                        }
+                    }),
+                )
+                .route(
+                    "/token",
+                    web::post().to(move || {
+                        let id_token = id_token.lock().unwrap().clone();
+                        async move {
+                            HttpResponse::Ok().json(json!({
+                                "access_token": "upstream-access-token",
+                                "token_type": "Bearer",
+                                "expires_in": 3600,
+                                "id_token": id_token,
+                            }))
+                        }
+                    }),
+                )
+                .route(
+                    "/userinfo",
+                    web::get().to(|| async {
+                        HttpResponse::Ok().json(json!({ "sub": "42", "name": "Ada Lovelace" }))
+                    }),
+                )
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let issuer = format!("http://{}", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+        issuer
+    }
+
+    #[actix_web::test]
+    async fn signs_in_through_a_discovered_issuer() {
+        let (key, _) = SigningKey::generate(Algorithm::ES256).unwrap();
+        let keys = JwtManagerBuilder::new(key).build();
+        let jwks_path =
+            std::env::temp_dir().join(format!("kuri-issuer-{}.json", generate_token(8)));
+        std::fs::write(&jwks_path, serde_json::to_string(&keys.jwks()).unwrap()).unwrap();
+        let id_token = IdToken::default();
+        let issuer = mock_issuer(jwks_path.to_string_lossy().into_owned(), id_token.clone());
+
+        let provider = GenericOidcProvider::discover(
+            OidcProviderConfig {
+                provider_id: 10,
+                name: "mock".to_string(),
+                issuer: issuer.clone(),
+                client_id: "kurilogin".to_string(),
+                client_secret: "secret".to_string(),
+                scopes: vec!["openid".to_string(), "email".to_string()],
+                claims: ClaimMappings::default(),
+            },
+            "https://login.test/auth/mock/callback".to_string(),
+        )
+        .await
+        .unwrap();
+
+        let authorization = provider.get_authorization_url().await;
+        assert!(authorization
+            .url
+            .starts_with(&format!("{}/authorize?", issuer)));
+        let nonce = authorization.nonce.unwrap();
+        let now = Utc::now().timestamp();
+        *id_token.lock().unwrap() = keys
+            .sign(&json!({
+                "iss": issuer,
+                "aud": "kurilogin",
+                "sub": "42",
+                "iat": now,
+                "exp": now + 300,
+                "nonce": nonce,
+                "email": "ada@example.com",
+                "email_verified": true,
+            }))
+            .unwrap();
+
+        let token_response = provider
+            .exchange_token("upstream-code".to_string(), authorization.pkce_verifier)
+            .await
+            .unwrap();
+        let profile = provider
+            .fetch_user_profile(&token_response, Some(&nonce))
+            .await
+            .unwrap();
+        std::fs::remove_file(&jwks_path).unwrap();
+
+        assert_eq!(profile.provider_user_id, "42");
+        assert_eq!(profile.email.as_deref(), Some("ada@example.com"));
+        assert!(profile.email_verified);
+        // Not in the ID token, so it comes from the userinfo endpoint.
+        assert_eq!(profile.display_name.as_deref(), Some("Ada Lovelace"));
+    }
+}
@@ -0,0 +1,173 @@
+use async_trait::async_trait;
+use oauth2::{
+    reqwest::async_http_client, AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken,
+    PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, Scope, TokenResponse, TokenUrl,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::modules::auth::{
+    ports::Provider, AuthError, AuthorizationRequest, OAuthAuthorization, ProviderTokenResponse,
+    UserProfile,
+};
+
+use super::OidcClient;
+
+const API_URL: &str = "https://api.github.com";
+
+#[derive(Debug, Serialize, Deserialize)]
+struct GitHubUser {
+    id: i64,
+    login: String,
+    name: Option<String>,
+    avatar_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubEmail {
+    email: String,
+    primary: bool,
+    verified: bool,
+}
+
+/// GitHub OAuth apps. GitHub is not an OpenID Connect provider, so the profile comes from its
+/// REST API and the email from `/user/emails`.
+pub struct GitHubProvider {
+    client: OidcClient,
+    /// GitHub's grant revocation authenticates the app itself with these.
+    client_id: String,
+    client_secret: String,
+}
+
+impl GitHubProvider {
+    pub fn new(client_id: String, client_secret: String, redirect_uri: String) -> Self {
+        let auth_url = AuthUrl::new("https://github.com/login/oauth/authorize".to_string())
+            .expect("Invalid authorization endpoint URL");
+        let token_url = TokenUrl::new("https://github.com/login/oauth/access_token".to_string())
+            .expect("Invalid token endpoint URL");
+
+        let client = OidcClient::new(
+            ClientId::new(client_id.clone()),
+            Some(ClientSecret::new(client_secret.clone())),
+            auth_url,
+            Some(token_url),
+        )
+        .set_redirect_uri(RedirectUrl::new(redirect_uri).expect("Invalid redirect URI"));
+
+        GitHubProvider {
+            client,
+            client_id,
+            client_secret,
+        }
+    }
+
+    async fn get<T: for<'de> Deserialize<'de>>(
+        &self,
+        path: &str,
+        access_token: &str,
+    ) -> Result<T, AuthError> {
+        Ok(reqwest::Client::new()
+            .get(format!("{}{}", API_URL, path))
+            .bearer_auth(access_token)
+            .header(reqwest::header::USER_AGENT, "KuriLogin")
+            .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<T>()
+            .await?)
+    }
+}
+
+#[async_trait]
+impl Provider for GitHubProvider {
+    async fn get_authorization_url(&self) -> AuthorizationRequest {
+        let scopes = ["read:user", "user:email"];
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        let (auth_url, csrf_token) = scopes
+            .iter()
+            .fold(
+                self.client.authorize_url(CsrfToken::new_random),
+                |url, scope| url.add_scope(Scope::new(scope.to_string())),
+            )
+            .set_pkce_challenge(pkce_challenge)
+            .url();
+
+        AuthorizationRequest {
+            url: auth_url.to_string(),
+            csrf_token,
+            pkce_verifier,
+            nonce: None,
+        }
+    }
+
+    async fn exchange_token(
+        &self,
+        code: String,
+        pkce_verifier: PkceCodeVerifier,
+    ) -> Result<ProviderTokenResponse, AuthError> {
+        self.client
+            .exchange_code(AuthorizationCode::new(code))
+            .set_pkce_verifier(pkce_verifier)
+            .request_async(async_http_client)
+            .await
+            .map_err(|err| {
+                log::error!("Failed to exchange code with GitHub: {:?}", err);
+                AuthError::OAuth2RequestTokenError(err.to_string())
+            })
+    }
+
+    async fn fetch_user_profile(
+        &self,
+        token_response: &ProviderTokenResponse,
+        _nonce: Option<&str>,
+    ) -> Result<UserProfile, AuthError> {
+        let access_token = token_response.access_token().secret();
+        let user: GitHubUser = self.get("/user", access_token).await?;
+        let emails: Vec<GitHubEmail> = self.get("/user/emails", access_token).await?;
+
+        // Only the primary address, and only once GitHub has verified it.
+        let email = emails
+            .into_iter()
+            .find(|email| email.primary && email.verified)
+            .map(|email| email.email);
+
+        Ok(UserProfile {
+            provider_user_id: user.id.to_string(),
+            email_verified: email.is_some(),
+            email,
+            display_name: Some(user.name.clone().unwrap_or_else(|| user.login.clone())),
+            given_name: None,
+            family_name: None,
+            avatar_url: user.avatar_url.clone(),
+            locale: None,
+            raw_claims: serde_json::to_value(&user).unwrap_or(Value::Null),
+        })
+    }
+
+    /// GitHub has no RFC 7009 endpoint; deleting the grant revokes every token of the app
+    /// for the user.
+    async fn revoke_authorization(
+        &self,
+        authorization: &OAuthAuthorization,
+    ) -> Result<(), AuthError> {
+        reqwest::Client::new()
+            .delete(format!("{}/applications/{}/grant", API_URL, self.client_id))
+            .basic_auth(&self.client_id, Some(&self.client_secret))
+            .header(reqwest::header::USER_AGENT, "KuriLogin")
+            .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+            .json(&serde_json::json!({ "access_token": authorization.access_token }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    fn provider_id(&self) -> i32 {
+        2 // Represents GitHub as an OAuth provider in your system
+    }
+
+    fn name(&self) -> &str {
+        "github"
+    }
+}
@@ -1,61 +1,116 @@
 use async_trait::async_trait;
 use oauth2::{
-    basic::{BasicClient, BasicTokenType},
-    reqwest::async_http_client,
-    AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, EmptyExtraTokenFields,
-    RedirectUrl, Scope, StandardTokenResponse, TokenUrl,
+    reqwest::async_http_client, AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken,
+    PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, RevocationUrl, Scope, TokenResponse,
+    TokenUrl,
 };
 
 use serde_json::Value;
 
-use crate::modules::auth::{ports::Provider, AuthError};
+use crate::{
+    modules::auth::{
+        ports::Provider, AuthError, AuthorizationRequest, OAuthAuthorization,
+        ProviderTokenResponse, UserProfile,
+    },
+    utils::config::ClaimMappings,
+};
+
+use super::{
+    id_token, profile_from_claims, revoke_authorization, IdTokenVerifier, JwksCache, OidcClient,
+};
+
+/// Profile claims that are filled in from the userinfo endpoint when the ID token lacks them.
+const PROFILE_CLAIMS: [&str; 3] = ["email", "given_name", "picture"];
 
 pub struct GoogleProvider {
-    client: BasicClient,
+    client: OidcClient,
+    id_token_verifier: IdTokenVerifier,
 }
 
 impl GoogleProvider {
-    pub fn new(client_id: String, client_secret: String, redirect_uri: String) -> Self {
+    pub fn new(
+        client_id: String,
+        client_secret: String,
+        redirect_uri: String,
+        jwks_uri: String,
+    ) -> Self {
         let auth_url = AuthUrl::new("https://accounts.google.com/o/oauth2/v2/auth".to_string())
             .expect("Invalid authorization endpoint URL");
         let token_url = TokenUrl::new("https://oauth2.googleapis.com/token".to_string())
             .expect("Invalid token endpoint URL");
+        let revocation_url = RevocationUrl::new("https://oauth2.googleapis.com/revoke".to_string())
+            .expect("Invalid revocation endpoint URL");
 
-        let client = BasicClient::new(
+        let id_token_verifier = IdTokenVerifier::new(
+            JwksCache::new(jwks_uri),
+            vec![
+                "https://accounts.google.com".to_string(),
+                "accounts.google.com".to_string(),
+            ],
+            client_id.clone(),
+        );
+
+        let client = OidcClient::new(
             ClientId::new(client_id),
             Some(ClientSecret::new(client_secret)),
             auth_url,
             Some(token_url),
         )
-        .set_redirect_uri(RedirectUrl::new(redirect_uri).expect("Invalid redirect URI"));
+        .set_redirect_uri(RedirectUrl::new(redirect_uri).expect("Invalid redirect URI"))
+        .set_revocation_uri(revocation_url);
 
-        GoogleProvider { client }
+        GoogleProvider {
+            client,
+            id_token_verifier,
+        }
+    }
+
+    async fn fetch_userinfo(&self, access_token: &str) -> Result<Value, AuthError> {
+        let user_info_url = "https://www.googleapis.com/oauth2/v3/userinfo";
+        Ok(reqwest::Client::new()
+            .get(user_info_url)
+            .bearer_auth(access_token)
+            .send()
+            .await?
+            .json::<Value>()
+            .await?)
     }
 }
 
 #[async_trait]
 impl Provider for GoogleProvider {
-    async fn get_authorization_url(&self) -> (String, CsrfToken) {
-        let scopes = vec!["email", "profile", "openid"];
+    async fn get_authorization_url(&self) -> AuthorizationRequest {
+        let scopes = ["email", "profile", "openid"];
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+        let nonce = CsrfToken::new_random().secret().to_string();
         let (auth_url, csrf_token) = scopes
             .iter()
             .fold(
                 self.client.authorize_url(CsrfToken::new_random),
                 |url, scope| url.add_scope(Scope::new(scope.to_string())),
             )
+            .set_pkce_challenge(pkce_challenge)
+            .add_extra_param("nonce", &nonce)
             .add_extra_param("access_type", "offline")
             .add_extra_param("prompt", "consent")
             .url();
 
-        (auth_url.to_string(), csrf_token)
+        AuthorizationRequest {
+            url: auth_url.to_string(),
+            csrf_token,
+            pkce_verifier,
+            nonce: Some(nonce),
+        }
     }
 
     async fn exchange_token(
         &self,
         code: String,
-    ) -> Result<StandardTokenResponse<EmptyExtraTokenFields, BasicTokenType>, AuthError> {
+        pkce_verifier: PkceCodeVerifier,
+    ) -> Result<ProviderTokenResponse, AuthError> {
         self.client
             .exchange_code(AuthorizationCode::new(code))
+            .set_pkce_verifier(pkce_verifier)
             .request_async(async_http_client)
             .await
             .map_err(|err| {
@@ -64,18 +119,50 @@ impl Provider for GoogleProvider {
             })
     }
 
-    async fn fetch_user_info(&self, access_token: String) -> Result<Value, reqwest::Error> {
-        let user_info_url = "https://www.googleapis.com/oauth2/v3/userinfo";
-        reqwest::Client::new()
-            .get(user_info_url)
-            .bearer_auth(access_token)
-            .send()
-            .await?
-            .json::<Value>()
-            .await
+    async fn fetch_user_profile(
+        &self,
+        token_response: &ProviderTokenResponse,
+        nonce: Option<&str>,
+    ) -> Result<UserProfile, AuthError> {
+        let mut claims = self
+            .id_token_verifier
+            .verify(id_token(token_response)?, nonce)
+            .await?;
+
+        if PROFILE_CLAIMS
+            .iter()
+            .any(|claim| !claims.contains_key(*claim))
+        {
+            let user_info = self
+                .fetch_userinfo(token_response.access_token().secret())
+                .await?;
+            if user_info.get("sub") != claims.get("sub") {
+                return Err(AuthError::InvalidTokenError(
+                    "userinfo subject does not match id_token".into(),
+                ));
+            }
+            if let Value::Object(user_info) = user_info {
+                for (key, value) in user_info {
+                    claims.entry(key).or_insert(value);
+                }
+            }
+        }
+
+        profile_from_claims(claims, &ClaimMappings::default())
+    }
+
+    async fn revoke_authorization(
+        &self,
+        authorization: &OAuthAuthorization,
+    ) -> Result<(), AuthError> {
+        revoke_authorization(&self.client, authorization).await
     }
 
     fn provider_id(&self) -> i32 {
         1 // Represents Google as an OAuth provider in your system
     }
+
+    fn name(&self) -> &str {
+        "google"
+    }
 }
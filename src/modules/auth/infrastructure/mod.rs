@@ -1,4 +1,21 @@
+mod github_provider;
+pub use github_provider::*;
+
 mod google_provider;
 pub use google_provider::*;
 
+mod generic_oidc_provider;
+pub use generic_oidc_provider::*;
+
+pub use kuri_auth::JwksCache;
+
+mod oidc;
+pub use oidc::*;
+
+mod smtp_mailer;
+pub use smtp_mailer::*;
+
+mod file_mailer;
+pub use file_mailer::*;
+
 mod db_adapter;
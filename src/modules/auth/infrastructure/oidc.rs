@@ -0,0 +1,255 @@
+use jsonwebtoken::{decode, decode_header, Algorithm, Validation};
+use oauth2::{
+    basic::{
+        BasicErrorResponse, BasicRevocationErrorResponse, BasicTokenIntrospectionResponse,
+        BasicTokenType,
+    },
+    reqwest::async_http_client,
+    AccessToken, Client, RefreshToken, StandardRevocableToken,
+};
+use serde_json::{Map, Value};
+
+use crate::{
+    modules::auth::{AuthError, OAuthAuthorization, ProviderTokenResponse, UserProfile},
+    utils::config::ClaimMappings,
+};
+
+use super::JwksCache;
+
+/// OAuth2 client whose token responses carry the OpenID Connect `id_token`.
+pub type OidcClient = Client<
+    BasicErrorResponse,
+    ProviderTokenResponse,
+    BasicTokenType,
+    BasicTokenIntrospectionResponse,
+    StandardRevocableToken,
+    BasicRevocationErrorResponse,
+>;
+
+/// Verifies OpenID Connect ID tokens against a provider's published keys.
+pub struct IdTokenVerifier {
+    jwks: JwksCache,
+    issuers: Vec<String>,
+    audience: String,
+}
+
+impl IdTokenVerifier {
+    pub fn new(jwks: JwksCache, issuers: Vec<String>, audience: String) -> Self {
+        Self {
+            jwks,
+            issuers,
+            audience,
+        }
+    }
+
+    /// Checks the signature, `iss`, `aud`, `exp` and, when one was sent, `nonce`, and
+    /// returns the token's claims.
+    pub async fn verify(
+        &self,
+        id_token: &str,
+        nonce: Option<&str>,
+    ) -> Result<Map<String, Value>, AuthError> {
+        let header = decode_header(id_token)?;
+        if !matches!(
+            header.alg,
+            Algorithm::RS256
+                | Algorithm::RS384
+                | Algorithm::RS512
+                | Algorithm::PS256
+                | Algorithm::PS384
+                | Algorithm::PS512
+                | Algorithm::ES256
+                | Algorithm::ES384
+                | Algorithm::EdDSA
+        ) {
+            return Err(AuthError::InvalidTokenError(format!(
+                "Unsupported id_token algorithm {:?}",
+                header.alg
+            )));
+        }
+        let kid = header
+            .kid
+            .ok_or_else(|| AuthError::InvalidTokenError("id_token has no kid".into()))?;
+        let key = self.jwks.decoding_key(&kid).await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&self.issuers);
+        validation.set_audience(&[&self.audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+        let claims = decode::<Map<String, Value>>(id_token, &key, &validation)?.claims;
+
+        if let Some(expected) = nonce {
+            if claims.get("nonce").and_then(Value::as_str) != Some(expected) {
+                return Err(AuthError::InvalidTokenError(
+                    "id_token nonce mismatch".into(),
+                ));
+            }
+        }
+
+        Ok(claims)
+    }
+}
+
+/// Revokes a stored authorization at the client's revocation endpoint (RFC 7009). Revoking
+/// the refresh token, when there is one, ends the whole grant.
+pub async fn revoke_authorization(
+    client: &OidcClient,
+    authorization: &OAuthAuthorization,
+) -> Result<(), AuthError> {
+    let token = match &authorization.refresh_token {
+        Some(refresh_token) => {
+            StandardRevocableToken::RefreshToken(RefreshToken::new(refresh_token.clone()))
+        }
+        None => StandardRevocableToken::AccessToken(AccessToken::new(
+            authorization.access_token.clone(),
+        )),
+    };
+    client
+        .revoke_token(token)
+        .map_err(|err| AuthError::RevocationFailed(err.to_string()))?
+        .request_async(async_http_client)
+        .await
+        .map_err(|err| AuthError::RevocationFailed(err.to_string()))
+}
+
+/// Pulls the `id_token` out of a token response.
+pub fn id_token(token_response: &ProviderTokenResponse) -> Result<&str, AuthError> {
+    token_response
+        .extra_fields()
+        .id_token
+        .as_deref()
+        .ok_or_else(|| AuthError::InvalidTokenError("No id_token received".into()))
+}
+
+/// Builds a profile from ID token and userinfo claims, reading each field from the claim
+/// named in `mappings`.
+pub fn profile_from_claims(
+    claims: Map<String, Value>,
+    mappings: &ClaimMappings,
+) -> Result<UserProfile, AuthError> {
+    let string = |claim: &str| match claims.get(claim)? {
+        Value::String(value) => Some(value.clone()),
+        Value::Number(value) => Some(value.to_string()),
+        _ => None,
+    };
+    // Some providers send `email_verified` as a string.
+    let email_verified = match claims.get(&mappings.email_verified) {
+        Some(Value::Bool(verified)) => *verified,
+        Some(Value::String(verified)) => verified == "true",
+        _ => false,
+    };
+
+    Ok(UserProfile {
+        provider_user_id: string(&mappings.subject)
+            .ok_or_else(|| AuthError::AuthenticationFailed("User ID not found".into()))?,
+        email: string(&mappings.email),
+        email_verified,
+        display_name: string(&mappings.name),
+        given_name: string(&mappings.given_name),
+        family_name: string(&mappings.family_name),
+        avatar_url: string(&mappings.picture),
+        locale: string(&mappings.locale),
+        raw_claims: Value::Object(claims),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use chrono::Utc;
+    use kuri_auth::{JwtManager, JwtManagerBuilder, SigningKey};
+    use serde_json::json;
+
+    use super::*;
+    use crate::utils::token::generate_token;
+
+    const ISSUER: &str = "https://issuer.test";
+
+    /// A signing key, and a verifier that trusts it through a local JWKS file.
+    fn keys_and_verifier() -> (JwtManager, IdTokenVerifier, PathBuf) {
+        let (key, _) = SigningKey::generate(Algorithm::ES256).unwrap();
+        let keys = JwtManagerBuilder::new(key).build();
+        let path = std::env::temp_dir().join(format!("kuri-id-token-{}.json", generate_token(8)));
+        std::fs::write(&path, serde_json::to_string(&keys.jwks()).unwrap()).unwrap();
+        let verifier = IdTokenVerifier::new(
+            JwksCache::new(path.to_string_lossy().into_owned()),
+            vec![ISSUER.to_string()],
+            "kurilogin".to_string(),
+        );
+        (keys, verifier, path)
+    }
+
+    fn id_token(keys: &JwtManager, aud: &str, exp_in: i64, nonce: &str) -> String {
+        let now = Utc::now().timestamp();
+        keys.sign(&json!({
+            "iss": ISSUER,
+            "aud": aud,
+            "sub": "42",
+            "iat": now,
+            "exp": now + exp_in,
+            "nonce": nonce,
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn accepts_a_valid_id_token() {
+        let (keys, verifier, path) = keys_and_verifier();
+        let claims = verifier
+            .verify(&id_token(&keys, "kurilogin", 300, "n0nce"), Some("n0nce"))
+            .await;
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(claims.unwrap()["sub"], "42");
+    }
+
+    #[tokio::test]
+    async fn rejects_another_audience() {
+        let (keys, verifier, path) = keys_and_verifier();
+        let claims = verifier
+            .verify(
+                &id_token(&keys, "someone-else", 300, "n0nce"),
+                Some("n0nce"),
+            )
+            .await;
+        std::fs::remove_file(path).unwrap();
+        assert!(claims.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_an_expired_id_token() {
+        let (keys, verifier, path) = keys_and_verifier();
+        // Past the default 60 seconds of leeway.
+        let claims = verifier
+            .verify(&id_token(&keys, "kurilogin", -300, "n0nce"), Some("n0nce"))
+            .await;
+        std::fs::remove_file(path).unwrap();
+        assert!(claims.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_another_nonce() {
+        let (keys, verifier, path) = keys_and_verifier();
+        let claims = verifier
+            .verify(&id_token(&keys, "kurilogin", 300, "n0nce"), Some("other"))
+            .await;
+        std::fs::remove_file(path).unwrap();
+        assert!(claims.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_keys_the_issuer_did_not_publish() {
+        let (_, verifier, path) = keys_and_verifier();
+        let (other_keys, _, other_path) = keys_and_verifier();
+        let claims = verifier
+            .verify(
+                &id_token(&other_keys, "kurilogin", 300, "n0nce"),
+                Some("n0nce"),
+            )
+            .await;
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(other_path).unwrap();
+        assert!(claims.is_err());
+    }
+}
@@ -0,0 +1,78 @@
+use async_trait::async_trait;
+use lettre::{
+    message::{header::ContentType, Mailbox},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+
+use crate::modules::auth::{ports::Mailer, AuthError, Email};
+
+/// How the connection to the SMTP server is secured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmtpSecurity {
+    /// TLS from the start, usually on port 465.
+    Tls,
+    /// Upgraded with STARTTLS, usually on port 587.
+    StartTls,
+    /// Unencrypted; only for local mail catchers.
+    None,
+}
+
+/// Sends emails through an SMTP relay.
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(
+        host: &str,
+        port: u16,
+        security: SmtpSecurity,
+        credentials: Option<(String, String)>,
+        from: &str,
+    ) -> Result<Self, AuthError> {
+        let mut builder = match security {
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host),
+            SmtpSecurity::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host),
+            SmtpSecurity::None => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(
+                host,
+            )),
+        }
+        .map_err(|e| AuthError::MailDeliveryFailed(e.to_string()))?
+        .port(port);
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+        let from = from.parse().map_err(|e| {
+            AuthError::MailDeliveryFailed(format!("invalid sender {}: {}", from, e))
+        })?;
+
+        Ok(Self {
+            transport: builder.build(),
+            from,
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, email: &Email) -> Result<(), AuthError> {
+        let to: Mailbox = email.to.parse().map_err(|e| {
+            AuthError::MailDeliveryFailed(format!("invalid recipient {}: {}", email.to, e))
+        })?;
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(&email.subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(email.body.clone())
+            .map_err(|e| AuthError::MailDeliveryFailed(e.to_string()))?;
+
+        self.transport.send(message).await.map_err(|e| {
+            log::error!("Failed to send email to {}: {}", email.to, e);
+            AuthError::MailDeliveryFailed(e.to_string())
+        })?;
+        Ok(())
+    }
+}
@@ -0,0 +1,29 @@
+use std::sync::Arc;
+
+use actix_web::{web, HttpResponse, Responder, ResponseError};
+
+use crate::modules::{auth::api::FirstPartyUser, session::AppService};
+
+pub async fn list_sessions(
+    app_service: web::Data<Arc<AppService>>,
+    user: FirstPartyUser,
+) -> impl Responder {
+    match app_service.list_sessions(&user).await {
+        Ok(sessions) => HttpResponse::Ok().json(sessions),
+        Err(e) => e.error_response(),
+    }
+}
+
+pub async fn revoke_session(
+    app_service: web::Data<Arc<AppService>>,
+    user: FirstPartyUser,
+    session_id: web::Path<i32>,
+) -> impl Responder {
+    match app_service
+        .revoke_session(&user, session_id.into_inner())
+        .await
+    {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(e) => e.error_response(),
+    }
+}
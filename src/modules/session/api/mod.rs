@@ -0,0 +1,4 @@
+mod handler;
+
+mod routes_config;
+pub use routes_config::*;
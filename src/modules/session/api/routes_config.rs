@@ -0,0 +1,11 @@
+use actix_web::web;
+
+use super::handler::{list_sessions, revoke_session};
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/me/sessions")
+            .route("", web::get().to(list_sessions))
+            .route("/{session_id}", web::delete().to(revoke_session)),
+    );
+}
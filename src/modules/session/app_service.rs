@@ -0,0 +1,89 @@
+use std::sync::Arc;
+
+use crate::{
+    error::AppError,
+    modules::auth::{Claims, JwtManager},
+};
+
+use super::{ports::Repository, Session, SessionError};
+
+pub struct AppService {
+    repo: Arc<dyn Repository>,
+    jwt_manager: Arc<JwtManager>,
+}
+
+impl AppService {
+    pub fn new(repo: Arc<dyn Repository>, jwt_manager: Arc<JwtManager>) -> Self {
+        Self { repo, jwt_manager }
+    }
+}
+
+impl AppService {
+    pub async fn start_session(&self, session: &Session) -> Result<Session, AppError> {
+        Ok(self.repo.create_session(session).await?)
+    }
+
+    /// Records activity on the session behind a refresh token family. Returns `false` if the
+    /// session has been signed out, in which case its tokens must not be refreshed.
+    pub async fn touch_session(&self, refresh_family_id: &str) -> Result<bool, AppError> {
+        // Families issued before sessions were recorded have no session to check.
+        Ok(self
+            .repo
+            .touch_session(refresh_family_id)
+            .await?
+            .map_or(true, |session| session.revoked_at.is_none()))
+    }
+
+    pub async fn find_session(&self, refresh_family_id: &str) -> Result<Option<Session>, AppError> {
+        Ok(self.repo.find_session_by_family(refresh_family_id).await?)
+    }
+
+    /// Whether the session behind a refresh token family is still signed in, without
+    /// recording activity.
+    pub async fn is_session_active(&self, refresh_family_id: &str) -> Result<bool, AppError> {
+        Ok(self
+            .find_session(refresh_family_id)
+            .await?
+            .map_or(true, |session| session.revoked_at.is_none()))
+    }
+
+    /// The signed-in sessions of the token's user, flagging the one the token belongs to.
+    pub async fn list_sessions(&self, claims: &Claims) -> Result<Vec<Session>, AppError> {
+        let mut sessions = self.repo.list_active_sessions(claims.sub).await?;
+        for session in &mut sessions {
+            session.current = claims.sid.as_ref() == Some(&session.refresh_family_id);
+        }
+        Ok(sessions)
+    }
+
+    /// Signs out one of the token's user's sessions, e.g. on a lost device.
+    pub async fn revoke_session(&self, claims: &Claims, session_id: i32) -> Result<(), AppError> {
+        let session = self
+            .repo
+            .revoke_session(claims.sub, session_id)
+            .await?
+            .ok_or(SessionError::SessionNotFound)?;
+        Ok(self
+            .jwt_manager
+            .revoke_session(&session.refresh_family_id)
+            .await?)
+    }
+
+    pub async fn end_session(&self, refresh_family_id: &str) -> Result<(), AppError> {
+        Ok(self
+            .repo
+            .revoke_session_by_family(refresh_family_id)
+            .await?)
+    }
+
+    /// Signs out every session of the user. `JwtManager::revoke_all` spares tokens issued in
+    /// the current second, so the tokens of each session are revoked by `sid` as well.
+    pub async fn end_all_sessions(&self, user_id: i32) -> Result<(), AppError> {
+        for session in self.repo.list_active_sessions(user_id).await? {
+            self.jwt_manager
+                .revoke_session(&session.refresh_family_id)
+                .await?;
+        }
+        Ok(self.repo.revoke_user_sessions(user_id).await?)
+    }
+}
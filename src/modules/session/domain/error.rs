@@ -0,0 +1,10 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum SessionError {
+    #[error("Session not found")]
+    SessionNotFound,
+
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] sqlx::Error),
+}
@@ -0,0 +1,7 @@
+mod models;
+pub use models::*;
+
+pub mod ports;
+
+mod error;
+pub use error::*;
@@ -0,0 +1,97 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+
+/// A login on one device, kept alive by its refresh token family.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct Session {
+    pub session_id: i32,
+    pub user_id: i32,
+    pub provider_id: Option<i32>,
+    #[serde(skip_serializing)]
+    pub refresh_family_id: String,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    #[serde(skip_serializing)]
+    pub revoked_at: Option<DateTime<Utc>>,
+    /// Whether this is the session of the token the listing was requested with.
+    #[sqlx(skip)]
+    pub current: bool,
+}
+
+/// Where a login came from, as reported by the HTTP request.
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+}
+
+pub struct SessionBuilder {
+    session_id: Option<i32>,
+    user_id: Option<i32>,
+    provider_id: Option<i32>,
+    refresh_family_id: Option<String>,
+    user_agent: Option<String>,
+    ip_address: Option<String>,
+    created_at: Option<DateTime<Utc>>,
+    last_seen_at: Option<DateTime<Utc>>,
+}
+impl SessionBuilder {
+    pub fn new() -> Self {
+        Self {
+            session_id: None,
+            user_id: None,
+            provider_id: None,
+            refresh_family_id: None,
+            user_agent: None,
+            ip_address: None,
+            created_at: None,
+            last_seen_at: None,
+        }
+    }
+    pub fn session_id(mut self, session_id: i32) -> Self {
+        self.session_id = Some(session_id);
+        self
+    }
+    pub fn user_id(mut self, user_id: i32) -> Self {
+        self.user_id = Some(user_id);
+        self
+    }
+    pub fn provider_id(mut self, provider_id: i32) -> Self {
+        self.provider_id = Some(provider_id);
+        self
+    }
+    pub fn refresh_family_id<S: Into<String>>(mut self, refresh_family_id: S) -> Self {
+        self.refresh_family_id = Some(refresh_family_id.into());
+        self
+    }
+    pub fn client(mut self, client: &ClientInfo) -> Self {
+        self.user_agent = client.user_agent.clone();
+        self.ip_address = client.ip_address.clone();
+        self
+    }
+    pub fn created_at(mut self, created_at: DateTime<Utc>) -> Self {
+        self.created_at = Some(created_at);
+        self
+    }
+    pub fn last_seen_at(mut self, last_seen_at: DateTime<Utc>) -> Self {
+        self.last_seen_at = Some(last_seen_at);
+        self
+    }
+    pub fn build(self) -> Session {
+        Session {
+            session_id: self.session_id.unwrap_or(0),
+            user_id: self.user_id.unwrap_or(0),
+            provider_id: self.provider_id,
+            refresh_family_id: self.refresh_family_id.unwrap_or_default(),
+            user_agent: self.user_agent,
+            ip_address: self.ip_address,
+            created_at: self.created_at.unwrap_or_else(Utc::now),
+            last_seen_at: self.last_seen_at.unwrap_or_else(Utc::now),
+            revoked_at: None,
+            current: false,
+        }
+    }
+}
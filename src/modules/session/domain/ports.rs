@@ -0,0 +1,32 @@
+use async_trait::async_trait;
+
+use super::{Session, SessionError};
+
+#[async_trait]
+pub trait Repository: Send + Sync {
+    async fn create_session(&self, session: &Session) -> Result<Session, SessionError>;
+
+    /// Sessions of the user that have not been revoked, most recently seen first.
+    async fn list_active_sessions(&self, user_id: i32) -> Result<Vec<Session>, SessionError>;
+
+    async fn find_session_by_family(
+        &self,
+        refresh_family_id: &str,
+    ) -> Result<Option<Session>, SessionError>;
+
+    /// Updates `last_seen_at` of the session owning the refresh token family.
+    async fn touch_session(&self, refresh_family_id: &str)
+        -> Result<Option<Session>, SessionError>;
+
+    /// Revokes one of the user's sessions. Returns `None` if it does not exist, belongs to
+    /// someone else or is already revoked.
+    async fn revoke_session(
+        &self,
+        user_id: i32,
+        session_id: i32,
+    ) -> Result<Option<Session>, SessionError>;
+
+    async fn revoke_session_by_family(&self, refresh_family_id: &str) -> Result<(), SessionError>;
+
+    async fn revoke_user_sessions(&self, user_id: i32) -> Result<(), SessionError>;
+}
@@ -0,0 +1,120 @@
+use async_trait::async_trait;
+
+use crate::{
+    modules::session::{ports::Repository, Session, SessionError},
+    utils::postgres::PostgresRepository,
+};
+
+#[async_trait]
+impl Repository for PostgresRepository {
+    async fn create_session(&self, session: &Session) -> Result<Session, SessionError> {
+        let query = "
+            INSERT INTO sessions
+                (user_id, provider_id, refresh_family_id, user_agent, ip_address,
+                 created_at, last_seen_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING *;
+        ";
+        sqlx::query_as::<_, Session>(query)
+            .bind(session.user_id)
+            .bind(session.provider_id)
+            .bind(&session.refresh_family_id)
+            .bind(&session.user_agent)
+            .bind(&session.ip_address)
+            .bind(session.created_at)
+            .bind(session.last_seen_at)
+            .fetch_one(&*self.pg_pool)
+            .await
+            .map_err(|e| {
+                log::error!("Failed to create session: {}", e);
+                SessionError::from(e)
+            })
+    }
+
+    async fn list_active_sessions(&self, user_id: i32) -> Result<Vec<Session>, SessionError> {
+        let query = "
+            SELECT * FROM sessions
+            WHERE user_id = $1 AND revoked_at IS NULL
+            ORDER BY last_seen_at DESC;
+        ";
+        sqlx::query_as::<_, Session>(query)
+            .bind(user_id)
+            .fetch_all(&*self.pg_pool)
+            .await
+            .map_err(SessionError::from)
+    }
+
+    async fn find_session_by_family(
+        &self,
+        refresh_family_id: &str,
+    ) -> Result<Option<Session>, SessionError> {
+        let query = "
+            SELECT * FROM sessions WHERE refresh_family_id = $1;
+        ";
+        sqlx::query_as::<_, Session>(query)
+            .bind(refresh_family_id)
+            .fetch_optional(&*self.pg_pool)
+            .await
+            .map_err(SessionError::from)
+    }
+
+    async fn touch_session(
+        &self,
+        refresh_family_id: &str,
+    ) -> Result<Option<Session>, SessionError> {
+        let query = "
+            UPDATE sessions SET last_seen_at = NOW()
+            WHERE refresh_family_id = $1
+            RETURNING *;
+        ";
+        sqlx::query_as::<_, Session>(query)
+            .bind(refresh_family_id)
+            .fetch_optional(&*self.pg_pool)
+            .await
+            .map_err(SessionError::from)
+    }
+
+    async fn revoke_session(
+        &self,
+        user_id: i32,
+        session_id: i32,
+    ) -> Result<Option<Session>, SessionError> {
+        let query = "
+            UPDATE sessions SET revoked_at = NOW()
+            WHERE session_id = $1 AND user_id = $2 AND revoked_at IS NULL
+            RETURNING *;
+        ";
+        sqlx::query_as::<_, Session>(query)
+            .bind(session_id)
+            .bind(user_id)
+            .fetch_optional(&*self.pg_pool)
+            .await
+            .map_err(SessionError::from)
+    }
+
+    async fn revoke_session_by_family(&self, refresh_family_id: &str) -> Result<(), SessionError> {
+        let query = "
+            UPDATE sessions SET revoked_at = NOW()
+            WHERE refresh_family_id = $1 AND revoked_at IS NULL;
+        ";
+        sqlx::query(query)
+            .bind(refresh_family_id)
+            .execute(&*self.pg_pool)
+            .await
+            .map(|_| ())
+            .map_err(SessionError::from)
+    }
+
+    async fn revoke_user_sessions(&self, user_id: i32) -> Result<(), SessionError> {
+        let query = "
+            UPDATE sessions SET revoked_at = NOW()
+            WHERE user_id = $1 AND revoked_at IS NULL;
+        ";
+        sqlx::query(query)
+            .bind(user_id)
+            .execute(&*self.pg_pool)
+            .await
+            .map(|_| ())
+            .map_err(SessionError::from)
+    }
+}
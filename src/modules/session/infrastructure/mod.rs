@@ -0,0 +1 @@
+mod db_adapter;
@@ -0,0 +1,9 @@
+mod domain;
+pub use domain::*;
+
+mod app_service;
+pub use app_service::*;
+
+pub mod infrastructure;
+
+pub mod api;
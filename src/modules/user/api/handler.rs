@@ -2,14 +2,27 @@ use std::sync::Arc;
 
 use actix_web::{web, HttpResponse, Responder, ResponseError};
 
-use crate::modules::user::AppService;
+use crate::modules::{auth::api::FirstPartyUser, user::AppService};
 
+pub async fn get_current_user(
+    app_service: web::Data<Arc<AppService>>,
+    user: FirstPartyUser,
+) -> impl Responder {
+    match app_service.get_user(user.user_id()).await {
+        Ok(user) => HttpResponse::Ok().json(user),
+        Err(e) => e.error_response(),
+    }
+}
+
+/// Deprecated: the token ends up in URLs and logs. Use `GET /me` with a bearer token instead.
 pub async fn get_user(
     app_service: web::Data<Arc<AppService>>,
     token: web::Path<String>,
 ) -> impl Responder {
     match app_service.get_user_by_id(&token.into_inner()).await {
-        Ok(user) => HttpResponse::Ok().json(user),
+        Ok(user) => HttpResponse::Ok()
+            .insert_header(("Deprecation", "true"))
+            .json(user),
         Err(e) => e.error_response(),
     }
 }
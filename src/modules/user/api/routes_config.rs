@@ -1,7 +1,12 @@
 use actix_web::web;
 
-use super::handler::get_user;
+use super::handler::{get_current_user, get_user};
 
 pub fn config(cfg: &mut web::ServiceConfig) {
-    cfg.service(web::scope("/me").route("/{token}", web::get().to(get_user)));
+    cfg.route("/me", web::get().to(get_current_user));
+}
+
+/// The deprecated `GET /me/{token}`, only registered when `ALLOW_PATH_TOKEN` is set.
+pub fn path_token_config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/me/{token}", web::get().to(get_user));
 }
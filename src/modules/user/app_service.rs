@@ -1,6 +1,9 @@
 use std::sync::Arc;
 
-use crate::{error::AppError, modules::auth::JwtManager};
+use crate::{
+    error::AppError,
+    modules::auth::{AuthError, JwtManager},
+};
 
 use super::{ports::Repository, User};
 
@@ -16,12 +19,30 @@ impl AppService {
 }
 
 impl AppService {
-    pub async fn upsert_user(&self, user: &User) -> Result<User, AppError> {
-        Ok(self.repo.upsert_user(user).await?)
+    pub async fn create_user(&self, user: &User) -> Result<User, AppError> {
+        Ok(self.repo.create_user(user).await?)
     }
 
+    pub async fn get_user(&self, user_id: i32) -> Result<User, AppError> {
+        Ok(self.repo.get_user_by_id(user_id).await?)
+    }
+
+    pub async fn find_user_by_email(&self, email: &str) -> Result<Option<User>, AppError> {
+        Ok(self.repo.find_user_by_email(email).await?)
+    }
+
+    pub async fn mark_email_verified(&self, user_id: i32) -> Result<(), AppError> {
+        Ok(self.repo.mark_email_verified(user_id).await?)
+    }
+
+    /// The user of a first-party token; like `GET /me`, tokens issued to a client are refused.
     pub async fn get_user_by_id(&self, token: &str) -> Result<User, AppError> {
-        let claim = self.jwt_manager.verify_jwt(token)?;
+        let claim = self.jwt_manager.verify_jwt(token).await?;
+        if let Some(client_id) = &claim.client_id {
+            return Err(
+                AuthError::Forbidden(format!("token was issued to client {}", client_id)).into(),
+            );
+        }
         Ok(self.repo.get_user_by_id(claim.sub).await?)
     }
 }
@@ -6,6 +6,9 @@ use sqlx::FromRow;
 pub struct User {
     pub user_id: i32,
     pub email: Option<String>,
+    /// Whether the user proved they own `email`, through a provider that verified it or an
+    /// emailed link. Only verified emails join other sign-in methods to the account.
+    pub email_verified: bool,
     pub name: Option<String>,
     pub avatar_url: Option<String>,
     pub created_at: DateTime<Utc>,
@@ -15,6 +18,7 @@ pub struct User {
 pub struct UserBuilder {
     user_id: Option<i32>,
     email: Option<String>,
+    email_verified: bool,
     name: Option<String>,
     avatar_url: Option<String>,
     created_at: Option<DateTime<Utc>>,
@@ -25,6 +29,7 @@ impl UserBuilder {
         Self {
             user_id: None,
             email: None,
+            email_verified: false,
             name: None,
             avatar_url: None,
             created_at: None,
@@ -39,6 +44,10 @@ impl UserBuilder {
         self.email = Some(email.into());
         self
     }
+    pub fn email_verified(mut self, email_verified: bool) -> Self {
+        self.email_verified = email_verified;
+        self
+    }
     pub fn name<S: Into<String>>(mut self, name: S) -> Self {
         self.name = Some(name.into());
         self
@@ -59,6 +68,7 @@ impl UserBuilder {
         User {
             user_id: self.user_id.unwrap_or(0),
             email: self.email,
+            email_verified: self.email_verified,
             name: self.name,
             avatar_url: self.avatar_url,
             created_at: self.created_at.unwrap_or_else(Utc::now),
@@ -4,6 +4,8 @@ use super::{User, UserError};
 
 #[async_trait]
 pub trait Repository: Send + Sync {
-    async fn upsert_user(&self, user: &User) -> Result<User, UserError>;
+    async fn create_user(&self, user: &User) -> Result<User, UserError>;
     async fn get_user_by_id(&self, user_id: i32) -> Result<User, UserError>;
+    async fn find_user_by_email(&self, email: &str) -> Result<Option<User>, UserError>;
+    async fn mark_email_verified(&self, user_id: i32) -> Result<(), UserError>;
 }
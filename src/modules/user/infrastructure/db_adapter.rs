@@ -7,16 +7,15 @@ use crate::{
 
 #[async_trait]
 impl Repository for PostgresRepository {
-    async fn upsert_user(&self, user: &User) -> Result<User, UserError> {
+    async fn create_user(&self, user: &User) -> Result<User, UserError> {
         let query = "
-            INSERT INTO users (email, name, avatar_url, created_at, updated_at)
-            VALUES ($1, $2, $3, NOW(), NOW())
-            ON CONFLICT (email) DO UPDATE
-            SET name = EXCLUDED.name, avatar_url = EXCLUDED.avatar_url, updated_at = NOW()
+            INSERT INTO users (email, email_verified, name, avatar_url, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, NOW(), NOW())
             RETURNING *;
         ";
         sqlx::query_as::<_, User>(query)
             .bind(&user.email)
+            .bind(user.email_verified)
             .bind(&user.name)
             .bind(&user.avatar_url)
             .fetch_one(&*self.pg_pool)
@@ -34,4 +33,27 @@ impl Repository for PostgresRepository {
             .await
             .map_err(UserError::from)
     }
+
+    async fn find_user_by_email(&self, email: &str) -> Result<Option<User>, UserError> {
+        let query = "
+            SELECT * FROM users WHERE email = $1;
+        ";
+        sqlx::query_as::<_, User>(query)
+            .bind(email)
+            .fetch_optional(&*self.pg_pool)
+            .await
+            .map_err(UserError::from)
+    }
+
+    async fn mark_email_verified(&self, user_id: i32) -> Result<(), UserError> {
+        let query = "
+            UPDATE users SET email_verified = TRUE, updated_at = NOW() WHERE user_id = $1;
+        ";
+        sqlx::query(query)
+            .bind(user_id)
+            .execute(&*self.pg_pool)
+            .await
+            .map(|_| ())
+            .map_err(UserError::from)
+    }
 }
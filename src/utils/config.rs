@@ -3,9 +3,110 @@ use std::env;
 pub struct Config {
     pub google_client_id: String,
     pub google_client_secret: String,
+    pub google_jwks_uri: String,
+    pub github_client_id: Option<String>,
+    pub github_client_secret: Option<String>,
+    pub oidc_providers: Vec<OidcProviderConfig>,
     pub domain: String,
     pub database_url: String,
-    pub jwt_secret: String,
+    pub jwt_secret: Option<String>,
+    pub jwt_algorithm: String,
+    pub jwt_private_key_path: Option<String>,
+    pub jwt_key_id: Option<String>,
+    pub jwt_issuer: String,
+    pub jwt_audience: Option<String>,
+    pub jwt_ttl_seconds: i64,
+    pub jwt_leeway_seconds: u64,
+    pub jwt_key_retirement_seconds: i64,
+    pub refresh_token_ttl_seconds: i64,
+    pub revocation_store: String,
+    pub auth_cookie_name: String,
+    pub refresh_cookie_name: String,
+    pub csrf_cookie_name: String,
+    pub cookie_domain: Option<String>,
+    pub cookie_same_site: String,
+    pub cookie_secure: bool,
+    pub clients: Vec<ClientConfig>,
+    pub return_to_origins: Vec<String>,
+    pub default_provider: String,
+    pub revoke_upstream_tokens: bool,
+    /// `verified` (the default) or `never`.
+    pub auto_link_by_email: String,
+    pub password_min_length: usize,
+    pub password_max_length: usize,
+    /// File of passwords to refuse, one per line, e.g. a list of the most common breached
+    /// passwords.
+    pub password_blocklist_file: Option<String>,
+    /// Argon2id cost of password hashes; the defaults follow the OWASP recommendation.
+    pub argon2_memory_kib: u32,
+    pub argon2_iterations: u32,
+    pub argon2_parallelism: u32,
+    pub email_verification_url: String,
+    pub password_reset_url: String,
+    /// `log` (the default) or `smtp`.
+    pub mailer: String,
+    /// Where the `log` mailer appends emails; without it they only go to the log.
+    pub mail_file: Option<String>,
+    pub mail_from: String,
+    pub smtp_host: Option<String>,
+    pub smtp_port: u16,
+    /// `starttls` (the default), `tls` or `none`.
+    pub smtp_security: String,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+    pub allow_path_token: bool,
+    pub admin_token: Option<String>,
+}
+
+/// A generic OpenID Connect provider, read from `OIDC_<NAME>_*` variables for every name
+/// listed in `OIDC_PROVIDERS`.
+pub struct OidcProviderConfig {
+    pub provider_id: i32,
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub scopes: Vec<String>,
+    pub claims: ClaimMappings,
+}
+
+/// An application that starts logins, read from `CLIENT_<NAME>_*` variables for every name
+/// listed in `CLIENTS`.
+pub struct ClientConfig {
+    pub client_id: String,
+    /// `json` (the default), `cookie`, `fragment` or `code`.
+    pub token_delivery: String,
+    pub redirect_urls: Vec<String>,
+    /// Set for confidential clients; public ones redeem codes with PKCE instead.
+    pub secret: Option<String>,
+}
+
+/// Names of the provider claims that hold each profile field.
+pub struct ClaimMappings {
+    pub subject: String,
+    pub email: String,
+    pub email_verified: String,
+    pub name: String,
+    pub given_name: String,
+    pub family_name: String,
+    pub picture: String,
+    pub locale: String,
+}
+
+impl Default for ClaimMappings {
+    /// The standard OpenID Connect claim names.
+    fn default() -> Self {
+        ClaimMappings {
+            subject: "sub".to_string(),
+            email: "email".to_string(),
+            email_verified: "email_verified".to_string(),
+            name: "name".to_string(),
+            given_name: "given_name".to_string(),
+            family_name: "family_name".to_string(),
+            picture: "picture".to_string(),
+            locale: "locale".to_string(),
+        }
+    }
 }
 
 impl Config {
@@ -14,9 +115,224 @@ impl Config {
             google_client_id: env::var("GOOGLE_CLIENT_ID").expect("GOOGLE_CLIENT_ID not set"),
             google_client_secret: env::var("GOOGLE_CLIENT_SECRET")
                 .expect("GOOGLE_CLIENT_SECRET not set"),
+            google_jwks_uri: env::var("GOOGLE_JWKS_URI")
+                .unwrap_or_else(|_| "https://www.googleapis.com/oauth2/v3/certs".to_string()),
+            github_client_id: optional_var("GITHUB_CLIENT_ID"),
+            github_client_secret: optional_var("GITHUB_CLIENT_SECRET"),
+            oidc_providers: env::var("OIDC_PROVIDERS")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(OidcProviderConfig::from_env)
+                .collect(),
+            jwt_issuer: env::var("JWT_ISSUER")
+                .or_else(|_| env::var("DOMAIN"))
+                .expect("DOMAIN not set"),
             domain: env::var("DOMAIN").expect("DOMAIN not set"),
             database_url: env::var("DATABASE_URL").expect("DATABASE_URL not set"),
-            jwt_secret: env::var("JWT_SECRET").expect("JWT_SECRET not set"),
+            jwt_secret: optional_var("JWT_SECRET"),
+            jwt_algorithm: env::var("JWT_ALGORITHM").unwrap_or_else(|_| "HS256".to_string()),
+            jwt_private_key_path: optional_var("JWT_PRIVATE_KEY_PATH"),
+            jwt_key_id: optional_var("JWT_KEY_ID"),
+            jwt_audience: optional_var("JWT_AUDIENCE"),
+            jwt_ttl_seconds: env::var("JWT_TTL_SECONDS")
+                .map(|ttl| ttl.parse().expect("JWT_TTL_SECONDS must be an integer"))
+                .unwrap_or(3600),
+            jwt_leeway_seconds: env::var("JWT_LEEWAY_SECONDS")
+                .map(|leeway| {
+                    leeway
+                        .parse()
+                        .expect("JWT_LEEWAY_SECONDS must be an integer")
+                })
+                .unwrap_or(60),
+            jwt_key_retirement_seconds: env::var("JWT_KEY_RETIREMENT_SECONDS")
+                .map(|seconds| {
+                    seconds
+                        .parse()
+                        .expect("JWT_KEY_RETIREMENT_SECONDS must be an integer")
+                })
+                .unwrap_or(86400),
+            refresh_token_ttl_seconds: env::var("REFRESH_TOKEN_TTL_SECONDS")
+                .map(|ttl| {
+                    ttl.parse()
+                        .expect("REFRESH_TOKEN_TTL_SECONDS must be an integer")
+                })
+                .unwrap_or(30 * 86400),
+            revocation_store: env::var("REVOCATION_STORE")
+                .unwrap_or_else(|_| "postgres".to_string()),
+            auth_cookie_name: env::var("AUTH_COOKIE_NAME")
+                .unwrap_or_else(|_| "kuri_session".to_string()),
+            refresh_cookie_name: env::var("REFRESH_COOKIE_NAME")
+                .unwrap_or_else(|_| "kuri_refresh".to_string()),
+            csrf_cookie_name: env::var("CSRF_COOKIE_NAME")
+                .unwrap_or_else(|_| "kuri_csrf".to_string()),
+            cookie_domain: optional_var("COOKIE_DOMAIN"),
+            cookie_same_site: env::var("COOKIE_SAME_SITE").unwrap_or_else(|_| "Lax".to_string()),
+            cookie_secure: env::var("COOKIE_SECURE")
+                .map(|secure| secure != "false" && secure != "0")
+                .unwrap_or(true),
+            clients: env::var("CLIENTS")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(ClientConfig::from_env)
+                .collect(),
+            return_to_origins: env::var("RETURN_TO_ORIGINS")
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|origin| !origin.is_empty())
+                .map(String::from)
+                .collect(),
+            default_provider: env::var("DEFAULT_PROVIDER").unwrap_or_else(|_| "google".to_string()),
+            revoke_upstream_tokens: env::var("REVOKE_UPSTREAM_TOKENS")
+                .map(|revoke| revoke == "true" || revoke == "1")
+                .unwrap_or(false),
+            auto_link_by_email: env::var("AUTO_LINK_BY_EMAIL")
+                .unwrap_or_else(|_| "verified".to_string()),
+            password_min_length: env::var("PASSWORD_MIN_LENGTH")
+                .map(|length| {
+                    length
+                        .parse()
+                        .expect("PASSWORD_MIN_LENGTH must be an integer")
+                })
+                .unwrap_or(12),
+            password_max_length: env::var("PASSWORD_MAX_LENGTH")
+                .map(|length| {
+                    length
+                        .parse()
+                        .expect("PASSWORD_MAX_LENGTH must be an integer")
+                })
+                .unwrap_or(128),
+            password_blocklist_file: optional_var("PASSWORD_BLOCKLIST_FILE"),
+            argon2_memory_kib: env::var("ARGON2_MEMORY_KIB")
+                .map(|memory| {
+                    memory
+                        .parse()
+                        .expect("ARGON2_MEMORY_KIB must be an integer")
+                })
+                .unwrap_or(19456),
+            argon2_iterations: env::var("ARGON2_ITERATIONS")
+                .map(|iterations| {
+                    iterations
+                        .parse()
+                        .expect("ARGON2_ITERATIONS must be an integer")
+                })
+                .unwrap_or(2),
+            argon2_parallelism: env::var("ARGON2_PARALLELISM")
+                .map(|lanes| {
+                    lanes
+                        .parse()
+                        .expect("ARGON2_PARALLELISM must be an integer")
+                })
+                .unwrap_or(1),
+            email_verification_url: env::var("EMAIL_VERIFICATION_URL").unwrap_or_else(|_| {
+                format!(
+                    "{}/verify-email",
+                    env::var("DOMAIN").expect("DOMAIN not set")
+                )
+            }),
+            password_reset_url: env::var("PASSWORD_RESET_URL").unwrap_or_else(|_| {
+                format!(
+                    "{}/reset-password",
+                    env::var("DOMAIN").expect("DOMAIN not set")
+                )
+            }),
+            mailer: env::var("MAILER").unwrap_or_else(|_| "log".to_string()),
+            mail_file: optional_var("MAIL_FILE"),
+            mail_from: env::var("MAIL_FROM")
+                .unwrap_or_else(|_| "KuriLogin <no-reply@localhost>".to_string()),
+            smtp_host: optional_var("SMTP_HOST"),
+            smtp_port: env::var("SMTP_PORT")
+                .map(|port| port.parse().expect("SMTP_PORT must be a port number"))
+                .unwrap_or(587),
+            smtp_security: env::var("SMTP_SECURITY").unwrap_or_else(|_| "starttls".to_string()),
+            smtp_username: optional_var("SMTP_USERNAME"),
+            smtp_password: optional_var("SMTP_PASSWORD"),
+            allow_path_token: env::var("ALLOW_PATH_TOKEN")
+                .map(|allow| allow == "true" || allow == "1")
+                .unwrap_or(false),
+            admin_token: optional_var("ADMIN_TOKEN"),
+        }
+    }
+}
+
+/// The ids and names of the Google and GitHub providers, reserved even when GitHub is not
+/// configured since its id is already in the database.
+const BUILTIN_PROVIDERS: [(i32, &str); 2] = [(1, "google"), (2, "github")];
+
+/// An optional setting; set but empty, as in `NAME=` in an `.env` file, counts as unset.
+fn optional_var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+impl ClientConfig {
+    fn from_env(name: &str) -> ClientConfig {
+        let prefix = format!("CLIENT_{}", name.to_uppercase().replace('-', "_"));
+        ClientConfig {
+            client_id: name.to_lowercase(),
+            token_delivery: env::var(format!("{}_TOKEN_DELIVERY", prefix))
+                .unwrap_or_else(|_| "json".to_string()),
+            redirect_urls: env::var(format!("{}_REDIRECT_URLS", prefix))
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|url| !url.is_empty())
+                .map(String::from)
+                .collect(),
+            secret: optional_var(&format!("{}_SECRET", prefix)),
+        }
+    }
+}
+
+impl OidcProviderConfig {
+    fn from_env(name: &str) -> OidcProviderConfig {
+        let prefix = format!("OIDC_{}", name.to_uppercase().replace('-', "_"));
+        let var = |key: &str| {
+            let key = format!("{}_{}", prefix, key);
+            env::var(&key).unwrap_or_else(|_| panic!("{} not set", key))
+        };
+        let var_or = |key: &str, default: &str| {
+            env::var(format!("{}_{}", prefix, key)).unwrap_or_else(|_| default.to_string())
+        };
+
+        let defaults = ClaimMappings::default();
+        let provider_id = var("PROVIDER_ID")
+            .parse()
+            .unwrap_or_else(|_| panic!("{}_PROVIDER_ID must be an integer", prefix));
+        let name = name.to_lowercase();
+        if let Some((id, builtin)) = BUILTIN_PROVIDERS
+            .iter()
+            .find(|(id, builtin)| *id == provider_id || *builtin == name)
+        {
+            panic!(
+                "OIDC provider {} must not reuse the id or name of {} (id {})",
+                name, builtin, id
+            );
+        }
+
+        OidcProviderConfig {
+            provider_id,
+            name,
+            issuer: var("ISSUER"),
+            client_id: var("CLIENT_ID"),
+            client_secret: var("CLIENT_SECRET"),
+            scopes: var_or("SCOPES", "openid email profile")
+                .split_whitespace()
+                .map(String::from)
+                .collect(),
+            claims: ClaimMappings {
+                subject: var_or("CLAIM_SUBJECT", &defaults.subject),
+                email: var_or("CLAIM_EMAIL", &defaults.email),
+                email_verified: var_or("CLAIM_EMAIL_VERIFIED", &defaults.email_verified),
+                name: var_or("CLAIM_NAME", &defaults.name),
+                given_name: var_or("CLAIM_GIVEN_NAME", &defaults.given_name),
+                family_name: var_or("CLAIM_FAMILY_NAME", &defaults.family_name),
+                picture: var_or("CLAIM_PICTURE", &defaults.picture),
+                locale: var_or("CLAIM_LOCALE", &defaults.locale),
+            },
         }
     }
 }
@@ -1,2 +1,4 @@
 pub mod config;
+pub mod password;
 pub mod postgres;
+pub mod token;
@@ -0,0 +1,129 @@
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher as _, PasswordVerifier, SaltString},
+    Algorithm, Argon2, Params, Version,
+};
+use rand::rngs::OsRng;
+
+use super::token::generate_token;
+
+/// Hashes and verifies passwords with Argon2id.
+#[derive(Clone)]
+pub struct PasswordHasher {
+    argon2: Argon2<'static>,
+    /// Verified against when an account has no password, so that unknown emails take as long
+    /// to reject as wrong passwords.
+    dummy_hash: String,
+}
+
+impl PasswordHasher {
+    pub fn new(params: Params) -> Self {
+        let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
+        let salt = SaltString::generate(&mut OsRng);
+        let dummy_hash = argon2
+            .hash_password(generate_token(32).as_bytes(), &salt)
+            .expect("Failed to hash with the configured Argon2 parameters")
+            .to_string();
+        Self { argon2, dummy_hash }
+    }
+
+    /// The PHC string of `password`, carrying its salt and parameters.
+    pub fn hash(&self, password: &str) -> Result<String, argon2::password_hash::Error> {
+        let salt = SaltString::generate(&mut OsRng);
+        Ok(self
+            .argon2
+            .hash_password(password.as_bytes(), &salt)?
+            .to_string())
+    }
+
+    /// Whether `password` matches `hash`. Without a hash the password is checked against a
+    /// throwaway one and rejected, costing the same time.
+    pub fn verify(&self, password: &str, hash: Option<&str>) -> bool {
+        let matches = |hash: &str| {
+            PasswordHash::new(hash)
+                .map(|parsed| {
+                    self.argon2
+                        .verify_password(password.as_bytes(), &parsed)
+                        .is_ok()
+                })
+                .unwrap_or(false)
+        };
+        match hash {
+            Some(hash) => matches(hash),
+            None => {
+                matches(&self.dummy_hash);
+                false
+            }
+        }
+    }
+
+    /// Whether `hash` was made with other parameters than the configured ones, so it should be
+    /// replaced at the next successful login.
+    pub fn needs_rehash(&self, hash: &str) -> bool {
+        let Ok(parsed) = PasswordHash::new(hash) else {
+            return true;
+        };
+        let current = self.argon2.params();
+        parsed.algorithm != Algorithm::Argon2id.ident()
+            || Params::try_from(&parsed).map_or(true, |params| {
+                params.m_cost() != current.m_cost()
+                    || params.t_cost() != current.t_cost()
+                    || params.p_cost() != current.p_cost()
+            })
+    }
+}
+
+impl std::fmt::Debug for PasswordHasher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PasswordHasher")
+            .field("params", self.argon2.params())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cheap parameters, so the tests do not spend seconds hashing.
+    fn hasher(iterations: u32) -> PasswordHasher {
+        PasswordHasher::new(Params::new(1024, iterations, 1, None).unwrap())
+    }
+
+    #[test]
+    fn verifies_the_hashed_password_only() {
+        let hasher = hasher(1);
+        let hash = hasher.hash("plum kettle orbit").unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(hasher.verify("plum kettle orbit", Some(&hash)));
+        assert!(!hasher.verify("plum kettle orbiT", Some(&hash)));
+        assert!(!hasher.verify("plum kettle orbit", Some("not a hash")));
+    }
+
+    #[test]
+    fn hashes_are_salted() {
+        let hasher = hasher(1);
+        assert_ne!(
+            hasher.hash("plum kettle orbit").unwrap(),
+            hasher.hash("plum kettle orbit").unwrap()
+        );
+    }
+
+    #[test]
+    fn accounts_without_a_hash_never_verify() {
+        let hasher = hasher(1);
+        assert!(!hasher.verify("", None));
+        assert!(!hasher.verify("plum kettle orbit", None));
+        // The dummy hash is real work with the configured parameters.
+        assert!(!hasher.needs_rehash(&hasher.dummy_hash));
+    }
+
+    #[test]
+    fn hashes_with_other_parameters_need_a_rehash() {
+        let old = hasher(1).hash("plum kettle orbit").unwrap();
+        let hasher = hasher(2);
+        assert!(hasher.needs_rehash(&old));
+        assert!(hasher.verify("plum kettle orbit", Some(&old)));
+        assert!(!hasher.needs_rehash(&hasher.hash("plum kettle orbit").unwrap()));
+        assert!(hasher.needs_rehash("not a hash"));
+    }
+}
@@ -14,7 +14,7 @@ impl PostgresRepository {
         let config = Config::from_env();
 
         // Append SSL parameters to the connection URL
-        let conn_url = format!("{}", config.database_url);
+        let conn_url = config.database_url.to_string();
 
         let pool = PgPool::connect(&conn_url).await.unwrap();
         sqlx::migrate!("./migrations/")